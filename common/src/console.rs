@@ -0,0 +1,708 @@
+//! Console module parses and carries out the commands typed into the jig's USB serial port.
+//!
+//! Received bytes are collected by a [`LineBuffer`] until Enter ends the line. [`parse`] turns
+//! the line into a [`Command`] and [`dispatch`] carries it out on a [`Jig`], which the firmware
+//! implements with the same operations the buttons trigger. Every command ends with a line of
+//! `ok` or `error: <reason>`, so a script can tell when it has finished and whether it worked.
+//!
+//! Commands:
+//! - `help`: list the commands
+//! - `version`: the firmware name and version
+//! - `scan`: the address of the sensor in each socket
+//! - `program`: program every socket, as the start button does
+//! - `verify`: check each sensor answers at its planned address
+//! - `reset-default`: put each sensor back on its factory default address
+//! - `monitor [on|off]`: print each result as it is recorded
+//! - `get [<setting>]`: one setting, or all of them
+//! - `set <setting> <value>`: change and save a setting
+//! - `log dump`: the result log as CSV, oldest first
+//! - `diag`: LED frame, retry, reinit and failure counts
+//! - `stats`: runs, yield and failures by error and socket, since power on and over the jig's
+//!   life
+//! - `next-address [<address>]`: the address continuous mode hands out next, or start handing
+//!   them out from `address` again
+
+use core::fmt::Write;
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    counters::Counters,
+    record::{CSV_HEADER, ErrorCode, ResultRecord},
+};
+
+/// The longest line that can be typed
+pub const MAX_LINE: usize = 80;
+/// The longest line of a reply
+pub const MAX_REPLY: usize = 96;
+
+/// A line typed into the console
+pub type Line = String<MAX_LINE>;
+/// A line of a reply
+pub type ReplyLine = String<MAX_REPLY>;
+
+const HELP: [&str; 13] = [
+    "help                   this list",
+    "version                firmware name and version",
+    "scan                   sensor address in each socket",
+    "program                program every socket",
+    "verify                 check sensors answer at their new address",
+    "reset-default          put sensors back on the default address",
+    "monitor [on|off]       print results as they are recorded",
+    "get [<setting>]        show one setting or all of them",
+    "set <setting> <value>  change and save a setting",
+    "log dump               result log as CSV, oldest first",
+    "diag                   LED failure counts",
+    "stats                  runs, yield and failures since power on and ever",
+    "next-address [<addr>]  next continuous address, or start again from addr",
+];
+
+/// Why a typed line isn't a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    /// The line was longer than [`MAX_LINE`]
+    LineTooLong,
+    UnknownCommand,
+    /// The command needs more arguments
+    MissingArgument,
+    /// The command was given more arguments than it takes
+    UnexpectedArgument,
+    UnknownSetting,
+    /// The value isn't a number or name the setting takes, or is out of range
+    BadValue,
+}
+
+impl ParseError {
+    /// The reason for the console, after `error: `
+    pub const fn message(&self) -> &'static str {
+        match self {
+            ParseError::LineTooLong => "line too long",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::UnexpectedArgument => "too many arguments",
+            ParseError::UnknownSetting => "unknown setting, try get",
+            ParseError::BadValue => "bad value",
+        }
+    }
+}
+
+/// Collects received bytes into lines
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    line: Line,
+    /// Some of the line didn't fit
+    too_long: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            too_long: false,
+        }
+    }
+
+    /// Add a received byte. Backspace and delete remove the last character, and other control
+    /// characters and non-ASCII bytes are ignored.
+    ///
+    /// # Returns
+    /// The line once a carriage return or line feed ends it, otherwise None. Empty lines are
+    /// skipped, so a CR LF pair ends only one line
+    pub fn push(&mut self, byte: u8) -> Option<Result<Line, ParseError>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                if core::mem::take(&mut self.too_long) {
+                    Some(Err(ParseError::LineTooLong))
+                } else if line.is_empty() {
+                    None
+                } else {
+                    Some(Ok(line))
+                }
+            }
+            0x08 | 0x7f => {
+                self.line.pop();
+                None
+            }
+            b' '..=b'~' => {
+                if self.line.push(byte as char).is_err() {
+                    self.too_long = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Names of the [`SettingId::Language`] choices, in the order the firmware numbers them
+const LANGUAGES: &[&str] = &["english", "german", "french"];
+/// Names of the [`SettingId::Mode`] choices, in the order the firmware numbers them
+const MODES: &[&str] = &["fixture", "continuous"];
+/// Names of the [`SettingId::AtLimit`] choices, in the order the firmware numbers them
+const AT_LIMIT: &[&str] = &["stop", "wrap"];
+
+/// The values a setting takes, all held as a u32
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingKind {
+    /// A whole number from `min` to `max`, typed in decimal or as hex starting `0x`
+    Number { min: u32, max: u32 },
+    /// A 7 bit I2C address, shown in hex
+    Address,
+    /// A time in milliseconds, up to a minute, typed with or without `ms` after it
+    Millis,
+    /// One of the names, held as its index
+    Choice(&'static [&'static str]),
+}
+
+impl SettingKind {
+    /// The lowest and highest values the setting takes
+    pub const fn range(&self) -> (u32, u32) {
+        match self {
+            SettingKind::Number { min, max } => (*min, *max),
+            SettingKind::Address => (0, 0x7f),
+            SettingKind::Millis => (0, 60_000),
+            SettingKind::Choice(names) => (0, names.len() as u32 - 1),
+        }
+    }
+
+    /// True if the setting takes `value`
+    pub fn accepts(&self, value: u32) -> bool {
+        let (min, max) = self.range();
+        (min..=max).contains(&value)
+    }
+
+    /// Read a typed value
+    pub fn parse(&self, text: &str) -> Result<u32, ParseError> {
+        let value = match self {
+            SettingKind::Number { .. } | SettingKind::Address => number(text),
+            SettingKind::Millis => number(text.strip_suffix("ms").unwrap_or(text)),
+            SettingKind::Choice(names) => names
+                .iter()
+                .position(|name| *name == text)
+                .map(|i| i as u32),
+        };
+        value
+            .filter(|value| self.accepts(*value))
+            .ok_or(ParseError::BadValue)
+    }
+
+    /// Write a value the way it is typed
+    pub fn write<W: Write>(&self, value: u32, out: &mut W) -> core::fmt::Result {
+        match self {
+            SettingKind::Number { .. } => write!(out, "{}", value),
+            SettingKind::Address => write!(out, "0x{:02x}", value),
+            SettingKind::Millis => write!(out, "{}ms", value),
+            SettingKind::Choice(names) => match names.get(value as usize) {
+                Some(name) => out.write_str(name),
+                None => write!(out, "unknown ({})", value),
+            },
+        }
+    }
+}
+
+/// Read a number in decimal, or in hex if it starts `0x`
+fn number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// A setting that can be read and changed from the console
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SettingId {
+    LedBrightness,
+    TorchBrightness,
+    AddressFirst,
+    AddressStep,
+    RetryAttempts,
+    RetryBackoff,
+    Language,
+    ScanTimeout,
+    ProgramTimeout,
+    VerifyTimeout,
+    Mode,
+    ContinuousFirst,
+    ContinuousLimit,
+    AtLimit,
+}
+
+impl SettingId {
+    /// Every setting, in the order `get` lists them
+    pub const ALL: [SettingId; 14] = [
+        SettingId::LedBrightness,
+        SettingId::TorchBrightness,
+        SettingId::AddressFirst,
+        SettingId::AddressStep,
+        SettingId::RetryAttempts,
+        SettingId::RetryBackoff,
+        SettingId::Language,
+        SettingId::ScanTimeout,
+        SettingId::ProgramTimeout,
+        SettingId::VerifyTimeout,
+        SettingId::Mode,
+        SettingId::ContinuousFirst,
+        SettingId::ContinuousLimit,
+        SettingId::AtLimit,
+    ];
+
+    /// The name typed into the console
+    pub const fn name(&self) -> &'static str {
+        match self {
+            SettingId::LedBrightness => "led_brightness",
+            SettingId::TorchBrightness => "torch_brightness",
+            SettingId::AddressFirst => "address_first",
+            SettingId::AddressStep => "address_step",
+            SettingId::RetryAttempts => "retry_attempts",
+            SettingId::RetryBackoff => "retry_backoff",
+            SettingId::Language => "language",
+            SettingId::ScanTimeout => "scan_timeout",
+            SettingId::ProgramTimeout => "program_timeout",
+            SettingId::VerifyTimeout => "verify_timeout",
+            SettingId::Mode => "mode",
+            SettingId::ContinuousFirst => "continuous_first",
+            SettingId::ContinuousLimit => "continuous_limit",
+            SettingId::AtLimit => "at_limit",
+        }
+    }
+
+    pub const fn kind(&self) -> SettingKind {
+        match self {
+            SettingId::LedBrightness | SettingId::TorchBrightness => {
+                SettingKind::Number { min: 0, max: 255 }
+            }
+            SettingId::AddressStep => SettingKind::Number { min: 1, max: 0x70 },
+            SettingId::RetryAttempts => SettingKind::Number { min: 1, max: 10 },
+            SettingId::AddressFirst | SettingId::ContinuousFirst | SettingId::ContinuousLimit => {
+                SettingKind::Address
+            }
+            SettingId::RetryBackoff
+            | SettingId::ScanTimeout
+            | SettingId::ProgramTimeout
+            | SettingId::VerifyTimeout => SettingKind::Millis,
+            SettingId::Language => SettingKind::Choice(LANGUAGES),
+            SettingId::Mode => SettingKind::Choice(MODES),
+            SettingId::AtLimit => SettingKind::Choice(AT_LIMIT),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|id| id.name() == name)
+    }
+}
+
+/// A command typed into the console
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Help,
+    Version,
+    Scan,
+    Program,
+    Verify,
+    ResetDefault,
+    /// Turn printing results as they are recorded on or off
+    Monitor(bool),
+    /// Show one setting, or all of them
+    Get(Option<SettingId>),
+    /// Change and save a setting, to a value already checked against its [`SettingKind`]
+    Set(SettingId, u32),
+    LogDump,
+    Diag,
+    Stats,
+    /// Show the next address continuous mode hands out, or start again from the one given
+    NextAddress(Option<u8>),
+}
+
+/// Turn a line typed into the console into a command. Words are separated by spaces
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let setting = |name: Option<&str>| {
+        SettingId::from_name(name.ok_or(ParseError::MissingArgument)?)
+            .ok_or(ParseError::UnknownSetting)
+    };
+    let command = match words.next().ok_or(ParseError::UnknownCommand)? {
+        "help" => Command::Help,
+        "version" => Command::Version,
+        "scan" => Command::Scan,
+        "program" => Command::Program,
+        "verify" => Command::Verify,
+        "reset-default" => Command::ResetDefault,
+        "monitor" => match words.next() {
+            None | Some("on") => Command::Monitor(true),
+            Some("off") => Command::Monitor(false),
+            Some(_) => return Err(ParseError::BadValue),
+        },
+        "get" => match words.next() {
+            None => Command::Get(None),
+            name => Command::Get(Some(setting(name)?)),
+        },
+        "set" => {
+            let id = setting(words.next())?;
+            let value = id
+                .kind()
+                .parse(words.next().ok_or(ParseError::MissingArgument)?)?;
+            Command::Set(id, value)
+        }
+        "log" => match words.next() {
+            Some("dump") => Command::LogDump,
+            Some(_) => return Err(ParseError::UnknownCommand),
+            None => return Err(ParseError::MissingArgument),
+        },
+        "diag" => Command::Diag,
+        "stats" => Command::Stats,
+        "next-address" => match words.next() {
+            None => Command::NextAddress(None),
+            Some(text) => Command::NextAddress(Some(SettingKind::Address.parse(text)? as u8)),
+        },
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    match words.next() {
+        Some(_) => Err(ParseError::UnexpectedArgument),
+        None => Ok(command),
+    }
+}
+
+/// Why a command couldn't be carried out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CommandError {
+    /// The firmware can't do this yet
+    Unsupported,
+    /// The lid is open, so sensors can't be written
+    InterlockOpen,
+    /// The run was stopped part way through
+    Aborted,
+    /// The firmware doesn't accept the value
+    Rejected,
+    /// The change was made but couldn't be saved to flash, so is lost at the next reset
+    NotSaved,
+    /// The result log couldn't be read
+    LogUnreadable,
+    /// The jig is still working on the commands before this one
+    Busy,
+    /// The continuous address counter couldn't be read or saved
+    AddressesUnavailable,
+}
+
+impl CommandError {
+    /// The reason for the console, after `error: `
+    pub const fn message(&self) -> &'static str {
+        match self {
+            CommandError::Unsupported => "not supported by this firmware",
+            CommandError::InterlockOpen => "lid is open",
+            CommandError::Aborted => "run aborted",
+            CommandError::Rejected => "value rejected",
+            CommandError::NotSaved => "changed but not saved",
+            CommandError::LogUnreadable => "result log unreadable",
+            CommandError::Busy => "busy, try again",
+            CommandError::AddressesUnavailable => "address counter unavailable",
+        }
+    }
+}
+
+/// How a programming run ended
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RunSummary {
+    /// The run number the results were logged under
+    pub run: u32,
+    /// The number of sensors that were tried
+    pub attempted: u8,
+    /// The number of those that failed
+    pub failed: u8,
+}
+
+/// How reliably the status LEDs are being driven, as shown by `diag`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LedHealth {
+    /// Frames sent successfully
+    pub frames: u32,
+    /// Transmissions that failed and were retried
+    pub retries: u32,
+    /// Times the LED hardware was set up again
+    pub reinits: u32,
+    /// Frames that could not be sent at all
+    pub failures: u32,
+    /// What went wrong most recently, if anything has
+    pub last_error: Option<&'static str>,
+}
+
+/// The operations of the jig that console commands and protocol requests are carried out with
+#[allow(async_fn_in_trait)]
+pub trait Jig {
+    /// The firmware name and version shown by `version`
+    const VERSION: &'static str;
+    /// Where reading the result log has got to, starting from the oldest record
+    type LogCursor: Default;
+
+    /// The number of sensor sockets
+    fn sockets(&self) -> u8;
+
+    /// Look for the sensor in socket `position`
+    ///
+    /// # Returns
+    /// The sensor's address, or None if the socket is empty
+    async fn scan(&mut self, position: u8) -> Result<Option<u8>, CommandError>;
+
+    /// Program every socket, just as the start button does
+    async fn program(&mut self) -> Result<RunSummary, CommandError>;
+
+    /// Check the sensor in socket `position` answers at its planned address
+    async fn verify(&mut self, position: u8) -> Result<bool, CommandError>;
+
+    /// Put the sensor in socket `position` back on its factory default address
+    async fn reset_default(&mut self, position: u8) -> Result<(), CommandError>;
+
+    /// Turn sending results as they are recorded on or off
+    fn set_monitor(&mut self, on: bool);
+
+    /// Turn sending live readings from the sensors on or off
+    fn watch_readings(&mut self, on: bool) -> Result<(), CommandError>;
+
+    /// The current value of a setting
+    fn setting(&self, id: SettingId) -> u32;
+
+    /// Change a setting, put it to use and save it
+    async fn set_setting(&mut self, id: SettingId, value: u32) -> Result<(), CommandError>;
+
+    /// Read the log record after `cursor`
+    ///
+    /// # Returns
+    /// The record, or None once there are no more
+    fn next_record(
+        &mut self,
+        cursor: &mut Self::LogCursor,
+    ) -> Result<Option<ResultRecord>, CommandError>;
+
+    /// How reliably the status LEDs have been driven since the jig started
+    fn led_health(&self) -> LedHealth;
+
+    /// What the jig has done since it was switched on
+    fn session_counters(&self) -> Counters;
+
+    /// What the jig has done over its life, or None if the stored counters couldn't be read
+    fn lifetime_counters(&self) -> Option<Counters>;
+
+    /// The address continuous mode hands out next
+    ///
+    /// # Returns
+    /// The address, or None if every address in the plan has been handed out
+    fn next_address(&self) -> Result<Option<u8>, CommandError>;
+
+    /// Start handing out continuous addresses from `address` again. Only for when the sensors
+    /// given addresses from there on are known to be gone
+    async fn restart_addresses(&mut self, address: u8) -> Result<(), CommandError>;
+}
+
+/// Where the lines of a reply go
+#[allow(async_fn_in_trait)]
+pub trait Reply {
+    /// Send one line, without its line ending
+    async fn line(&mut self, text: &str);
+}
+
+/// Carry out a command and reply with its output, then `ok` or `error: <reason>`
+pub async fn dispatch<J: Jig, R: Reply>(command: Command, jig: &mut J, reply: &mut R) {
+    match carry_out(command, jig, reply).await {
+        Ok(()) => reply.line("ok").await,
+        Err(e) => reply_command_error(e, reply).await,
+    }
+}
+
+/// Reply to a line that isn't a command
+pub async fn reply_parse_error<R: Reply>(e: ParseError, reply: &mut R) {
+    error(reply, e.message()).await
+}
+
+/// Reply to a command that couldn't be carried out
+pub async fn reply_command_error<R: Reply>(e: CommandError, reply: &mut R) {
+    error(reply, e.message()).await
+}
+
+async fn error<R: Reply>(reply: &mut R, message: &str) {
+    let mut line = ReplyLine::new();
+    // Can't fail, as every message fits
+    let _ = write!(line, "error: {}", message);
+    reply.line(&line).await
+}
+
+/// Replace what `line` holds with formatted text, cutting it short if it doesn't fit
+fn format(line: &mut ReplyLine, args: core::fmt::Arguments) {
+    line.clear();
+    let _ = line.write_fmt(args);
+}
+
+/// Reply with the counters under a label, as shown by `stats`
+async fn reply_counters<R: Reply>(label: &str, counters: &Counters, reply: &mut R) {
+    let mut line = ReplyLine::new();
+    format(
+        &mut line,
+        format_args!(
+            "{}: {} boots, {} runs, {} attempted, {} programmed, {} hours",
+            label,
+            counters.boots,
+            counters.runs,
+            counters.attempted,
+            counters.programmed,
+            counters.uptime_hours
+        ),
+    );
+    reply.line(&line).await;
+    match counters.first_pass_yield() {
+        Some(tenths) => format(
+            &mut line,
+            format_args!("{} yield: {}.{}%", label, tenths / 10, tenths % 10),
+        ),
+        None => format(&mut line, format_args!("{} yield: none tried", label)),
+    }
+    reply.line(&line).await;
+    // Only the reasons that have happened, so the list stays short
+    for code in 1..=counters.error_failures.len() as u16 {
+        let Some(error) = ErrorCode::from_u16(code) else {
+            continue;
+        };
+        let failures = counters.failures(error);
+        if failures > 0 {
+            format(
+                &mut line,
+                format_args!("{} {}: {}", label, error.name(), failures),
+            );
+            reply.line(&line).await;
+        }
+    }
+    format(&mut line, format_args!("{} by socket:", label));
+    for failures in counters.socket_failures {
+        let _ = write!(line, " {}", failures);
+    }
+    reply.line(&line).await;
+}
+
+/// Write a setting as `name = value`
+fn format_setting(line: &mut ReplyLine, id: SettingId, value: u32) {
+    format(line, format_args!("{} = ", id.name()));
+    let _ = id.kind().write(value, line);
+}
+
+async fn carry_out<J: Jig, R: Reply>(
+    command: Command,
+    jig: &mut J,
+    reply: &mut R,
+) -> Result<(), CommandError> {
+    let mut line = ReplyLine::new();
+    match command {
+        Command::Help => {
+            for text in HELP {
+                reply.line(text).await;
+            }
+        }
+        Command::Version => reply.line(J::VERSION).await,
+        Command::Scan => {
+            for position in 0..jig.sockets() {
+                match jig.scan(position).await? {
+                    Some(address) => format(
+                        &mut line,
+                        format_args!("socket {}: 0x{:02x}", position, address),
+                    ),
+                    None => format(&mut line, format_args!("socket {}: empty", position)),
+                }
+                reply.line(&line).await;
+            }
+        }
+        Command::Program => {
+            let summary = jig.program().await?;
+            format(
+                &mut line,
+                format_args!(
+                    "run {}: {} programmed, {} failed",
+                    summary.run,
+                    summary.attempted.saturating_sub(summary.failed),
+                    summary.failed
+                ),
+            );
+            reply.line(&line).await;
+        }
+        Command::Verify => {
+            for position in 0..jig.sockets() {
+                let answered = jig.verify(position).await?;
+                let result = if answered { "ok" } else { "no answer" };
+                format(&mut line, format_args!("socket {}: {}", position, result));
+                reply.line(&line).await;
+            }
+        }
+        Command::ResetDefault => {
+            for position in 0..jig.sockets() {
+                jig.reset_default(position).await?;
+                format(&mut line, format_args!("socket {}: reset", position));
+                reply.line(&line).await;
+            }
+        }
+        Command::Monitor(on) => jig.set_monitor(on),
+        Command::Get(Some(id)) => {
+            format_setting(&mut line, id, jig.setting(id));
+            reply.line(&line).await;
+        }
+        Command::Get(None) => {
+            for id in SettingId::ALL {
+                format_setting(&mut line, id, jig.setting(id));
+                reply.line(&line).await;
+            }
+        }
+        Command::Set(id, value) => {
+            jig.set_setting(id, value).await?;
+            format_setting(&mut line, id, jig.setting(id));
+            reply.line(&line).await;
+        }
+        Command::LogDump => {
+            reply.line(CSV_HEADER).await;
+            let mut cursor = J::LogCursor::default();
+            while let Some(record) = jig.next_record(&mut cursor)? {
+                line.clear();
+                let _ = record.write_csv(&mut line);
+                reply.line(&line).await;
+            }
+        }
+        Command::Diag => {
+            let led = jig.led_health();
+            format(
+                &mut line,
+                format_args!(
+                    "led: {} frames, {} retries, {} reinits, {} failures",
+                    led.frames, led.retries, led.reinits, led.failures
+                ),
+            );
+            reply.line(&line).await;
+            format(
+                &mut line,
+                format_args!("led last error: {}", led.last_error.unwrap_or("none")),
+            );
+            reply.line(&line).await;
+        }
+        Command::Stats => {
+            reply_counters("session", &jig.session_counters(), reply).await;
+            match jig.lifetime_counters() {
+                Some(counters) => reply_counters("lifetime", &counters, reply).await,
+                None => reply.line("lifetime: unreadable").await,
+            }
+        }
+        Command::NextAddress(restart) => {
+            if let Some(address) = restart {
+                jig.restart_addresses(address).await?;
+            }
+            match jig.next_address()? {
+                Some(address) => format(&mut line, format_args!("next address: 0x{:02x}", address)),
+                None => format(
+                    &mut line,
+                    format_args!("next address: none, all handed out"),
+                ),
+            }
+            reply.line(&line).await;
+        }
+    }
+    Ok(())
+}
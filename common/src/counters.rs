@@ -0,0 +1,178 @@
+//! Counters module keeps lifetime statistics about the jig, to spot a socket wearing out or a
+//! bad batch of sensors.
+//!
+//! [`Counters`] are kept in the flash store under [`Key::Counters`]. Counting happens in RAM and
+//! the counters are only saved at the end of each run, once an hour for the uptime, and at boot.
+//! The store skips writes that change nothing and spreads the rest over its sectors, so a run
+//! costs at most one record. Stored counters that can't be read are left alone rather than
+//! saved over from zero, as the flash may only have failed for a moment.
+//!
+//! Layout, all little endian, [`ENCODED_LEN`] bytes:
+//! - 0..2: layout version, 2..4: reserved
+//! - 4..24: boots, runs, sensors attempted, sensors programmed, uptime hours
+//! - 24..48: failures for each [`ErrorCode`] other than [`ErrorCode::NoError`], in code order
+//! - 48..80: failures at each socket position
+//! - 80..84: CRC-32 of everything before it
+
+use crate::{
+    crc::crc32,
+    flash::Flash,
+    record::{ErrorCode, ResultRecord, Verdict},
+    storage::{Key, KvStore, MAX_VALUE_LEN, StorageError},
+};
+
+/// The layout version written by this firmware
+pub const COUNTERS_VERSION: u16 = 1;
+
+/// The length of encoded counters in bytes
+pub const ENCODED_LEN: usize = 84;
+
+/// The number of error codes that count as failures
+pub const FAILURE_KINDS: usize = 6;
+
+/// The number of socket positions with their own failure count
+pub const SOCKET_COUNT: usize = 8;
+
+const CRC_OFFSET: usize = ENCODED_LEN - 4;
+
+/// Running totals of what the jig has done
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Counters {
+    pub boots: u32,
+    /// Programming runs started, including those aborted
+    pub runs: u32,
+    /// Sensors that passed or failed. Sensors in a run that was aborted don't count
+    pub attempted: u32,
+    /// Sensors that were programmed and verified
+    pub programmed: u32,
+    /// Whole hours the jig has been powered on
+    pub uptime_hours: u32,
+    /// Failures by error code, starting at code 1. See [`Self::failures`]
+    pub error_failures: [u32; FAILURE_KINDS],
+    /// Failures and aborts by socket position
+    pub socket_failures: [u32; SOCKET_COUNT],
+}
+
+/// Read four little endian bytes as a counter
+fn counter(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl Counters {
+    /// Count the result of one sensor
+    pub fn record(&mut self, record: &ResultRecord) {
+        match record.verdict {
+            Verdict::Pass => {
+                self.attempted = self.attempted.saturating_add(1);
+                self.programmed = self.programmed.saturating_add(1);
+            }
+            Verdict::Fail => self.attempted = self.attempted.saturating_add(1),
+            Verdict::Aborted => {}
+        }
+        if let Some(index) = (record.error as usize).checked_sub(1)
+            && let Some(failures) = self.error_failures.get_mut(index)
+        {
+            *failures = failures.saturating_add(1);
+        }
+        if record.verdict != Verdict::Pass
+            && let Some(failures) = self.socket_failures.get_mut(record.position as usize)
+        {
+            *failures = failures.saturating_add(1);
+        }
+    }
+
+    /// The number of sensors that didn't pass for the given reason
+    pub fn failures(&self, error: ErrorCode) -> u32 {
+        (error as usize)
+            .checked_sub(1)
+            .and_then(|index| self.error_failures.get(index))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The share of sensors that passed the first time they were tried, in tenths of a
+    /// percent. A sensor that fails and is tried again in a later run counts as a new attempt.
+    ///
+    /// # Returns
+    /// The yield from 0 to 1000, or None if no sensors have been tried
+    pub fn first_pass_yield(&self) -> Option<u16> {
+        (self.attempted > 0).then(|| (self.programmed as u64 * 1000 / self.attempted as u64) as u16)
+    }
+
+    pub fn to_bytes(&self) -> [u8; ENCODED_LEN] {
+        let mut bytes = [0; ENCODED_LEN];
+        bytes[0..2].copy_from_slice(&COUNTERS_VERSION.to_le_bytes());
+        let totals = [
+            self.boots,
+            self.runs,
+            self.attempted,
+            self.programmed,
+            self.uptime_hours,
+        ];
+        let counters = totals
+            .iter()
+            .chain(self.error_failures.iter())
+            .chain(self.socket_failures.iter());
+        for (chunk, value) in bytes[4..CRC_OFFSET]
+            .as_chunks_mut::<4>()
+            .0
+            .iter_mut()
+            .zip(counters)
+        {
+            *chunk = value.to_le_bytes();
+        }
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode counters, or None if they are the wrong length, version or fail their CRC
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ENCODED_LEN
+            || u16::from_le_bytes([bytes[0], bytes[1]]) != COUNTERS_VERSION
+            || crc32(&bytes[..CRC_OFFSET]).to_le_bytes() != bytes[CRC_OFFSET..]
+        {
+            return None;
+        }
+        let mut counters = Self {
+            boots: counter(&bytes[4..8]),
+            runs: counter(&bytes[8..12]),
+            attempted: counter(&bytes[12..16]),
+            programmed: counter(&bytes[16..20]),
+            uptime_hours: counter(&bytes[20..24]),
+            ..Self::default()
+        };
+        let stored = counters
+            .error_failures
+            .iter_mut()
+            .chain(counters.socket_failures.iter_mut());
+        for (value, chunk) in stored.zip(bytes[24..CRC_OFFSET].as_chunks::<4>().0) {
+            *value = u32::from_le_bytes(*chunk);
+        }
+        Some(counters)
+    }
+
+    /// Load the counters from the store, starting again from zero if none are stored or the
+    /// stored ones are corrupt.
+    ///
+    /// # Returns
+    /// The counters, or the error if the flash couldn't be read. The stored counters may still
+    /// be intact, so the caller must not save over them
+    pub fn load<F: Flash>(store: &mut KvStore<F>) -> Result<Self, StorageError> {
+        let mut bytes = [0; MAX_VALUE_LEN];
+        Ok(match store.read(Key::Counters, &mut bytes)? {
+            Some(len) => Self::from_bytes(&bytes[..len]).unwrap_or_else(|| {
+                #[cfg(feature = "defmt")]
+                defmt::warn!("COUNTERS: Stored counters are corrupt, starting from zero");
+                Self::default()
+            }),
+            None => Self::default(),
+        })
+    }
+
+    /// Save the counters to the store
+    pub fn save<F: Flash>(&self, store: &mut KvStore<F>) -> Result<(), StorageError> {
+        store.write(Key::Counters, &self.to_bytes())
+    }
+}
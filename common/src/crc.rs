@@ -0,0 +1,18 @@
+//! CRC module checks that data read back from flash or a link is what was written.
+
+/// The reflected CRC-32 polynomial used by Ethernet, zip and many others
+const POLY: u32 = 0xedb8_8320;
+
+/// CRC-32 (IEEE) of `data`. Bitwise rather than table driven, as it only covers a few bytes
+/// at a time and the table would cost 1 KiB of flash.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
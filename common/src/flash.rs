@@ -0,0 +1,45 @@
+//! Flash module is how everything kept across a reset reaches the flash, whether it is the
+//! jig's own or [`crate::ram_flash::RamFlash`] in a test.
+
+/// Why a flash operation failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlashError {
+    /// The operation reached outside the flash
+    OutOfBounds,
+    /// The offset or length was not a multiple of [`WRITE_SIZE`] or the sector size
+    NotAligned,
+    /// The partition has fewer sectors than what is kept in it needs
+    TooFewSectors,
+    /// The flash itself reported an error
+    Hardware,
+}
+
+/// Reads and writes must start on, and be a whole number of, this many bytes
+pub const WRITE_SIZE: u32 = 4;
+
+/// Check an offset and length are aligned to [`WRITE_SIZE`]
+pub fn check_aligned(offset: u32, len: usize) -> Result<(), FlashError> {
+    if offset.is_multiple_of(WRITE_SIZE) && (len as u32).is_multiple_of(WRITE_SIZE) {
+        Ok(())
+    } else {
+        Err(FlashError::NotAligned)
+    }
+}
+
+/// Somewhere to keep data that survives a reset. Flash can only be erased a sector at a time,
+/// back to all 0xFF bytes, and writes can only clear bits. The storage code only talks to flash
+/// through this, so it can be tested against [`crate::ram_flash::RamFlash`].
+pub trait Flash {
+    /// The size of one erasable sector in bytes
+    const SECTOR_SIZE: u32;
+
+    /// Read `buf.len()` bytes starting at `offset`. Both must be aligned to [`WRITE_SIZE`]
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FlashError>;
+
+    /// Write `data` starting at `offset`. Both must be aligned to [`WRITE_SIZE`]
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Erase the sector starting at `offset`, which must be aligned to [`Self::SECTOR_SIZE`]
+    fn erase(&mut self, offset: u32) -> Result<(), FlashError>;
+}
@@ -0,0 +1,322 @@
+//! Gestures module turns button edges into clicks, double clicks, long presses and chords.
+//!
+//! The jig has only two buttons, so each one supports several gestures and pressing both
+//! together is a gesture of its own. [`GestureRecognizer`] is a pure state machine: it is fed the
+//! time of every press and release and asked for timed events with [`GestureRecognizer::poll`],
+//! so it needs no hardware and can be tested with made up timestamps.
+//!
+//! The rotary encoder, where fitted, publishes its steps and presses as [`ButtonEvent`]s too.
+//! Menus should use [`ButtonEvent::navigation`] rather than matching gestures, so they work the
+//! same with the buttons or the encoder.
+//!
+//! Timings follow the old press/hold classification of the button task. A press held for half a
+//! second is a long press, and from a second onwards it repeats until released.
+
+use embassy_time::{Duration, Instant};
+
+/// Identifies which of the jig's buttons an event came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ButtonId {
+    /// The button on GPIO9
+    Button0,
+    /// The button on GPIO3
+    Button1,
+}
+
+impl ButtonId {
+    /// Index of this button in per-button arrays
+    const fn index(&self) -> usize {
+        match self {
+            ButtonId::Button0 => 0,
+            ButtonId::Button1 => 1,
+        }
+    }
+
+    /// The other button
+    const fn other(&self) -> Self {
+        match self {
+            ButtonId::Button0 => ButtonId::Button1,
+            ButtonId::Button1 => ButtonId::Button0,
+        }
+    }
+}
+
+/// What was done with a button, or with both buttons together
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gesture {
+    /// A short press with no second press following it
+    Click,
+    /// Two short presses in quick succession
+    DoubleClick,
+    /// Held past the long press time. Sent while the button is still held
+    LongPress,
+    /// Still held after a long press. Sent repeatedly until the button is released
+    Repeat,
+}
+
+/// What was done with the rotary encoder
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncoderAction {
+    /// Turned one detent clockwise
+    Clockwise,
+    /// Turned one detent anticlockwise
+    AntiClockwise,
+    /// The push switch was pressed and released
+    Click,
+    /// The push switch was held past the long press time. Sent while it is still held
+    LongPress,
+}
+
+/// A recognised gesture
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ButtonEvent {
+    /// A gesture on a single button
+    Single(ButtonId, Gesture),
+    /// A gesture with both buttons pressed together. Chords can be clicked, long pressed and
+    /// repeated but not double clicked
+    Chord(Gesture),
+    /// A step or press of the rotary encoder
+    Encoder(EncoderAction),
+}
+
+/// Moving around menus and value editors. The buttons and the rotary encoder both map onto
+/// these, so anything driven by navigation works with either
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Navigation {
+    /// Move to the next item, or increase a value
+    Next,
+    /// Move to the previous item, or decrease a value
+    Previous,
+    /// Enter the selected item, or accept a value
+    Select,
+    /// Leave the current menu, or cancel an edit
+    Back,
+}
+
+impl ButtonEvent {
+    /// The navigation this event stands for, if any.
+    ///
+    /// With the buttons, button 0 moves: click for next, double click for previous, and hold to
+    /// keep moving. Button 1 clicks to select and holds to go back. With the encoder, turning
+    /// moves, clicking selects and holding goes back.
+    pub const fn navigation(&self) -> Option<Navigation> {
+        match self {
+            ButtonEvent::Single(ButtonId::Button0, Gesture::Click)
+            | ButtonEvent::Single(ButtonId::Button0, Gesture::LongPress)
+            | ButtonEvent::Single(ButtonId::Button0, Gesture::Repeat)
+            | ButtonEvent::Encoder(EncoderAction::Clockwise) => Some(Navigation::Next),
+            ButtonEvent::Single(ButtonId::Button0, Gesture::DoubleClick)
+            | ButtonEvent::Encoder(EncoderAction::AntiClockwise) => Some(Navigation::Previous),
+            ButtonEvent::Single(ButtonId::Button1, Gesture::Click)
+            | ButtonEvent::Encoder(EncoderAction::Click) => Some(Navigation::Select),
+            ButtonEvent::Single(ButtonId::Button1, Gesture::LongPress)
+            | ButtonEvent::Encoder(EncoderAction::LongPress) => Some(Navigation::Back),
+            _ => None,
+        }
+    }
+}
+
+/// The times that separate one gesture from another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GestureTimings {
+    /// The longest gap between releasing and pressing again that counts as a double click.
+    /// Single clicks are reported once this has passed without a second press
+    pub double_click: Duration,
+    /// How long a button must be held to be a long press
+    pub long_press: Duration,
+    /// How long after the press the first repeat is sent
+    pub repeat_delay: Duration,
+    /// How often repeats are sent after the first
+    pub repeat_interval: Duration,
+    /// The longest gap between pressing the two buttons that counts as pressing them together
+    pub chord_window: Duration,
+}
+
+impl Default for GestureTimings {
+    fn default() -> Self {
+        Self {
+            double_click: Duration::from_millis(300),
+            long_press: Duration::from_millis(500),
+            repeat_delay: Duration::from_millis(1000),
+            repeat_interval: Duration::from_millis(250),
+            chord_window: Duration::from_millis(150),
+        }
+    }
+}
+
+/// Where a button, or the chord, is in making up a gesture
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyState {
+    /// Released and nothing pending
+    Idle,
+    /// Held down since the given time
+    Down {
+        since: Instant,
+        /// When the next long press or repeat is due
+        next: Instant,
+        /// True once the long press has been sent
+        long: bool,
+    },
+    /// Released after a short press, waiting to see if a second press follows
+    Released { at: Instant },
+    /// Pressed a second time within the double click time
+    SecondDown,
+    /// Part of a chord, so ignored until both buttons are released. The chord itself ends as soon
+    /// as either button is released
+    Chorded { down: bool },
+}
+
+impl KeyState {
+    /// The state of a key pressed at `at`
+    fn down(at: Instant, timings: &GestureTimings) -> Self {
+        KeyState::Down {
+            since: at,
+            next: at + timings.long_press,
+            long: false,
+        }
+    }
+
+    /// When this key next needs [`GestureRecognizer::poll`] to be called
+    fn deadline(&self, timings: &GestureTimings) -> Option<Instant> {
+        match *self {
+            KeyState::Down { next, .. } => Some(next),
+            KeyState::Released { at } => Some(at + timings.double_click),
+            _ => None,
+        }
+    }
+
+    /// Advance any timers that are due at `now`
+    fn poll(&mut self, now: Instant, timings: &GestureTimings) -> Option<Gesture> {
+        match self {
+            KeyState::Down { since, next, long } if now >= *next => {
+                let gesture = if *long {
+                    *next += timings.repeat_interval;
+                    Gesture::Repeat
+                } else {
+                    *long = true;
+                    *next = (*since + timings.repeat_delay).max(*next + timings.repeat_interval);
+                    Gesture::LongPress
+                };
+                Some(gesture)
+            }
+            KeyState::Released { at } if now >= *at + timings.double_click => {
+                *self = KeyState::Idle;
+                Some(Gesture::Click)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Recognises gestures from the press and release times of both buttons
+pub struct GestureRecognizer {
+    /// The times that separate one gesture from another
+    timings: GestureTimings,
+    /// The state of each button on its own
+    keys: [KeyState; 2],
+    /// The state of the two-button chord
+    chord: KeyState,
+}
+
+impl GestureRecognizer {
+    pub fn new(timings: GestureTimings) -> Self {
+        Self {
+            timings,
+            keys: [KeyState::Idle; 2],
+            chord: KeyState::Idle,
+        }
+    }
+
+    /// Feed in a press or release.
+    ///
+    /// # Arguments
+    /// * `button` - The button that changed
+    /// * `pressed` - True if it was pressed, false if it was released
+    /// * `at` - When it changed
+    ///
+    /// # Returns
+    /// A gesture completed by this edge, if any
+    pub fn edge(&mut self, button: ButtonId, pressed: bool, at: Instant) -> Option<ButtonEvent> {
+        let timings = self.timings;
+        let other = self.keys[button.other().index()];
+        let key = &mut self.keys[button.index()];
+        match (*key, pressed) {
+            (KeyState::Idle, true) => {
+                // Pressing shortly after the other button, before it has done anything, makes a chord
+                if let KeyState::Down {
+                    since, long: false, ..
+                } = other
+                    && at - since <= timings.chord_window
+                {
+                    *key = KeyState::Chorded { down: true };
+                    self.keys[button.other().index()] = KeyState::Chorded { down: true };
+                    self.chord = KeyState::down(since, &timings);
+                } else {
+                    *key = KeyState::down(at, &timings);
+                }
+                None
+            }
+            (KeyState::Down { long, .. }, false) => {
+                *key = if long {
+                    KeyState::Idle
+                } else {
+                    KeyState::Released { at }
+                };
+                None
+            }
+            (KeyState::Released { .. }, true) => {
+                *key = KeyState::SecondDown;
+                None
+            }
+            (KeyState::SecondDown, false) => {
+                *key = KeyState::Idle;
+                Some(ButtonEvent::Single(button, Gesture::DoubleClick))
+            }
+            (KeyState::Chorded { .. }, down) => {
+                *key = KeyState::Chorded { down };
+                if down {
+                    return None;
+                }
+                if other == (KeyState::Chorded { down: false }) {
+                    // Both buttons are up so they go back to working on their own
+                    self.keys = [KeyState::Idle; 2];
+                }
+                // The chord ends when either button is released
+                match core::mem::replace(&mut self.chord, KeyState::Idle) {
+                    KeyState::Down { long: false, .. } => Some(ButtonEvent::Chord(Gesture::Click)),
+                    _ => None,
+                }
+            }
+            // Edges that don't fit the state, such as a release we never saw pressed, are ignored
+            _ => None,
+        }
+    }
+
+    /// Report any gesture whose time has come. Call this whenever [`Self::deadline`] passes, and
+    /// keep calling it until it returns None as more than one gesture may be due.
+    pub fn poll(&mut self, now: Instant) -> Option<ButtonEvent> {
+        let timings = self.timings;
+        if let Some(gesture) = self.chord.poll(now, &timings) {
+            return Some(ButtonEvent::Chord(gesture));
+        }
+        for button in [ButtonId::Button0, ButtonId::Button1] {
+            if let Some(gesture) = self.keys[button.index()].poll(now, &timings) {
+                return Some(ButtonEvent::Single(button, gesture));
+            }
+        }
+        None
+    }
+
+    /// The next time [`Self::poll`] may have something to report, if any
+    pub fn deadline(&self) -> Option<Instant> {
+        [self.chord, self.keys[0], self.keys[1]]
+            .iter()
+            .filter_map(|key| key.deadline(&self.timings))
+            .min()
+    }
+}
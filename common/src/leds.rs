@@ -0,0 +1,470 @@
+//! Leds module turns the frames the animations draw into what is sent to the LED string.
+//!
+//! [`LedDriver`] colour corrects and dims each [`LedBuffer`] with its [`LedConfig`], keeps it
+//! within the [`PowerBudget`] and hands it to a [`LedOutput`], which the firmware implements for
+//! each kind of LED hardware. None of this touches the hardware, so it is tested on the host with
+//! [`crate::recording::RecordingOutput`] standing in for the LEDs.
+
+use core::{cell::Cell, ops::Range};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use smart_leds::RGB8;
+
+#[cfg(feature = "socket-leds")]
+use crate::counters::SOCKET_COUNT;
+
+/// The number of LEDs in the string we are driving
+#[cfg(not(feature = "socket-leds"))]
+pub const LED_STRING_SIZE: usize = 1;
+
+/// The number of LEDs in the string we are driving. The status pixel comes first, followed by
+/// one pixel next to each sensor socket
+#[cfg(feature = "socket-leds")]
+pub const LED_STRING_SIZE: usize = 1 + SOCKET_COUNT;
+
+/// Convenience type so we speak the same language when dealing with animations etc.
+pub type LedBuffer = [RGB8; LED_STRING_SIZE];
+
+/// A contiguous run of pixels in the LED string that an animation draws to
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PixelRange {
+    /// Index of the first pixel
+    pub start: usize,
+    /// Number of pixels in the run
+    pub len: usize,
+}
+
+impl PixelRange {
+    /// Every pixel in the string
+    pub const ALL: Self = Self::new(0, LED_STRING_SIZE);
+
+    /// Create a range of `len` pixels starting at `start`
+    pub const fn new(start: usize, len: usize) -> Self {
+        Self { start, len }
+    }
+
+    /// A range covering just the pixel at `index`
+    pub const fn single(index: usize) -> Self {
+        Self::new(index, 1)
+    }
+
+    /// The buffer indices covered by this range, clipped to the length of the string
+    pub fn indices(&self) -> Range<usize> {
+        let start = self.start.min(LED_STRING_SIZE);
+        let end = self.start.saturating_add(self.len).min(LED_STRING_SIZE);
+        start..end
+    }
+
+    /// Copy the pixels covered by this range from `src` into `dst`, leaving the rest of `dst`
+    /// untouched
+    pub fn blit(&self, dst: &mut LedBuffer, src: &LedBuffer) {
+        let range = self.indices();
+        dst[range.clone()].copy_from_slice(&src[range]);
+    }
+}
+
+/// The gamma curve used to turn logical colour values into LED drive levels. Different LED parts
+/// respond differently, so pick the one that makes ramps look even on the fitted part
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GammaCurve {
+    /// No correction, the logical value is sent as is
+    Linear,
+    /// Square law, a gentle curve suiting LEDs that are already fairly perceptually even
+    Square,
+    /// The smart-leds gamma table (about 2.8), which suits most WS2812 parts
+    #[default]
+    Standard,
+}
+
+/// Per-channel scale applied after gamma correction to balance the white point of an
+/// installation. 255 leaves a channel at full scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WhiteBalance {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Default for WhiteBalance {
+    fn default() -> Self {
+        Self {
+            r: 255,
+            g: 255,
+            b: 255,
+        }
+    }
+}
+
+/// A simple model of the current drawn by the LED string, used to keep it within what the
+/// supply can deliver
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerBudget {
+    /// Current drawn by each of the red, green and blue channels of one LED at full scale, in mA
+    pub channel_ma: [u16; 3],
+    /// Current drawn by each LED when it is off, in mA
+    pub idle_ma: u16,
+    /// The most current the string may draw, in mA
+    pub budget_ma: u32,
+}
+
+impl Default for PowerBudget {
+    /// Typical WS2812 figures against what the QT Py 3V3 regulator can spare
+    fn default() -> Self {
+        Self {
+            channel_ma: [20, 20, 20],
+            idle_ma: 1,
+            budget_ma: 400,
+        }
+    }
+}
+
+/// What the power model made of the last frame sent to the string
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerReport {
+    /// The estimated current drawn by the frame as sent, in mA
+    pub estimated_ma: u32,
+    /// True if the frame was dimmed to keep within the budget
+    pub limited: bool,
+}
+
+impl PowerBudget {
+    /// The estimated current drawn by the string showing `frame`, in mA
+    pub fn estimate_ma(&self, frame: &LedBuffer) -> u32 {
+        self.idle_total_ma() + self.dynamic_ma(frame)
+    }
+
+    /// Dim `frame` evenly so that its estimated current stays within the budget.
+    ///
+    /// # Returns
+    /// The estimate for the frame as it was left, and whether it had to be dimmed
+    pub fn limit(&self, frame: &mut LedBuffer) -> PowerReport {
+        let dynamic = self.dynamic_ma(frame);
+        let allowed = self.budget_ma.saturating_sub(self.idle_total_ma());
+        let limited = dynamic > allowed;
+        if limited {
+            let factor = (allowed * 255 / dynamic) as u8;
+            for pix in frame.iter_mut() {
+                *pix = RGB8::new(
+                    scale(pix.r, factor),
+                    scale(pix.g, factor),
+                    scale(pix.b, factor),
+                );
+            }
+        }
+        PowerReport {
+            estimated_ma: self.estimate_ma(frame),
+            limited,
+        }
+    }
+
+    /// Current drawn by the string with every LED off
+    fn idle_total_ma(&self) -> u32 {
+        self.idle_ma as u32 * LED_STRING_SIZE as u32
+    }
+
+    /// Current drawn by the lit channels of `frame`
+    fn dynamic_ma(&self, frame: &LedBuffer) -> u32 {
+        let [r, g, b] = self.channel_ma.map(|ma| ma as u32);
+        let scaled: u32 = frame
+            .iter()
+            .map(|pix| pix.r as u32 * r + pix.g as u32 * g + pix.b as u32 * b)
+            .sum();
+        scaled / 255
+    }
+}
+
+/// Colour correction applied to every frame on its way to the LED string
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LedConfig {
+    /// The gamma curve for the fitted LED part
+    pub gamma: GammaCurve,
+    /// The white balance for this installation
+    pub white_balance: WhiteBalance,
+    /// The current budget frames are limited to
+    pub power: PowerBudget,
+}
+
+impl LedConfig {
+    /// Use the given gamma curve
+    pub fn with_gamma(mut self, gamma: GammaCurve) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Use the given white balance
+    pub fn with_white_balance(mut self, white_balance: WhiteBalance) -> Self {
+        self.white_balance = white_balance;
+        self
+    }
+
+    /// Use the given power budget
+    pub fn with_power(mut self, power: PowerBudget) -> Self {
+        self.power = power;
+        self
+    }
+
+    /// Apply gamma correction, white balance and brightness to a logical frame.
+    ///
+    /// # Parameters
+    /// * `frame` - The logical colours to show
+    /// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+    ///
+    /// # Returns
+    /// The values to send to the LED string
+    pub fn correct(&self, frame: &LedBuffer, brightness: u8) -> LedBuffer {
+        let mut output = *frame;
+        match self.gamma {
+            GammaCurve::Linear => {}
+            GammaCurve::Square => {
+                for pix in output.iter_mut() {
+                    *pix = RGB8::new(square(pix.r), square(pix.g), square(pix.b));
+                }
+            }
+            GammaCurve::Standard => {
+                for (pix, corrected) in output
+                    .iter_mut()
+                    .zip(smart_leds::gamma(frame.iter().cloned()))
+                {
+                    *pix = corrected;
+                }
+            }
+        }
+        let wb = self.white_balance;
+        let balanced =
+            output.map(|pix| RGB8::new(scale(pix.r, wb.r), scale(pix.g, wb.g), scale(pix.b, wb.b)));
+        for (pix, corrected) in output
+            .iter_mut()
+            .zip(smart_leds::brightness(balanced.iter().cloned(), brightness))
+        {
+            *pix = corrected;
+        }
+        output
+    }
+}
+
+/// Square law gamma for one channel
+fn square(v: u8) -> u8 {
+    ((v as u16 * v as u16) / 255) as u8
+}
+
+/// Scale one channel by `factor`, where 255 leaves it unchanged
+fn scale(v: u8, factor: u8) -> u8 {
+    ((v as u16 * factor as u16) / 255) as u8
+}
+
+/// Errors from driving the LED string
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedError {
+    /// The output hardware could not be set up
+    Init,
+    /// The output failed to send a frame, even after retrying and reinitialising it
+    Transmission,
+}
+
+impl LedError {
+    /// A short description for the console
+    pub const fn message(&self) -> &'static str {
+        match self {
+            LedError::Init => "hardware not set up",
+            LedError::Transmission => "transmission failed",
+        }
+    }
+}
+
+/// Counters describing how reliably the LED string is being driven
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LedDiagnostics {
+    /// Frames sent successfully
+    pub frames: u32,
+    /// Transmissions that failed and were retried by the output
+    pub retries: u32,
+    /// Times the output hardware was reinitialised
+    pub reinits: u32,
+    /// Frames that could not be sent at all
+    pub failures: u32,
+    /// The most recent error, if any
+    pub last_error: Option<LedError>,
+}
+
+impl LedDiagnostics {
+    const fn new() -> Self {
+        Self {
+            frames: 0,
+            retries: 0,
+            reinits: 0,
+            failures: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// The LED diagnostics, shared so they can be read from outside the display task
+static LED_DIAGNOSTICS: Mutex<CriticalSectionRawMutex, Cell<LedDiagnostics>> =
+    Mutex::new(Cell::new(LedDiagnostics::new()));
+
+/// A snapshot of the LED diagnostic counters
+pub fn led_diagnostics() -> LedDiagnostics {
+    LED_DIAGNOSTICS.lock(|d| d.get())
+}
+
+/// Update the LED diagnostic counters, such as when an output retries or reinitialises
+pub fn update_diagnostics(f: impl FnOnce(&mut LedDiagnostics)) {
+    LED_DIAGNOSTICS.lock(|d| {
+        let mut diagnostics = d.get();
+        f(&mut diagnostics);
+        d.set(diagnostics);
+    });
+}
+
+/// Something that can show a frame of corrected values on physical LEDs. The display task only
+/// talks to the LEDs through this, via [`LedDriver`], so the fixture can use whichever output
+/// suits its hardware.
+#[allow(async_fn_in_trait)]
+pub trait LedOutput {
+    /// Show a frame. The values have already been colour corrected and dimmed by [`LedDriver`]
+    async fn show(&mut self, frame: &LedBuffer) -> Result<(), LedError>;
+}
+
+/// An output that may be missing because it couldn't be set up. Without one every frame fails
+/// with [`LedError::Init`], so the jig carries on programming and the failures are counted in
+/// [`led_diagnostics`]
+impl<O: LedOutput> LedOutput for Option<O> {
+    async fn show(&mut self, frame: &LedBuffer) -> Result<(), LedError> {
+        match self {
+            Some(output) => output.show(frame).await,
+            None => Err(LedError::Init),
+        }
+    }
+}
+
+/// Holds the state needed to drive the LED strip
+pub struct LedDriver<O: LedOutput> {
+    /// The hardware the frames are shown on
+    backend: O,
+    /// Colour correction applied to every frame
+    config: LedConfig,
+    /// The corrected values last sent to the string. Kept apart from the logical frame so the
+    /// caller's buffer is never modified
+    output: LedBuffer,
+    /// What the power model made of the last frame
+    power: PowerReport,
+}
+
+impl<O: LedOutput> LedDriver<O> {
+    /// Create a new driver for the LED string.
+    ///
+    /// # Parameters
+    /// * `backend` - The hardware to show frames on
+    /// * `config` - Colour correction for the fitted LED part and installation
+    pub fn new(backend: O, config: LedConfig) -> Self {
+        Self {
+            backend,
+            config,
+            output: LedBuffer::default(),
+            power: PowerReport::default(),
+        }
+    }
+
+    /// Change the colour correction. Takes effect from the next update
+    pub fn set_config(&mut self, config: LedConfig) {
+        self.config = config;
+    }
+
+    /// The colour correction in use
+    pub fn config(&self) -> LedConfig {
+        self.config
+    }
+
+    /// The corrected values last sent to the string
+    pub fn output(&self) -> &LedBuffer {
+        &self.output
+    }
+
+    /// The estimated current of the last frame and whether it was limited to the budget
+    pub fn power(&self) -> PowerReport {
+        self.power
+    }
+
+    /// The hardware the frames are shown on
+    pub fn backend(&self) -> &O {
+        &self.backend
+    }
+
+    /// The hardware the frames are shown on
+    pub fn backend_mut(&mut self) -> &mut O {
+        &mut self.backend
+    }
+}
+
+impl<O: LedOutput> LedDriver<O> {
+    /// Update the contents of the buffer to the LED string, applying colour correction and
+    /// brightness.
+    ///
+    /// This must be called every time you want to propagate changes you have made to the string to
+    /// the actual LED devices. This is not done automatically as you may want to do multiple changes
+    /// before updating the display. The buffer is left as it is, the corrected values go to a
+    /// separate output buffer.
+    ///
+    /// # Parameters
+    /// * `led_buffer` - Buffer containing the logical LED colours to write to the string
+    /// * `brightness` - Global brightness level from 0 (off) to 255 (max brightness)
+    ///
+    /// The frame is dimmed if needed to keep within the configured power budget, see [`Self::power`].
+    /// Failures are counted in [`led_diagnostics`].
+    pub async fn update_from_buffer(
+        &mut self,
+        led_buffer: &LedBuffer,
+        brightness: u8,
+    ) -> Result<(), LedError> {
+        self.output = self.config.correct(led_buffer, brightness);
+        let power = self.config.power.limit(&mut self.output);
+        #[cfg(feature = "defmt")]
+        if power.limited != self.power.limited {
+            if power.limited {
+                defmt::warn!(
+                    "LED: Limiting to {}mA power budget",
+                    self.config.power.budget_ma
+                );
+            } else {
+                defmt::info!("LED: Power limiting off");
+            }
+        }
+        self.power = power;
+        let result = self.backend.show(&self.output).await;
+        update_diagnostics(|d| match result {
+            Ok(()) => d.frames += 1,
+            Err(e) => {
+                d.failures += 1;
+                d.last_error = Some(e);
+            }
+        });
+        result
+    }
+
+    /// Switches all the LEDS off
+    #[allow(unused)]
+    pub async fn all_off(&mut self) -> Result<(), LedError> {
+        self.update_from_buffer(&LedBuffer::default(), 0).await
+    }
+
+    /// Switches all the LEDS to white at the specified brightness.
+    ///
+    /// # Parameters
+    /// * `brightness` - The brightness level to set all LEDs to, from 0 (off) to 255 (full brightness)
+    pub async fn white(&mut self, brightness: u8) -> Result<(), LedError> {
+        let mut b = LedBuffer::default();
+        b.fill(RGB8 {
+            r: 255,
+            g: 255,
+            b: 255,
+        });
+        self.update_from_buffer(&b, brightness).await
+    }
+}
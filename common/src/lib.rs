@@ -0,0 +1,20 @@
+//! Code shared between the jig firmware and the tools that talk to it. Everything here is
+//! `no_std` and free of hardware, so it is tested on the host with `cargo test` from this
+//! directory.
+
+#![no_std]
+
+pub mod console;
+pub mod counters;
+pub mod crc;
+pub mod flash;
+pub mod gestures;
+#[cfg(feature = "leds")]
+pub mod leds;
+pub mod protocol;
+pub mod ram_flash;
+pub mod record;
+#[cfg(feature = "leds")]
+pub mod recording;
+pub mod settings;
+pub mod storage;
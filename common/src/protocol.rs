@@ -0,0 +1,265 @@
+//! Protocol module is the binary link between the jig and test station software on a PC. It
+//! shares the USB serial port with the console.
+//!
+//! The host sends a [`Request`] and the jig answers with any number of [`Response`]s, ending
+//! with [`Response::Done`] or [`Response::Error`]. Results and readings the host has asked to
+//! watch are sent as they happen, so they can come between the responses to a request.
+//!
+//! Each message is encoded with postcard and followed by a little endian CRC-32 of the
+//! encoding. The lot is COBS encoded, so it holds no zero bytes, and a zero byte ends the frame.
+//! A frame that fails its CRC or doesn't decode is dropped, and reading picks up again after the
+//! next zero byte.
+//!
+//! The port starts as a console. A zero byte switches it to the protocol, so the host sends one
+//! before its first frame. [`Request::Close`] switches it back. Terminals can send a zero byte
+//! too, with Ctrl-@ or Ctrl-Space, so the port also goes back to the console if no frame
+//! decodes within [`FIRST_FRAME_TIMEOUT`] of switching. Once a frame has decoded the port stays
+//! on the protocol until it is closed, however long the host then waits for results.
+
+use embassy_time::Duration;
+use heapless::String;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    console::{CommandError, Jig, RunSummary, SettingId},
+    crc::crc32,
+    record::ResultRecord,
+};
+
+/// The protocol version reported in [`Response::Hello`]. Changes that old hosts or firmware
+/// can't read get a new version
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// How long after a zero byte switches the port to the protocol the first frame must decode
+/// by, or the port goes back to the console
+pub const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The longest message, encoded but before its CRC is added
+pub const MAX_MESSAGE: usize = 64;
+/// The longest frame, including the zero byte that ends it
+pub const MAX_FRAME: usize = cobs::max_encoding_length(MAX_MESSAGE + CRC_LEN) + 1;
+/// The longest firmware name and version sent in [`Response::Hello`]
+pub const FIRMWARE_LEN: usize = 32;
+
+const CRC_LEN: usize = 4;
+
+/// A request from the host
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    /// Ask which firmware and protocol version the jig runs
+    Hello,
+    /// Program every socket, just as the start button does
+    StartRun,
+    /// Turn sending a [`Response::Result`] for each sensor as it is recorded on or off
+    WatchResults(bool),
+    /// Turn sending [`Response::Reading`]s from the sensors on or off
+    WatchReadings(bool),
+    /// Read the whole result log as [`Response::LogRecord`]s, oldest first
+    ReadLog,
+    /// Read every setting as [`Response::Setting`]s
+    GetSettings,
+    /// Change and save a setting
+    SetSetting(SettingId, u32),
+    /// Stop watching and switch the port back to the console
+    Close,
+}
+
+/// A live reading from one sensor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Reading {
+    /// The socket the sensor is in
+    pub position: u8,
+    /// The sensor's force output, in its own raw units
+    pub value: u16,
+}
+
+/// A message from the jig
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    /// The answer to [`Request::Hello`]
+    Hello {
+        protocol: u16,
+        firmware: String<FIRMWARE_LEN>,
+    },
+    /// The request was carried out, and nothing more will be sent for it
+    Done,
+    /// The request couldn't be carried out, and nothing more will be sent for it
+    Error(CommandError),
+    /// A run started with [`Request::StartRun`] has ended
+    RunFinished(RunSummary),
+    /// A sensor result as it was recorded, while watching results
+    Result(ResultRecord),
+    /// A sensor reading, while watching readings
+    Reading(Reading),
+    /// A record read from the result log
+    LogRecord(ResultRecord),
+    /// The value of a setting, as the console shows it
+    Setting(SettingId, u32),
+}
+
+/// Why a frame couldn't be encoded or decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    /// The message or frame is longer than [`MAX_MESSAGE`] or [`MAX_FRAME`]
+    TooLong,
+    /// The frame isn't valid COBS or fails its CRC
+    Damaged,
+    /// The frame is intact but doesn't hold a message this version knows
+    Unknown,
+}
+
+/// Encode a message into a frame
+///
+/// # Returns
+/// The length of the frame, including the zero byte that ends it
+pub fn encode<T: Serialize + ?Sized>(
+    message: &T,
+    frame: &mut [u8; MAX_FRAME],
+) -> Result<usize, FrameError> {
+    let mut raw = [0; MAX_MESSAGE + CRC_LEN];
+    let len = postcard::to_slice(message, &mut raw[..MAX_MESSAGE])
+        .map_err(|_| FrameError::TooLong)?
+        .len();
+    let crc = crc32(&raw[..len]);
+    raw[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+    // Can't run out of room, as the frame fits the longest message
+    let encoded = cobs::encode(&raw[..len + CRC_LEN], frame);
+    frame[encoded] = 0;
+    Ok(encoded + 1)
+}
+
+/// Decode a frame, without the zero byte that ended it. The frame is decoded in place, so is
+/// left holding the raw message
+pub fn decode<T: DeserializeOwned>(frame: &mut [u8]) -> Result<T, FrameError> {
+    let len = cobs::decode_in_place(frame).map_err(|_| FrameError::Damaged)?;
+    let split = len.checked_sub(CRC_LEN).ok_or(FrameError::Damaged)?;
+    let (message, crc) = frame[..len].split_at(split);
+    if crc32(message).to_le_bytes() != crc {
+        return Err(FrameError::Damaged);
+    }
+    postcard::from_bytes(message).map_err(|_| FrameError::Unknown)
+}
+
+/// Collects received bytes into frames and decodes them
+#[derive(Debug)]
+pub struct FrameReader {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    /// Some of the frame didn't fit
+    too_long: bool,
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameReader {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            too_long: false,
+        }
+    }
+
+    /// Add a received byte
+    ///
+    /// # Returns
+    /// The message once a zero byte ends its frame, otherwise None. Empty frames are skipped,
+    /// so a zero byte can be sent at any time to start afresh
+    pub fn push<T: DeserializeOwned>(&mut self, byte: u8) -> Option<Result<T, FrameError>> {
+        if byte != 0 {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.too_long = true,
+            }
+            return None;
+        }
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.too_long) {
+            Some(Err(FrameError::TooLong))
+        } else if len == 0 {
+            None
+        } else {
+            Some(decode(&mut self.buf[..len]))
+        }
+    }
+}
+
+/// Where the responses to a request go
+#[allow(async_fn_in_trait)]
+pub trait Respond {
+    async fn send(&mut self, response: Response);
+}
+
+/// Carry out a request and send its responses, ending with [`Response::Done`] or
+/// [`Response::Error`]
+pub async fn serve<J: Jig, R: Respond>(request: Request, jig: &mut J, out: &mut R) {
+    match carry_out(request, jig, out).await {
+        Ok(()) => out.send(Response::Done).await,
+        Err(e) => out.send(Response::Error(e)).await,
+    }
+}
+
+async fn carry_out<J: Jig, R: Respond>(
+    request: Request,
+    jig: &mut J,
+    out: &mut R,
+) -> Result<(), CommandError> {
+    match request {
+        Request::Hello => {
+            let mut firmware = String::new();
+            // Keep what fits of a name that is too long
+            for c in J::VERSION.chars() {
+                if firmware.push(c).is_err() {
+                    break;
+                }
+            }
+            out.send(Response::Hello {
+                protocol: PROTOCOL_VERSION,
+                firmware,
+            })
+            .await;
+        }
+        Request::StartRun => {
+            let summary = jig.program().await?;
+            out.send(Response::RunFinished(summary)).await;
+        }
+        Request::WatchResults(on) => jig.set_monitor(on),
+        Request::WatchReadings(on) => jig.watch_readings(on)?,
+        Request::ReadLog => {
+            let mut cursor = J::LogCursor::default();
+            while let Some(record) = jig.next_record(&mut cursor)? {
+                out.send(Response::LogRecord(record)).await;
+            }
+        }
+        Request::GetSettings => {
+            for id in SettingId::ALL {
+                out.send(Response::Setting(id, jig.setting(id))).await;
+            }
+        }
+        Request::SetSetting(id, value) => {
+            // The console checks typed values, so check these the same way
+            if !id.kind().accepts(value) {
+                return Err(CommandError::Rejected);
+            }
+            jig.set_setting(id, value).await?;
+            out.send(Response::Setting(id, jig.setting(id))).await;
+        }
+        Request::Close => {
+            jig.set_monitor(false);
+            // Readings may not be supported, and there is nothing to stop if they aren't
+            let _ = jig.watch_readings(false);
+        }
+    }
+    Ok(())
+}
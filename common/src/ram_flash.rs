@@ -0,0 +1,90 @@
+//! RAM flash module stands in for flash in tests.
+
+use crate::flash::{Flash, FlashError, check_aligned};
+
+/// Flash kept in RAM, so the storage code can be checked in tests. It behaves like NOR flash:
+/// erasing sets a sector to 0xFF and writing can only clear bits. Reads and writes must be
+/// aligned just as they must be on the ESP32-C3.
+pub struct RamFlash<const SECTOR: usize, const SECTORS: usize> {
+    sectors: [[u8; SECTOR]; SECTORS],
+    /// How many times each sector has been erased
+    erases: [u32; SECTORS],
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> Default for RamFlash<SECTOR, SECTORS> {
+    fn default() -> Self {
+        Self {
+            sectors: [[0xff; SECTOR]; SECTORS],
+            erases: [0; SECTORS],
+        }
+    }
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> RamFlash<SECTOR, SECTORS> {
+    /// Create flash that is fully erased
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many times each sector has been erased
+    pub fn erases(&self) -> &[u32; SECTORS] {
+        &self.erases
+    }
+
+    /// The raw contents of the given sector
+    pub fn sector(&self, index: usize) -> &[u8; SECTOR] {
+        &self.sectors[index]
+    }
+
+    /// Overwrite bytes directly, ignoring the rules of flash, to simulate corruption
+    pub fn corrupt(&mut self, offset: u32, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let offset = offset as usize + i;
+            self.sectors[offset / SECTOR][offset % SECTOR] = *byte;
+        }
+    }
+
+    /// Check that `len` bytes from `offset` fall inside the flash
+    fn check(offset: u32, len: usize) -> Result<(), FlashError> {
+        if offset as usize + len > SECTOR * SECTORS {
+            Err(FlashError::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> Flash for RamFlash<SECTOR, SECTORS> {
+    const SECTOR_SIZE: u32 = SECTOR as u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        check_aligned(offset, buf.len())?;
+        Self::check(offset, buf.len())?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let offset = offset as usize + i;
+            *byte = self.sectors[offset / SECTOR][offset % SECTOR];
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_aligned(offset, data.len())?;
+        Self::check(offset, data.len())?;
+        for (i, byte) in data.iter().enumerate() {
+            let offset = offset as usize + i;
+            self.sectors[offset / SECTOR][offset % SECTOR] &= *byte;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, offset: u32) -> Result<(), FlashError> {
+        if !offset.is_multiple_of(Self::SECTOR_SIZE) {
+            return Err(FlashError::NotAligned);
+        }
+        Self::check(offset, SECTOR)?;
+        let index = offset as usize / SECTOR;
+        self.sectors[index] = [0xff; SECTOR];
+        self.erases[index] += 1;
+        Ok(())
+    }
+}
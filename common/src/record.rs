@@ -0,0 +1,132 @@
+//! Record module describes what happened to each sensor the jig has programmed.
+//!
+//! A [`ResultRecord`] is kept in the jig's flash log for every position of every run, and sent
+//! to the host as runs go on and when the log is read.
+
+use core::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// The CSV header matching [`ResultRecord::write_csv`]
+pub const CSV_HEADER: &str = "run,position,old_address,new_address,serial,verdict,error";
+
+/// The outcome for one sensor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Verdict {
+    /// The sensor was programmed and verified
+    Pass = 0,
+    /// The sensor could not be programmed
+    Fail = 1,
+    /// The run stopped while this sensor was being programmed
+    Aborted = 2,
+}
+
+impl Verdict {
+    /// The verdict with the number `value`, as stored in the log
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Verdict::Pass),
+            1 => Some(Verdict::Fail),
+            2 => Some(Verdict::Aborted),
+            _ => None,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Fail => "fail",
+            Verdict::Aborted => "aborted",
+        }
+    }
+}
+
+/// Why a sensor didn't pass. Codes are stored in the log, so never reuse one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// Nothing went wrong
+    NoError = 0,
+    /// No address could be given to the sensor, as the address plan has none for its position
+    /// or continuous mode has handed them all out
+    NoAddress = 1,
+    /// The operator pressed abort
+    AbortRequested = 2,
+    /// The lid was opened
+    InterlockOpen = 3,
+    /// No sensor answered in the socket
+    NotFound = 4,
+    /// The sensor didn't accept its new address
+    ProgramFailed = 5,
+    /// The sensor didn't answer at its new address
+    VerifyFailed = 6,
+}
+
+impl ErrorCode {
+    /// The error with the code `value`, as stored in the log
+    pub const fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(ErrorCode::NoError),
+            1 => Some(ErrorCode::NoAddress),
+            2 => Some(ErrorCode::AbortRequested),
+            3 => Some(ErrorCode::InterlockOpen),
+            4 => Some(ErrorCode::NotFound),
+            5 => Some(ErrorCode::ProgramFailed),
+            6 => Some(ErrorCode::VerifyFailed),
+            _ => None,
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::NoError => "none",
+            ErrorCode::NoAddress => "no_address",
+            ErrorCode::AbortRequested => "abort_requested",
+            ErrorCode::InterlockOpen => "interlock_open",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::ProgramFailed => "program_failed",
+            ErrorCode::VerifyFailed => "verify_failed",
+        }
+    }
+}
+
+/// What happened to the sensor at one position in one run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ResultRecord {
+    /// The run the sensor was programmed in, counting up from 1
+    pub run: u32,
+    /// The socket the sensor was in
+    pub position: u8,
+    /// The address the sensor answered on before programming, if it was found
+    pub old_address: Option<u8>,
+    /// The address the sensor was given, if there was one to give
+    pub new_address: Option<u8>,
+    /// The sensor's serial number, if it could be read
+    pub serial: Option<u32>,
+    pub verdict: Verdict,
+    pub error: ErrorCode,
+}
+
+impl ResultRecord {
+    /// Write the record as one line of CSV, without a line ending, in the columns of
+    /// [`CSV_HEADER`]. Unknown values are left empty.
+    pub fn write_csv<W: Write + ?Sized>(&self, out: &mut W) -> core::fmt::Result {
+        write!(out, "{},{},", self.run, self.position)?;
+        if let Some(address) = self.old_address {
+            write!(out, "0x{:x}", address)?;
+        }
+        out.write_char(',')?;
+        if let Some(address) = self.new_address {
+            write!(out, "0x{:x}", address)?;
+        }
+        out.write_char(',')?;
+        if let Some(serial) = self.serial {
+            write!(out, "{}", serial)?;
+        }
+        write!(out, ",{},{}", self.verdict.name(), self.error.name())
+    }
+}
@@ -0,0 +1,56 @@
+//! Recording module stands in for the LED hardware in tests.
+
+use heapless::Deque;
+
+use crate::leds::{LedBuffer, LedError, LedOutput};
+
+/// Keeps the frames it is asked to show instead of driving any hardware, so animations and the
+/// driver can be checked in tests. Once full, the oldest frames are dropped.
+#[derive(Default)]
+pub struct RecordingOutput<const N: usize> {
+    /// The most recent frames, oldest first
+    frames: Deque<LedBuffer, N>,
+    /// The number of upcoming frames that should fail, to exercise error handling
+    fail: u8,
+}
+
+impl<const N: usize> RecordingOutput<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The recorded frames, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &LedBuffer> {
+        self.frames.iter()
+    }
+
+    /// The most recently shown frame
+    pub fn last(&self) -> Option<&LedBuffer> {
+        self.frames.back()
+    }
+
+    /// Make the next `count` frames fail with [`LedError::Transmission`]
+    pub fn fail_next(&mut self, count: u8) {
+        self.fail = count;
+    }
+
+    /// Forget all recorded frames
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+impl<const N: usize> LedOutput for RecordingOutput<N> {
+    async fn show(&mut self, frame: &LedBuffer) -> Result<(), LedError> {
+        if self.fail > 0 {
+            self.fail -= 1;
+            return Err(LedError::Transmission);
+        }
+        if self.frames.is_full() {
+            self.frames.pop_front();
+        }
+        // Infallible because we made room above
+        let _ = self.frames.push_back(*frame);
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+//! Settings module holds everything about the jig that can be changed without new firmware.
+//!
+//! [`Settings`] are kept in the flash store under [`Key::Settings`] as a fixed little endian
+//! layout of [`ENCODED_LEN`] bytes, starting with the layout version. Missing settings fall back
+//! to [`Settings::default`], which matches how the jig behaved before settings were stored.
+//!
+//! Every change to the layout gets a new [`SETTINGS_VERSION`] and a migration function that
+//! turns the previous version's layout into the new one. Settings from an older firmware are run
+//! through each migration in turn, then saved again in the current layout. From version 2 on
+//! the layout ends with a CRC-32 of everything before it.
+//!
+//! Settings that fail their CRC, or were written by a newer firmware whose layout we can't know,
+//! are not used. The jig runs on the defaults and reports a [`SettingsFault`] so the operator
+//! can be warned. The stored settings are left alone until they are next saved, so going back to
+//! the newer firmware finds them intact.
+//!
+//! Layout history:
+//! - Version 1: brightness, address plan, retry policy, language and timeouts. No CRC.
+//! - Version 2: version 1 with a CRC-32 appended.
+//! - Version 3: version 2 with the programming mode and continuous address plan added before
+//!   the CRC.
+
+use embassy_time::Duration;
+
+use crate::{
+    console::SettingId,
+    crc::crc32,
+    flash::Flash,
+    storage::{Key, KvStore, MAX_VALUE_LEN, StorageError},
+};
+
+/// The layout version written by this firmware
+pub const SETTINGS_VERSION: u16 = 3;
+
+/// The length of encoded settings in bytes
+pub const ENCODED_LEN: usize = 32;
+
+/// The length of the version 1 layout in bytes
+const V1_LEN: usize = 24;
+
+/// The length of the version 2 layout in bytes
+const V2_LEN: usize = 28;
+
+/// The length of the CRC at the end of the layout
+const CRC_LEN: usize = 4;
+
+/// Why stored settings weren't used
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SettingsFault {
+    /// The settings failed their CRC, are the wrong length or hold invalid values
+    Corrupt,
+    /// The settings were written by a newer firmware with the given layout version
+    Newer(u16),
+    /// The settings couldn't be read from flash
+    Unreadable(StorageError),
+}
+
+impl SettingsFault {
+    /// A warning for the OLED, three lines of up to 12 characters
+    pub const fn message(&self) -> &'static str {
+        match self {
+            SettingsFault::Corrupt => "Bad settings\nDefaults\nin use",
+            SettingsFault::Newer(_) => "New settings\nDefaults\nin use",
+            SettingsFault::Unreadable(_) => "Flash error\nDefaults\nin use",
+        }
+    }
+}
+
+/// The language of text on the OLED
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Language {
+    English = 0,
+    German = 1,
+    French = 2,
+}
+
+impl Language {
+    const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Language::English),
+            1 => Some(Language::German),
+            2 => Some(Language::French),
+            _ => None,
+        }
+    }
+}
+
+/// How sensor positions map onto I2C addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AddressPlan {
+    /// The address given to the sensor in position 0
+    pub first: u8,
+    /// How much the address goes up for each position
+    pub step: u8,
+}
+
+impl AddressPlan {
+    /// The lowest and highest addresses a sensor may be given. The rest are reserved by I2C
+    pub const VALID: core::ops::RangeInclusive<u8> = 0x08..=0x77;
+
+    /// The address for the sensor at `pos`, or None if it falls outside [`Self::VALID`]
+    pub fn address(&self, pos: u8) -> Option<u8> {
+        let address = self.first as u16 + pos as u16 * self.step as u16;
+        u8::try_from(address)
+            .ok()
+            .filter(|address| Self::VALID.contains(address))
+    }
+}
+
+/// How sensors are given their addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ProgramMode {
+    /// Every socket of the fixture is programmed, with addresses from the [`AddressPlan`]
+    Fixture = 0,
+    /// Only the first socket is programmed, with the next address from the
+    /// [`ContinuousPlan`], for products with many sensors on one bus
+    Continuous = 1,
+}
+
+impl ProgramMode {
+    const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ProgramMode::Fixture),
+            1 => Some(ProgramMode::Continuous),
+            _ => None,
+        }
+    }
+}
+
+/// What continuous mode does once the last address has been handed out
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum AtLimit {
+    /// Refuse to program any more sensors
+    Stop = 0,
+    /// Start again from the first address
+    Wrap = 1,
+}
+
+impl AtLimit {
+    const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(AtLimit::Stop),
+            1 => Some(AtLimit::Wrap),
+            _ => None,
+        }
+    }
+}
+
+/// The range of addresses handed out one after another in continuous mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ContinuousPlan {
+    /// The first address handed out
+    pub first: u8,
+    /// The last address handed out
+    pub limit: u8,
+    pub at_limit: AtLimit,
+}
+
+/// How hard to try when talking to a sensor fails
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times to try each operation, including the first
+    pub attempts: u8,
+    /// How long to wait between attempts
+    pub backoff: Duration,
+}
+
+/// The longest each step of programming a sensor may take
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Looking for sensors on the bus
+    pub scan: Duration,
+    /// Writing a new address to one sensor
+    pub program: Duration,
+    /// Reading a sensor back at its new address
+    pub verify: Duration,
+}
+
+/// Everything about the jig that can be changed without new firmware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// The layout version these settings were stored as
+    pub version: u16,
+    /// Brightness of the status and socket LEDs
+    pub led_brightness: u8,
+    /// Brightness of the LEDs in torch mode
+    pub torch_brightness: u8,
+    pub address_plan: AddressPlan,
+    pub retry: RetryPolicy,
+    pub language: Language,
+    pub timeouts: Timeouts,
+    pub mode: ProgramMode,
+    pub continuous: ContinuousPlan,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            led_brightness: 10,
+            torch_brightness: 10,
+            address_plan: AddressPlan {
+                first: 0x08,
+                step: 1,
+            },
+            retry: RetryPolicy {
+                attempts: 3,
+                backoff: Duration::from_millis(50),
+            },
+            language: Language::English,
+            timeouts: Timeouts {
+                scan: Duration::from_millis(500),
+                program: Duration::from_millis(1000),
+                verify: Duration::from_millis(500),
+            },
+            mode: ProgramMode::Fixture,
+            continuous: ContinuousPlan {
+                first: *AddressPlan::VALID.start(),
+                limit: *AddressPlan::VALID.end(),
+                at_limit: AtLimit::Stop,
+            },
+        }
+    }
+}
+
+/// Milliseconds of a duration, saturating at u32::MAX
+fn millis(duration: Duration) -> [u8; 4] {
+    u32::try_from(duration.as_millis())
+        .unwrap_or(u32::MAX)
+        .to_le_bytes()
+}
+
+fn duration(bytes: &[u8]) -> Duration {
+    Duration::from_millis(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64)
+}
+
+/// True if the CRC at the end of `bytes` matches the rest
+fn crc_ok(bytes: &[u8]) -> bool {
+    let Some(split) = bytes.len().checked_sub(CRC_LEN) else {
+        return false;
+    };
+    let (data, crc) = bytes.split_at(split);
+    crc32(data).to_le_bytes() == crc
+}
+
+/// Migrate version 1 settings to version 2. The values are laid out the same but version 1 had
+/// no CRC, so there is nothing to check and one is added.
+fn migrate_v1(bytes: &[u8]) -> Result<[u8; V2_LEN], SettingsFault> {
+    if bytes.len() != V1_LEN {
+        return Err(SettingsFault::Corrupt);
+    }
+    let mut migrated = [0; V2_LEN];
+    migrated[0..2].copy_from_slice(&2u16.to_le_bytes());
+    migrated[2..V1_LEN].copy_from_slice(&bytes[2..V1_LEN]);
+    let crc = crc32(&migrated[..V1_LEN]);
+    migrated[V1_LEN..].copy_from_slice(&crc.to_le_bytes());
+    Ok(migrated)
+}
+
+/// Migrate version 2 settings to version 3, adding the default programming mode and
+/// continuous address plan
+fn migrate_v2(bytes: &[u8]) -> Result<[u8; ENCODED_LEN], SettingsFault> {
+    if bytes.len() != V2_LEN || !crc_ok(bytes) {
+        return Err(SettingsFault::Corrupt);
+    }
+    let defaults = Settings::default().to_bytes();
+    let mut migrated = [0; ENCODED_LEN];
+    migrated[0..2].copy_from_slice(&3u16.to_le_bytes());
+    migrated[2..V1_LEN].copy_from_slice(&bytes[2..V1_LEN]);
+    migrated[V1_LEN..V2_LEN].copy_from_slice(&defaults[V1_LEN..V2_LEN]);
+    let crc = crc32(&migrated[..V2_LEN]);
+    migrated[V2_LEN..].copy_from_slice(&crc.to_le_bytes());
+    Ok(migrated)
+}
+
+impl Settings {
+    /// Encode the settings in the current layout
+    pub fn to_bytes(&self) -> [u8; ENCODED_LEN] {
+        let mut bytes = [0; ENCODED_LEN];
+        bytes[0..2].copy_from_slice(&SETTINGS_VERSION.to_le_bytes());
+        bytes[2] = self.led_brightness;
+        bytes[3] = self.torch_brightness;
+        bytes[4] = self.address_plan.first;
+        bytes[5] = self.address_plan.step;
+        bytes[6] = self.retry.attempts;
+        bytes[7] = self.language as u8;
+        bytes[8..12].copy_from_slice(&millis(self.retry.backoff));
+        bytes[12..16].copy_from_slice(&millis(self.timeouts.scan));
+        bytes[16..20].copy_from_slice(&millis(self.timeouts.program));
+        bytes[20..24].copy_from_slice(&millis(self.timeouts.verify));
+        bytes[24] = self.mode as u8;
+        bytes[25] = self.continuous.first;
+        bytes[26] = self.continuous.limit;
+        bytes[27] = self.continuous.at_limit as u8;
+        let crc = crc32(&bytes[..ENCODED_LEN - CRC_LEN]);
+        bytes[ENCODED_LEN - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode settings stored by this or any older firmware, migrating them to the current
+    /// layout.
+    ///
+    /// # Returns
+    /// The settings, with [`Self::version`] set to the layout they were stored as
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SettingsFault> {
+        if bytes.len() < 2 {
+            return Err(SettingsFault::Corrupt);
+        }
+        let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let settings = match version {
+            1 => Self::from_current(&migrate_v2(&migrate_v1(bytes)?)?),
+            2 => Self::from_current(&migrate_v2(bytes)?),
+            SETTINGS_VERSION => Self::from_current(bytes),
+            // Newer layouts also end in a CRC, so corruption isn't mistaken for a newer firmware
+            newer if newer > SETTINGS_VERSION && crc_ok(bytes) => Err(SettingsFault::Newer(newer)),
+            _ => Err(SettingsFault::Corrupt),
+        }?;
+        Ok(Self {
+            version,
+            ..settings
+        })
+    }
+
+    /// Decode settings in the current layout
+    fn from_current(bytes: &[u8]) -> Result<Self, SettingsFault> {
+        if bytes.len() != ENCODED_LEN || !crc_ok(bytes) {
+            return Err(SettingsFault::Corrupt);
+        }
+        let language = Language::from_u8(bytes[7]).ok_or(SettingsFault::Corrupt)?;
+        let mode = ProgramMode::from_u8(bytes[24]).ok_or(SettingsFault::Corrupt)?;
+        let at_limit = AtLimit::from_u8(bytes[27]).ok_or(SettingsFault::Corrupt)?;
+        Ok(Self {
+            version: SETTINGS_VERSION,
+            led_brightness: bytes[2],
+            torch_brightness: bytes[3],
+            address_plan: AddressPlan {
+                first: bytes[4],
+                step: bytes[5],
+            },
+            retry: RetryPolicy {
+                attempts: bytes[6],
+                backoff: duration(&bytes[8..12]),
+            },
+            language,
+            timeouts: Timeouts {
+                scan: duration(&bytes[12..16]),
+                program: duration(&bytes[16..20]),
+                verify: duration(&bytes[20..24]),
+            },
+            mode,
+            continuous: ContinuousPlan {
+                first: bytes[25],
+                limit: bytes[26],
+                at_limit,
+            },
+        })
+    }
+
+    /// The value of one setting as the console shows it. Durations are in milliseconds and
+    /// choices are their number in the layout
+    pub fn value(&self, id: SettingId) -> u32 {
+        match id {
+            SettingId::LedBrightness => self.led_brightness as u32,
+            SettingId::TorchBrightness => self.torch_brightness as u32,
+            SettingId::AddressFirst => self.address_plan.first as u32,
+            SettingId::AddressStep => self.address_plan.step as u32,
+            SettingId::RetryAttempts => self.retry.attempts as u32,
+            SettingId::RetryBackoff => u32::from_le_bytes(millis(self.retry.backoff)),
+            SettingId::Language => self.language as u32,
+            SettingId::ScanTimeout => u32::from_le_bytes(millis(self.timeouts.scan)),
+            SettingId::ProgramTimeout => u32::from_le_bytes(millis(self.timeouts.program)),
+            SettingId::VerifyTimeout => u32::from_le_bytes(millis(self.timeouts.verify)),
+            SettingId::Mode => self.mode as u32,
+            SettingId::ContinuousFirst => self.continuous.first as u32,
+            SettingId::ContinuousLimit => self.continuous.limit as u32,
+            SettingId::AtLimit => self.continuous.at_limit as u32,
+        }
+    }
+
+    /// Change one setting to a value from the console
+    ///
+    /// # Returns
+    /// False, leaving the settings alone, if the value doesn't fit the setting
+    pub fn set_value(&mut self, id: SettingId, value: u32) -> bool {
+        self.try_set_value(id, value).is_some()
+    }
+
+    fn try_set_value(&mut self, id: SettingId, value: u32) -> Option<()> {
+        let byte = u8::try_from(value).ok();
+        let duration = Duration::from_millis(value as u64);
+        match id {
+            SettingId::LedBrightness => self.led_brightness = byte?,
+            SettingId::TorchBrightness => self.torch_brightness = byte?,
+            SettingId::AddressFirst => self.address_plan.first = byte?,
+            SettingId::AddressStep => self.address_plan.step = byte?,
+            SettingId::RetryAttempts => self.retry.attempts = byte?,
+            SettingId::RetryBackoff => self.retry.backoff = duration,
+            SettingId::Language => self.language = Language::from_u8(byte?)?,
+            SettingId::ScanTimeout => self.timeouts.scan = duration,
+            SettingId::ProgramTimeout => self.timeouts.program = duration,
+            SettingId::VerifyTimeout => self.timeouts.verify = duration,
+            SettingId::Mode => self.mode = ProgramMode::from_u8(byte?)?,
+            SettingId::ContinuousFirst => self.continuous.first = byte?,
+            SettingId::ContinuousLimit => self.continuous.limit = byte?,
+            SettingId::AtLimit => self.continuous.at_limit = AtLimit::from_u8(byte?)?,
+        }
+        Some(())
+    }
+
+    /// Load the settings from the store. Settings from an older firmware are saved again in
+    /// the current layout.
+    ///
+    /// # Returns
+    /// The stored settings, the defaults if none have been stored, or why the stored settings
+    /// can't be used. The caller should carry on with the defaults and warn the operator
+    pub fn load<F: Flash>(store: &mut KvStore<F>) -> Result<Self, SettingsFault> {
+        let mut bytes = [0; MAX_VALUE_LEN];
+        let len = match store.read(Key::Settings, &mut bytes) {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(Self::default()),
+            Err(e) => return Err(SettingsFault::Unreadable(e)),
+        };
+        let settings = Self::from_bytes(&bytes[..len])?;
+        if settings.version < SETTINGS_VERSION {
+            #[cfg(feature = "defmt")]
+            defmt::info!(
+                "SETTINGS: Migrated settings from version {}",
+                settings.version
+            );
+            if let Err(_e) = settings.save(store) {
+                #[cfg(feature = "defmt")]
+                defmt::warn!("SETTINGS: Failed to save migrated settings: {}", _e);
+            }
+        }
+        Ok(Self {
+            version: SETTINGS_VERSION,
+            ..settings
+        })
+    }
+
+    /// Save the settings to the store
+    pub fn save<F: Flash>(&self, store: &mut KvStore<F>) -> Result<(), StorageError> {
+        store.write(Key::Settings, &self.to_bytes())
+    }
+}
@@ -0,0 +1,372 @@
+//! Storage module keeps small values in flash so they survive a reset.
+//!
+//! [`KvStore`] is a log-structured key/value store spread over the sectors of one flash
+//! partition. Only one sector is active at a time. Each write appends a record for its key to
+//! the active sector and the newest record for a key wins. When the active sector is full, the
+//! newest record of every key is copied to the next sector, which then becomes active. The
+//! sectors are used in turn, so erases are spread evenly over the partition.
+//!
+//! Sector layout, all little endian:
+//! - 8 byte header: magic, then a sequence number that increases each time a sector becomes
+//!   active. The valid sector with the highest sequence is the active one.
+//! - Records, each a 4 byte header of key and length followed by the value padded to 4 bytes.
+//!   The first record with an erased key marks the end of the data.
+//!
+//! A record's value is written before its header, and a sector's records before its sector
+//! header, so losing power part way through a write leaves the previous value in place.
+
+use heapless::Vec;
+
+use crate::flash::{Flash, FlashError, WRITE_SIZE};
+
+/// The longest value that can be stored under one key
+pub const MAX_VALUE_LEN: usize = 128;
+/// The most keys that can be stored at once
+const MAX_KEYS: usize = 16;
+
+/// Marks a sector that holds records
+const SECTOR_MAGIC: u32 = 0x4b56_4a53;
+const SECTOR_HEADER_LEN: u32 = 8;
+const RECORD_HEADER_LEN: u32 = 4;
+/// The key of a record header that has not been written
+const ERASED_KEY: u16 = 0xffff;
+
+/// The values kept in the store
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u16)]
+pub enum Key {
+    /// The jig settings, see [`crate::settings::Settings`]
+    Settings = 1,
+    /// Lifetime statistics of the jig
+    Counters = 2,
+    /// The last panic until it is cleared
+    LastPanic = 3,
+    /// The next address for continuous mode
+    NextAddress = 4,
+}
+
+/// Why a store operation failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StorageError {
+    /// The flash failed
+    Flash(FlashError),
+    /// The value is longer than [`MAX_VALUE_LEN`]
+    TooLarge,
+    /// The buffer is too short for the stored value
+    BufferTooSmall,
+    /// The newest values of every key don't fit in one sector, or there are too many keys
+    Full,
+}
+
+impl From<FlashError> for StorageError {
+    fn from(error: FlashError) -> Self {
+        StorageError::Flash(error)
+    }
+}
+
+/// A record found while walking a sector
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    key: u16,
+    /// Offset of the value within the sector
+    offset: u32,
+    len: u16,
+}
+
+/// Rounds a length up to a whole number of flash writes
+const fn padded(len: u32) -> u32 {
+    len.div_ceil(WRITE_SIZE) * WRITE_SIZE
+}
+
+/// A wear-levelled key/value store in a flash partition
+pub struct KvStore<F: Flash> {
+    flash: F,
+    /// Offset of the first sector of the partition
+    base: u32,
+    /// The number of sectors in the partition
+    sectors: u32,
+    /// The index of the active sector
+    active: u32,
+    /// The sequence number of the active sector
+    sequence: u32,
+    /// Offset within the active sector of the first free byte
+    end: u32,
+}
+
+impl<F: Flash> KvStore<F> {
+    /// Open the store in a partition, formatting it if it holds no valid sectors.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash the partition is on
+    /// * `base` - Offset of the partition, aligned to a sector
+    /// * `sectors` - The number of sectors in the partition, at least two
+    pub fn open(flash: F, base: u32, sectors: u32) -> Result<Self, StorageError> {
+        // Compacting copies the records to another sector, so there must be one to copy to
+        if sectors < 2 {
+            return Err(FlashError::TooFewSectors.into());
+        }
+        let mut store = Self {
+            flash,
+            base,
+            sectors,
+            active: 0,
+            sequence: 0,
+            end: SECTOR_HEADER_LEN,
+        };
+
+        let mut newest = None;
+        for index in 0..sectors {
+            if let Some(sequence) = store.sector_sequence(index)?
+                && newest.is_none_or(|(_, newest)| sequence > newest)
+            {
+                newest = Some((index, sequence));
+            }
+        }
+
+        match newest {
+            Some((index, sequence)) => {
+                store.active = index;
+                store.sequence = sequence;
+                store.end = store.find_end()?;
+                // Anything after the last record should be erased. If it isn't, a write was cut
+                // short, so move the good records to a clean sector before appending any more
+                if !store.is_erased_from(store.end)? {
+                    store.compact(None)?;
+                }
+            }
+            None => {
+                store.flash.erase(base)?;
+                store.write_sector_header(0, 0)?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Read the newest value of `key` into `buf`.
+    ///
+    /// # Returns
+    /// The length of the value, or None if the key has never been written
+    pub fn read(&mut self, key: Key, buf: &mut [u8]) -> Result<Option<usize>, StorageError> {
+        let Some(record) = self.find(self.active, key as u16)? else {
+            return Ok(None);
+        };
+        let len = record.len as usize;
+        if len > buf.len() {
+            return Err(StorageError::BufferTooSmall);
+        }
+        let mut value = [0; MAX_VALUE_LEN];
+        self.read_value(self.active, &record, &mut value)?;
+        buf[..len].copy_from_slice(&value[..len]);
+        Ok(Some(len))
+    }
+
+    /// Store a new value for `key`. Nothing is written if the value has not changed.
+    pub fn write(&mut self, key: Key, value: &[u8]) -> Result<(), StorageError> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(StorageError::TooLarge);
+        }
+        let mut current = [0; MAX_VALUE_LEN];
+        if let Some(len) = self.read(key, &mut current)?
+            && current[..len] == *value
+        {
+            return Ok(());
+        }
+
+        if self.end + RECORD_HEADER_LEN + padded(value.len() as u32) > F::SECTOR_SIZE {
+            self.compact(Some((key as u16, value)))
+        } else {
+            self.append(key as u16, value)
+        }
+    }
+
+    /// The flash the store is kept in
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// Close the store, giving back the flash
+    pub fn into_flash(self) -> F {
+        self.flash
+    }
+
+    /// Offset of a sector in flash
+    fn sector_offset(&self, index: u32) -> u32 {
+        self.base + index * F::SECTOR_SIZE
+    }
+
+    /// The sequence number of a sector, or None if it doesn't hold records
+    fn sector_sequence(&mut self, index: u32) -> Result<Option<u32>, StorageError> {
+        let mut header = [0; SECTOR_HEADER_LEN as usize];
+        self.flash.read(self.sector_offset(index), &mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        Ok((magic == SECTOR_MAGIC).then_some(sequence))
+    }
+
+    fn write_sector_header(&mut self, index: u32, sequence: u32) -> Result<(), StorageError> {
+        let mut header = [0; SECTOR_HEADER_LEN as usize];
+        header[..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        self.flash.write(self.sector_offset(index), &header)?;
+        Ok(())
+    }
+
+    /// Read the record starting at `offset` in a sector, or None at the end of the records
+    fn record_at(&mut self, index: u32, offset: u32) -> Result<Option<Record>, StorageError> {
+        if offset + RECORD_HEADER_LEN > F::SECTOR_SIZE {
+            return Ok(None);
+        }
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        self.flash
+            .read(self.sector_offset(index) + offset, &mut header)?;
+        let key = u16::from_le_bytes([header[0], header[1]]);
+        let len = u16::from_le_bytes([header[2], header[3]]);
+        let offset = offset + RECORD_HEADER_LEN;
+        // A header cut short by a power loss could claim any length
+        if key == ERASED_KEY || len as usize > MAX_VALUE_LEN || offset + len as u32 > F::SECTOR_SIZE
+        {
+            return Ok(None);
+        }
+        Ok(Some(Record { key, offset, len }))
+    }
+
+    /// Read the value of a record in a sector. Flash reads whole words, so the padding after
+    /// the value is read too
+    ///
+    /// # Returns
+    /// The length of the value
+    fn read_value(
+        &mut self,
+        index: u32,
+        record: &Record,
+        value: &mut [u8; MAX_VALUE_LEN],
+    ) -> Result<usize, StorageError> {
+        let offset = self.sector_offset(index) + record.offset;
+        let padded = padded(record.len as u32) as usize;
+        self.flash.read(offset, &mut value[..padded])?;
+        Ok(record.len as usize)
+    }
+
+    /// The offset after a record
+    fn next_offset(record: &Record) -> u32 {
+        record.offset + padded(record.len as u32)
+    }
+
+    /// Find the newest record of `key` in a sector
+    fn find(&mut self, index: u32, key: u16) -> Result<Option<Record>, StorageError> {
+        let mut found = None;
+        let mut offset = SECTOR_HEADER_LEN;
+        while let Some(record) = self.record_at(index, offset)? {
+            if record.key == key {
+                found = Some(record);
+            }
+            offset = Self::next_offset(&record);
+        }
+        Ok(found)
+    }
+
+    /// Find the offset after the last record in the active sector
+    fn find_end(&mut self) -> Result<u32, StorageError> {
+        let mut offset = SECTOR_HEADER_LEN;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            offset = Self::next_offset(&record);
+        }
+        Ok(offset)
+    }
+
+    /// True if the active sector is erased from `offset` to its end
+    fn is_erased_from(&mut self, offset: u32) -> Result<bool, StorageError> {
+        let start = self.sector_offset(self.active);
+        let mut chunk = [0; 32];
+        let mut offset = offset;
+        while offset < F::SECTOR_SIZE {
+            let len = (F::SECTOR_SIZE - offset).min(chunk.len() as u32) as usize;
+            self.flash.read(start + offset, &mut chunk[..len])?;
+            if chunk[..len].iter().any(|byte| *byte != 0xff) {
+                return Ok(false);
+            }
+            offset += len as u32;
+        }
+        Ok(true)
+    }
+
+    /// Write a record at `offset` in a sector, value first so a cut short write has no header
+    fn write_record(
+        &mut self,
+        index: u32,
+        offset: u32,
+        key: u16,
+        value: &[u8],
+    ) -> Result<u32, StorageError> {
+        let start = self.sector_offset(index) + offset;
+        let whole = value.len() - value.len() % WRITE_SIZE as usize;
+        if whole > 0 {
+            self.flash
+                .write(start + RECORD_HEADER_LEN, &value[..whole])?;
+        }
+        if whole < value.len() {
+            let mut tail = [0xff; WRITE_SIZE as usize];
+            tail[..value.len() - whole].copy_from_slice(&value[whole..]);
+            self.flash
+                .write(start + RECORD_HEADER_LEN + whole as u32, &tail)?;
+        }
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        header[..2].copy_from_slice(&key.to_le_bytes());
+        header[2..].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        self.flash.write(start, &header)?;
+        Ok(offset + RECORD_HEADER_LEN + padded(value.len() as u32))
+    }
+
+    /// Append a record to the active sector
+    fn append(&mut self, key: u16, value: &[u8]) -> Result<(), StorageError> {
+        self.end = self.write_record(self.active, self.end, key, value)?;
+        Ok(())
+    }
+
+    /// Copy the newest record of every key to the next sector and make it active, optionally
+    /// replacing the value of one key on the way
+    fn compact(&mut self, replace: Option<(u16, &[u8])>) -> Result<(), StorageError> {
+        let mut keys: Vec<u16, MAX_KEYS> = Vec::new();
+        let mut offset = SECTOR_HEADER_LEN;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            if !keys.contains(&record.key) {
+                keys.push(record.key).map_err(|_| StorageError::Full)?;
+            }
+            offset = Self::next_offset(&record);
+        }
+        if let Some((key, _)) = replace
+            && !keys.contains(&key)
+        {
+            keys.push(key).map_err(|_| StorageError::Full)?;
+        }
+
+        let next = (self.active + 1) % self.sectors;
+        self.flash.erase(self.sector_offset(next))?;
+        let mut end = SECTOR_HEADER_LEN;
+        let mut value = [0; MAX_VALUE_LEN];
+        for key in keys {
+            let len = match replace {
+                Some((replace_key, new)) if replace_key == key => {
+                    value[..new.len()].copy_from_slice(new);
+                    new.len()
+                }
+                _ => match self.find(self.active, key)? {
+                    Some(record) => self.read_value(self.active, &record, &mut value)?,
+                    None => continue,
+                },
+            };
+            if end + RECORD_HEADER_LEN + padded(len as u32) > F::SECTOR_SIZE {
+                return Err(StorageError::Full);
+            }
+            end = self.write_record(next, end, key, &value[..len])?;
+        }
+        // Only now does the new sector take over from the old one
+        self.write_sector_header(next, self.sequence.wrapping_add(1))?;
+        self.active = next;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.end = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+//! Tests for the console parser and command dispatch
+//!
+//! You can run this using `cargo test --test console` from the `common` directory.
+
+use embassy_futures::block_on;
+use jig_common::{
+    console::{
+        Command, CommandError, Jig, LedHealth, LineBuffer, MAX_LINE, ParseError, Reply, RunSummary,
+        SettingId, dispatch, parse,
+    },
+    counters::Counters,
+    record::{CSV_HEADER, ErrorCode, ResultRecord, Verdict},
+};
+
+/// A jig with two sockets that keeps settings in memory
+struct MockJig {
+    settings: [u32; SettingId::ALL.len()],
+    monitor: bool,
+    lid_open: bool,
+    runs: u32,
+    log: Vec<ResultRecord>,
+    /// The next continuous address, or None once they are all handed out
+    next_address: Option<u8>,
+}
+
+fn record(position: u8, address: u8, verdict: Verdict, error: ErrorCode) -> ResultRecord {
+    ResultRecord {
+        run: 1,
+        position,
+        old_address: None,
+        new_address: Some(address),
+        serial: None,
+        verdict,
+        error,
+    }
+}
+
+impl MockJig {
+    fn new() -> Self {
+        Self {
+            settings: [0; SettingId::ALL.len()],
+            monitor: false,
+            lid_open: false,
+            runs: 0,
+            next_address: Some(0x08),
+            log: vec![
+                record(0, 0x08, Verdict::Pass, ErrorCode::NoError),
+                record(1, 0x09, Verdict::Fail, ErrorCode::VerifyFailed),
+            ],
+        }
+    }
+
+    fn index(id: SettingId) -> usize {
+        SettingId::ALL
+            .iter()
+            .position(|other| *other == id)
+            .unwrap()
+    }
+}
+
+impl Jig for MockJig {
+    const VERSION: &'static str = "mock-jig 1.2.3";
+    type LogCursor = usize;
+
+    fn sockets(&self) -> u8 {
+        2
+    }
+
+    async fn scan(&mut self, position: u8) -> Result<Option<u8>, CommandError> {
+        Ok((position == 0).then_some(0x04))
+    }
+
+    async fn program(&mut self) -> Result<RunSummary, CommandError> {
+        if self.lid_open {
+            return Err(CommandError::InterlockOpen);
+        }
+        self.runs += 1;
+        Ok(RunSummary {
+            run: self.runs,
+            attempted: 2,
+            failed: 1,
+        })
+    }
+
+    async fn verify(&mut self, position: u8) -> Result<bool, CommandError> {
+        Ok(position == 0)
+    }
+
+    async fn reset_default(&mut self, _position: u8) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn set_monitor(&mut self, on: bool) {
+        self.monitor = on;
+    }
+
+    fn watch_readings(&mut self, _on: bool) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn setting(&self, id: SettingId) -> u32 {
+        self.settings[Self::index(id)]
+    }
+
+    async fn set_setting(&mut self, id: SettingId, value: u32) -> Result<(), CommandError> {
+        self.settings[Self::index(id)] = value;
+        Ok(())
+    }
+
+    fn next_record(&mut self, cursor: &mut usize) -> Result<Option<ResultRecord>, CommandError> {
+        let record = self.log.get(*cursor).copied();
+        *cursor += 1;
+        Ok(record)
+    }
+
+    fn led_health(&self) -> LedHealth {
+        LedHealth {
+            frames: 1200,
+            retries: 3,
+            reinits: 1,
+            failures: 0,
+            last_error: Some("transmission failed"),
+        }
+    }
+
+    fn session_counters(&self) -> Counters {
+        let mut counters = Counters {
+            boots: 1,
+            runs: self.runs,
+            ..Counters::default()
+        };
+        for record in &self.log {
+            counters.record(record);
+        }
+        counters
+    }
+
+    // As if the flash couldn't be read at boot
+    fn lifetime_counters(&self) -> Option<Counters> {
+        None
+    }
+
+    fn next_address(&self) -> Result<Option<u8>, CommandError> {
+        Ok(self.next_address)
+    }
+
+    async fn restart_addresses(&mut self, address: u8) -> Result<(), CommandError> {
+        self.next_address = Some(address);
+        Ok(())
+    }
+}
+
+/// Keeps every line of a reply
+#[derive(Default)]
+struct Lines(Vec<String>);
+
+impl Reply for Lines {
+    async fn line(&mut self, text: &str) {
+        self.0.push(text.to_string());
+    }
+}
+
+fn run(jig: &mut MockJig, line: &str) -> Vec<String> {
+    let mut reply = Lines::default();
+    block_on(dispatch(parse(line).unwrap(), jig, &mut reply));
+    reply.0
+}
+
+#[test]
+fn lines_end_at_either_line_ending() {
+    let mut buffer = LineBuffer::new();
+    let mut lines = Vec::new();
+    for &byte in b"scan\r\nversion\n\rhelp\r" {
+        if let Some(line) = buffer.push(byte) {
+            lines.push(line.unwrap().to_string());
+        }
+    }
+    assert_eq!(lines, ["scan", "version", "help"]);
+}
+
+#[test]
+fn backspace_and_control_characters_are_handled() {
+    let mut buffer = LineBuffer::new();
+    let mut last = None;
+    for &byte in b"gte\x08\x08et\x7f\x1b\xc3\xa9t\r" {
+        last = buffer.push(byte);
+    }
+    assert_eq!(last.unwrap().unwrap().as_str(), "get");
+    // Backspace on an empty line does nothing
+    assert_eq!(buffer.push(0x08), None);
+}
+
+#[test]
+fn long_lines_are_rejected_whole() {
+    let mut buffer = LineBuffer::new();
+    for _ in 0..MAX_LINE + 5 {
+        assert_eq!(buffer.push(b'x'), None);
+    }
+    assert_eq!(buffer.push(b'\n'), Some(Err(ParseError::LineTooLong)));
+    // The next line starts afresh
+    for &byte in b"scan" {
+        buffer.push(byte);
+    }
+    assert_eq!(buffer.push(b'\n').unwrap().unwrap().as_str(), "scan");
+}
+
+#[test]
+fn commands_are_parsed() {
+    assert_eq!(parse("help"), Ok(Command::Help));
+    assert_eq!(parse("  reset-default "), Ok(Command::ResetDefault));
+    assert_eq!(parse("monitor"), Ok(Command::Monitor(true)));
+    assert_eq!(parse("monitor off"), Ok(Command::Monitor(false)));
+    assert_eq!(parse("log dump"), Ok(Command::LogDump));
+    assert_eq!(parse("diag"), Ok(Command::Diag));
+    assert_eq!(parse("stats"), Ok(Command::Stats));
+    assert_eq!(parse("next-address"), Ok(Command::NextAddress(None)));
+    assert_eq!(
+        parse("next-address 0x20"),
+        Ok(Command::NextAddress(Some(0x20)))
+    );
+    assert_eq!(parse("get"), Ok(Command::Get(None)));
+    assert_eq!(parse("get mode"), Ok(Command::Get(Some(SettingId::Mode))));
+    assert_eq!(
+        parse("set address_first 0x20"),
+        Ok(Command::Set(SettingId::AddressFirst, 0x20))
+    );
+    assert_eq!(
+        parse("set scan_timeout 750ms"),
+        Ok(Command::Set(SettingId::ScanTimeout, 750))
+    );
+    assert_eq!(
+        parse("set at_limit wrap"),
+        Ok(Command::Set(SettingId::AtLimit, 1))
+    );
+}
+
+#[test]
+fn bad_lines_are_explained() {
+    assert_eq!(parse("flash"), Err(ParseError::UnknownCommand));
+    assert_eq!(parse("log"), Err(ParseError::MissingArgument));
+    assert_eq!(parse("log tail"), Err(ParseError::UnknownCommand));
+    assert_eq!(parse("scan now"), Err(ParseError::UnexpectedArgument));
+    assert_eq!(parse("monitor maybe"), Err(ParseError::BadValue));
+    assert_eq!(parse("get colour"), Err(ParseError::UnknownSetting));
+    assert_eq!(parse("set mode"), Err(ParseError::MissingArgument));
+    assert_eq!(parse("set mode turbo"), Err(ParseError::BadValue));
+    assert_eq!(parse("set address_first 0x80"), Err(ParseError::BadValue));
+    assert_eq!(parse("next-address 0x80"), Err(ParseError::BadValue));
+    assert_eq!(parse("set retry_attempts 0"), Err(ParseError::BadValue));
+    assert_eq!(parse("set led_brightness -1"), Err(ParseError::BadValue));
+    assert_eq!(
+        parse("set led_brightness 10 20"),
+        Err(ParseError::UnexpectedArgument)
+    );
+}
+
+#[test]
+fn settings_are_shown_as_they_are_typed() {
+    for (id, value) in [
+        (SettingId::LedBrightness, 200),
+        (SettingId::AddressFirst, 0x2a),
+        (SettingId::VerifyTimeout, 1500),
+        (SettingId::Language, 2),
+    ] {
+        let mut shown = String::new();
+        id.kind().write(value, &mut shown).unwrap();
+        assert_eq!(id.kind().parse(&shown), Ok(value));
+    }
+}
+
+#[test]
+fn commands_reply_then_end_with_ok_or_error() {
+    let mut jig = MockJig::new();
+    assert_eq!(run(&mut jig, "version"), ["mock-jig 1.2.3", "ok"]);
+    assert_eq!(
+        run(&mut jig, "scan"),
+        ["socket 0: 0x04", "socket 1: empty", "ok"]
+    );
+    assert_eq!(
+        run(&mut jig, "verify"),
+        ["socket 0: ok", "socket 1: no answer", "ok"]
+    );
+    assert_eq!(
+        run(&mut jig, "reset-default"),
+        ["error: not supported by this firmware"]
+    );
+    assert_eq!(
+        run(&mut jig, "program"),
+        ["run 1: 1 programmed, 1 failed", "ok"]
+    );
+    jig.lid_open = true;
+    assert_eq!(run(&mut jig, "program"), ["error: lid is open"]);
+    assert_eq!(jig.runs, 1);
+    assert_eq!(
+        run(&mut jig, "diag"),
+        [
+            "led: 1200 frames, 3 retries, 1 reinits, 0 failures",
+            "led last error: transmission failed",
+            "ok"
+        ]
+    );
+}
+
+#[test]
+fn stats_show_failures_by_error_and_socket() {
+    let mut jig = MockJig::new();
+    assert_eq!(
+        run(&mut jig, "program"),
+        ["run 1: 1 programmed, 1 failed", "ok"]
+    );
+    assert_eq!(
+        run(&mut jig, "stats"),
+        [
+            "session: 1 boots, 1 runs, 2 attempted, 1 programmed, 0 hours",
+            "session yield: 50.0%",
+            "session verify_failed: 1",
+            "session by socket: 0 1 0 0 0 0 0 0",
+            "lifetime: unreadable",
+            "ok"
+        ]
+    );
+}
+
+#[test]
+fn next_address_is_shown_and_restarted() {
+    let mut jig = MockJig::new();
+    assert_eq!(run(&mut jig, "next-address"), ["next address: 0x08", "ok"]);
+    assert_eq!(
+        run(&mut jig, "next-address 0x30"),
+        ["next address: 0x30", "ok"]
+    );
+    jig.next_address = None;
+    assert_eq!(
+        run(&mut jig, "next-address"),
+        ["next address: none, all handed out", "ok"]
+    );
+}
+
+#[test]
+fn settings_are_changed_and_listed() {
+    let mut jig = MockJig::new();
+    assert_eq!(
+        run(&mut jig, "set mode continuous"),
+        ["mode = continuous", "ok"]
+    );
+    assert_eq!(jig.setting(SettingId::Mode), 1);
+    assert_eq!(
+        run(&mut jig, "get retry_backoff"),
+        ["retry_backoff = 0ms", "ok"]
+    );
+    let all = run(&mut jig, "get");
+    assert_eq!(all.len(), SettingId::ALL.len() + 1);
+    assert_eq!(all[0], "led_brightness = 0");
+    assert!(all.contains(&"mode = continuous".to_string()));
+}
+
+#[test]
+fn log_dump_and_monitor() {
+    let mut jig = MockJig::new();
+    assert_eq!(
+        run(&mut jig, "log dump"),
+        [
+            CSV_HEADER,
+            "1,0,,0x8,,pass,none",
+            "1,1,,0x9,,fail,verify_failed",
+            "ok"
+        ]
+    );
+    assert_eq!(run(&mut jig, "monitor"), ["ok"]);
+    assert!(jig.monitor);
+    assert_eq!(run(&mut jig, "monitor off"), ["ok"]);
+    assert!(!jig.monitor);
+}
@@ -0,0 +1,98 @@
+//! Tests for the lifetime counters and yield statistics
+//!
+//! You can run this using `cargo test --test counters` from the `common` directory.
+
+use jig_common::{
+    counters::{Counters, ENCODED_LEN},
+    ram_flash::RamFlash,
+    record::{ErrorCode, ResultRecord, Verdict},
+    storage::{Key, KvStore},
+};
+
+type TestFlash = RamFlash<256, 4>;
+
+fn result(position: u8, verdict: Verdict, error: ErrorCode) -> ResultRecord {
+    ResultRecord {
+        run: 1,
+        position,
+        old_address: None,
+        new_address: Some(0x08 + position),
+        serial: None,
+        verdict,
+        error,
+    }
+}
+
+#[test]
+fn results_are_counted_by_type_and_socket() {
+    let mut counters = Counters::default();
+    counters.record(&result(0, Verdict::Pass, ErrorCode::NoError));
+    counters.record(&result(1, Verdict::Fail, ErrorCode::NoAddress));
+    counters.record(&result(1, Verdict::Fail, ErrorCode::NoAddress));
+    counters.record(&result(2, Verdict::Aborted, ErrorCode::AbortRequested));
+    assert_eq!(counters.attempted, 3);
+    assert_eq!(counters.programmed, 1);
+    assert_eq!(counters.failures(ErrorCode::NoAddress), 2);
+    assert_eq!(counters.failures(ErrorCode::AbortRequested), 1);
+    assert_eq!(counters.failures(ErrorCode::NoError), 0);
+    assert_eq!(counters.socket_failures[..3], [0, 2, 1]);
+}
+
+#[test]
+fn yield_leaves_out_aborted_sensors() {
+    let mut counters = Counters::default();
+    assert_eq!(counters.first_pass_yield(), None);
+    for _ in 0..2 {
+        counters.record(&result(0, Verdict::Pass, ErrorCode::NoError));
+    }
+    counters.record(&result(1, Verdict::Fail, ErrorCode::VerifyFailed));
+    counters.record(&result(2, Verdict::Aborted, ErrorCode::InterlockOpen));
+    assert_eq!(counters.first_pass_yield(), Some(666));
+}
+
+#[test]
+fn counters_survive_reopening() {
+    let mut store = KvStore::open(TestFlash::new(), 0, 4).unwrap();
+    assert_eq!(Counters::load(&mut store), Ok(Counters::default()));
+    let mut counters = Counters {
+        boots: 3,
+        runs: 12,
+        uptime_hours: 40,
+        ..Counters::default()
+    };
+    counters.record(&result(7, Verdict::Fail, ErrorCode::ProgramFailed));
+    counters.save(&mut store).unwrap();
+
+    let mut store = KvStore::open(store.into_flash(), 0, 4).unwrap();
+    assert_eq!(Counters::load(&mut store), Ok(counters));
+}
+
+#[test]
+fn corrupt_counters_start_from_zero() {
+    let mut bytes = Counters {
+        runs: 5,
+        ..Counters::default()
+    }
+    .to_bytes();
+    bytes[8] ^= 0x01;
+    assert_eq!(Counters::from_bytes(&bytes), None);
+    assert_eq!(Counters::from_bytes(&bytes[..ENCODED_LEN - 4]), None);
+
+    let mut store = KvStore::open(TestFlash::new(), 0, 4).unwrap();
+    store.write(Key::Counters, &bytes).unwrap();
+    assert_eq!(Counters::load(&mut store), Ok(Counters::default()));
+}
+
+#[test]
+fn unchanged_counters_are_not_written_again() {
+    let mut store = KvStore::open(TestFlash::new(), 0, 4).unwrap();
+    let counters = Counters {
+        boots: 1,
+        ..Counters::default()
+    };
+    // Far more saves than fit in the store without the duplicates being skipped
+    for _ in 0..100 {
+        counters.save(&mut store).unwrap();
+    }
+    assert_eq!(store.flash().erases(), &[1, 0, 0, 0]);
+}
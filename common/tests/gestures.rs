@@ -0,0 +1,167 @@
+//! Tests for recognising button gestures from press and release times
+//!
+//! You can run this using `cargo test --test gestures` from the `common` directory.
+
+use embassy_time::Instant;
+use jig_common::gestures::{ButtonEvent, ButtonId, Gesture, GestureRecognizer, GestureTimings};
+
+const B0: ButtonId = ButtonId::Button0;
+const B1: ButtonId = ButtonId::Button1;
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+#[test]
+fn click_is_reported_after_double_click_time() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    assert_eq!(gestures.edge(B0, true, at(0)), None);
+    assert_eq!(gestures.edge(B0, false, at(100)), None);
+    assert_eq!(gestures.deadline(), Some(at(400)));
+    assert_eq!(gestures.poll(at(399)), None);
+    assert_eq!(
+        gestures.poll(at(400)),
+        Some(ButtonEvent::Single(B0, Gesture::Click))
+    );
+    assert_eq!(gestures.poll(at(1000)), None);
+    assert_eq!(gestures.deadline(), None);
+}
+
+#[test]
+fn double_click() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    assert_eq!(gestures.edge(B1, true, at(0)), None);
+    assert_eq!(gestures.edge(B1, false, at(100)), None);
+    assert_eq!(gestures.edge(B1, true, at(250)), None);
+    assert_eq!(
+        gestures.edge(B1, false, at(350)),
+        Some(ButtonEvent::Single(B1, Gesture::DoubleClick))
+    );
+    assert_eq!(gestures.poll(at(2000)), None);
+}
+
+#[test]
+fn slow_second_press_is_two_clicks() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    gestures.edge(B0, true, at(0));
+    gestures.edge(B0, false, at(100));
+    assert_eq!(
+        gestures.poll(at(450)),
+        Some(ButtonEvent::Single(B0, Gesture::Click))
+    );
+    gestures.edge(B0, true, at(500));
+    gestures.edge(B0, false, at(600));
+    assert_eq!(
+        gestures.poll(at(900)),
+        Some(ButtonEvent::Single(B0, Gesture::Click))
+    );
+}
+
+#[test]
+fn long_press_repeats_until_released() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    gestures.edge(B0, true, at(0));
+    assert_eq!(gestures.poll(at(499)), None);
+    assert_eq!(
+        gestures.poll(at(500)),
+        Some(ButtonEvent::Single(B0, Gesture::LongPress))
+    );
+    assert_eq!(gestures.deadline(), Some(at(1000)));
+    assert_eq!(
+        gestures.poll(at(1000)),
+        Some(ButtonEvent::Single(B0, Gesture::Repeat))
+    );
+    assert_eq!(
+        gestures.poll(at(1250)),
+        Some(ButtonEvent::Single(B0, Gesture::Repeat))
+    );
+    // Releasing after a long press is not also a click
+    assert_eq!(gestures.edge(B0, false, at(1300)), None);
+    assert_eq!(gestures.poll(at(5000)), None);
+}
+
+#[test]
+fn late_poll_catches_up_one_event_at_a_time() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    gestures.edge(B1, true, at(0));
+    assert_eq!(
+        gestures.poll(at(1100)),
+        Some(ButtonEvent::Single(B1, Gesture::LongPress))
+    );
+    assert_eq!(
+        gestures.poll(at(1100)),
+        Some(ButtonEvent::Single(B1, Gesture::Repeat))
+    );
+    assert_eq!(gestures.poll(at(1100)), None);
+}
+
+#[test]
+fn chord_click() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    assert_eq!(gestures.edge(B0, true, at(0)), None);
+    assert_eq!(gestures.edge(B1, true, at(80)), None);
+    assert_eq!(
+        gestures.edge(B0, false, at(200)),
+        Some(ButtonEvent::Chord(Gesture::Click))
+    );
+    assert_eq!(gestures.edge(B1, false, at(220)), None);
+    // Neither button reports a gesture of its own
+    assert_eq!(gestures.poll(at(2000)), None);
+}
+
+#[test]
+fn chord_long_press() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    gestures.edge(B1, true, at(0));
+    gestures.edge(B0, true, at(100));
+    assert_eq!(
+        gestures.poll(at(500)),
+        Some(ButtonEvent::Chord(Gesture::LongPress))
+    );
+    assert_eq!(
+        gestures.poll(at(1000)),
+        Some(ButtonEvent::Chord(Gesture::Repeat))
+    );
+    assert_eq!(gestures.edge(B1, false, at(1100)), None);
+    // The chord stops repeating as soon as either button is let go
+    assert_eq!(gestures.poll(at(1250)), None);
+    assert_eq!(gestures.edge(B0, false, at(1300)), None);
+    assert_eq!(gestures.poll(at(5000)), None);
+}
+
+#[test]
+fn slow_second_button_is_not_a_chord() {
+    let mut gestures = GestureRecognizer::new(GestureTimings::default());
+    gestures.edge(B0, true, at(0));
+    gestures.edge(B1, true, at(200));
+    gestures.edge(B1, false, at(300));
+    gestures.edge(B0, false, at(400));
+    assert_eq!(
+        gestures.poll(at(600)),
+        Some(ButtonEvent::Single(B1, Gesture::Click))
+    );
+    assert_eq!(
+        gestures.poll(at(700)),
+        Some(ButtonEvent::Single(B0, Gesture::Click))
+    );
+}
+
+#[test]
+fn custom_timings() {
+    let timings = GestureTimings {
+        long_press: embassy_time::Duration::from_millis(2000),
+        ..GestureTimings::default()
+    };
+    let mut gestures = GestureRecognizer::new(timings);
+    gestures.edge(B0, true, at(0));
+    assert_eq!(gestures.poll(at(1500)), None);
+    assert_eq!(
+        gestures.poll(at(2000)),
+        Some(ButtonEvent::Single(B0, Gesture::LongPress))
+    );
+    assert_eq!(gestures.poll(at(2249)), None);
+    assert_eq!(
+        gestures.poll(at(2250)),
+        Some(ButtonEvent::Single(B0, Gesture::Repeat))
+    );
+}
@@ -0,0 +1,69 @@
+//! Tests for the LED driver using the recording output
+//!
+//! You can run this using `cargo test --features leds --test led_driver` from the `common`
+//! directory.
+
+use embassy_futures::block_on;
+use jig_common::{
+    leds::{GammaCurve, LedBuffer, LedConfig, LedDriver, LedError, PowerBudget, led_diagnostics},
+    recording::RecordingOutput,
+};
+use smart_leds::RGB8;
+
+/// A driver that passes colours through unchanged
+fn linear_driver() -> LedDriver<RecordingOutput<4>> {
+    let config = LedConfig::default().with_gamma(GammaCurve::Linear);
+    LedDriver::new(RecordingOutput::new(), config)
+}
+
+#[test]
+fn update_leaves_buffer_alone() {
+    let mut led = linear_driver();
+    let mut frame = LedBuffer::default();
+    frame.fill(RGB8::new(200, 100, 50));
+    let original = frame;
+
+    for _ in 0..3 {
+        assert_eq!(block_on(led.update_from_buffer(&frame, 128)), Ok(()));
+    }
+
+    assert_eq!(frame, original);
+    // Every update shows the same dimmed frame rather than getting darker each time
+    let first = *led.backend().frames().next().unwrap();
+    assert_eq!(led.backend().last(), Some(&first));
+    assert!(first[0].r < original[0].r);
+}
+
+#[test]
+fn frames_are_limited_to_the_power_budget() {
+    let budget = PowerBudget {
+        channel_ma: [20, 20, 20],
+        idle_ma: 0,
+        budget_ma: 10,
+    };
+    let mut led = LedDriver::new(
+        RecordingOutput::<4>::new(),
+        LedConfig::default()
+            .with_gamma(GammaCurve::Linear)
+            .with_power(budget),
+    );
+
+    block_on(led.white(255)).unwrap();
+
+    assert!(led.power().limited);
+    assert!(led.power().estimated_ma <= budget.budget_ma);
+    assert_eq!(budget.estimate_ma(led.output()), led.power().estimated_ma);
+}
+
+#[test]
+fn failures_are_reported_and_counted() {
+    let mut led = linear_driver();
+    let before = led_diagnostics().failures;
+    led.backend_mut().fail_next(1);
+
+    assert_eq!(block_on(led.all_off()), Err(LedError::Transmission));
+    assert_eq!(block_on(led.all_off()), Ok(()));
+
+    assert_eq!(led_diagnostics().failures, before + 1);
+    assert_eq!(led_diagnostics().last_error, Some(LedError::Transmission));
+}
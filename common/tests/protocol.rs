@@ -0,0 +1,304 @@
+//! Tests for the framed binary protocol
+//!
+//! You can run this using `cargo test --test protocol` from the `common` directory.
+
+use embassy_futures::block_on;
+use jig_common::{
+    console::{CommandError, Jig, LedHealth, RunSummary, SettingId},
+    counters::Counters,
+    protocol::{
+        FrameError, FrameReader, MAX_FRAME, PROTOCOL_VERSION, Reading, Request, Respond, Response,
+        decode, encode, serve,
+    },
+    record::{ErrorCode, ResultRecord, Verdict},
+};
+
+const RECORD: ResultRecord = ResultRecord {
+    run: 70_000,
+    position: 3,
+    old_address: Some(0x04),
+    new_address: Some(0x7f),
+    serial: Some(u32::MAX),
+    verdict: Verdict::Fail,
+    error: ErrorCode::VerifyFailed,
+};
+
+/// A jig with one socket that keeps settings in memory
+#[derive(Default)]
+struct MockJig {
+    settings: [u32; SettingId::ALL.len()],
+    results: bool,
+}
+
+impl Jig for MockJig {
+    const VERSION: &'static str = "a-mock-jig-with-a-very-long-name 1.2.3";
+    type LogCursor = usize;
+
+    fn sockets(&self) -> u8 {
+        1
+    }
+
+    async fn scan(&mut self, _position: u8) -> Result<Option<u8>, CommandError> {
+        Ok(None)
+    }
+
+    async fn program(&mut self) -> Result<RunSummary, CommandError> {
+        Ok(RunSummary {
+            run: 9,
+            attempted: 1,
+            failed: 0,
+        })
+    }
+
+    async fn verify(&mut self, _position: u8) -> Result<bool, CommandError> {
+        Ok(false)
+    }
+
+    async fn reset_default(&mut self, _position: u8) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn set_monitor(&mut self, on: bool) {
+        self.results = on;
+    }
+
+    fn watch_readings(&mut self, _on: bool) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn setting(&self, id: SettingId) -> u32 {
+        self.settings[id as usize]
+    }
+
+    async fn set_setting(&mut self, id: SettingId, value: u32) -> Result<(), CommandError> {
+        self.settings[id as usize] = value;
+        Ok(())
+    }
+
+    fn next_record(&mut self, cursor: &mut usize) -> Result<Option<ResultRecord>, CommandError> {
+        *cursor += 1;
+        Ok((*cursor <= 2).then_some(RECORD))
+    }
+
+    fn led_health(&self) -> LedHealth {
+        LedHealth::default()
+    }
+
+    fn session_counters(&self) -> Counters {
+        Counters::default()
+    }
+
+    fn lifetime_counters(&self) -> Option<Counters> {
+        None
+    }
+
+    fn next_address(&self) -> Result<Option<u8>, CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    async fn restart_addresses(&mut self, _address: u8) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+}
+
+/// Keeps every response
+#[derive(Default)]
+struct Responses(Vec<Response>);
+
+impl Respond for Responses {
+    async fn send(&mut self, response: Response) {
+        self.0.push(response);
+    }
+}
+
+fn serve_one(jig: &mut MockJig, request: Request) -> Vec<Response> {
+    let mut out = Responses::default();
+    block_on(serve(request, jig, &mut out));
+    out.0
+}
+
+/// Encode a message and read it back through a [`FrameReader`]
+fn round_trip<T>(message: &T) -> T
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut frame = [0; MAX_FRAME];
+    let len = encode(message, &mut frame).unwrap();
+    assert_eq!(frame[len - 1], 0);
+    assert!(!frame[..len - 1].contains(&0));
+    let mut reader = FrameReader::new();
+    let (last, rest) = frame[..len].split_last().unwrap();
+    for &byte in rest {
+        assert!(reader.push::<T>(byte).is_none());
+    }
+    reader.push(*last).unwrap().unwrap()
+}
+
+#[test]
+fn requests_round_trip() {
+    for request in [
+        Request::Hello,
+        Request::StartRun,
+        Request::WatchResults(true),
+        Request::WatchReadings(false),
+        Request::ReadLog,
+        Request::GetSettings,
+        Request::SetSetting(SettingId::ScanTimeout, 60_000),
+        Request::Close,
+    ] {
+        assert_eq!(round_trip(&request), request);
+    }
+}
+
+#[test]
+fn responses_round_trip() {
+    for response in [
+        Response::Hello {
+            protocol: PROTOCOL_VERSION,
+            firmware: "x".repeat(32).as_str().try_into().unwrap(),
+        },
+        Response::Done,
+        Response::Error(CommandError::InterlockOpen),
+        Response::RunFinished(RunSummary {
+            run: u32::MAX,
+            attempted: 4,
+            failed: 4,
+        }),
+        Response::Result(RECORD),
+        Response::Reading(Reading {
+            position: 2,
+            value: u16::MAX,
+        }),
+        Response::LogRecord(RECORD),
+        Response::Setting(SettingId::AddressFirst, 0x7f),
+    ] {
+        assert_eq!(round_trip(&response), response);
+    }
+}
+
+#[test]
+fn damaged_frames_are_dropped() {
+    let mut frame = [0; MAX_FRAME];
+    let len = encode(&Request::ReadLog, &mut frame).unwrap();
+    let mut reader = FrameReader::new();
+
+    // A flipped bit fails the CRC
+    let mut damaged = frame;
+    damaged[1] ^= 0x01;
+    let mut results = damaged[..len]
+        .iter()
+        .filter_map(|&b| reader.push::<Request>(b));
+    assert_eq!(results.next(), Some(Err(FrameError::Damaged)));
+
+    // Too short to hold a CRC
+    assert_eq!(
+        decode::<Request>(&mut [0x02, 0x01]),
+        Err(FrameError::Damaged)
+    );
+
+    // The next good frame is read
+    let results: Vec<_> = frame[..len]
+        .iter()
+        .filter_map(|&b| reader.push::<Request>(b))
+        .collect();
+    assert_eq!(results, [Ok(Request::ReadLog)]);
+}
+
+#[test]
+fn reading_resyncs_after_noise() {
+    let mut frame = [0; MAX_FRAME];
+    let len = encode(&Request::GetSettings, &mut frame).unwrap();
+    let mut reader = FrameReader::new();
+    // Console text typed before switching, then a lone zero to start afresh
+    let mut bytes = b"scan\r\n\0".to_vec();
+    bytes.extend_from_slice(&frame[..len]);
+    let results: Vec<_> = bytes
+        .iter()
+        .filter_map(|&b| reader.push::<Request>(b))
+        .collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_err());
+    assert_eq!(results[1], Ok(Request::GetSettings));
+}
+
+#[test]
+fn frames_that_are_too_long_are_rejected() {
+    let mut reader = FrameReader::new();
+    for _ in 0..MAX_FRAME * 2 {
+        assert_eq!(reader.push::<Request>(0x55), None);
+    }
+    assert_eq!(reader.push::<Request>(0), Some(Err(FrameError::TooLong)));
+    // A message from a newer version that is an intact frame but not a known request
+    let mut frame = [0; MAX_FRAME];
+    let len = encode(&(200u8, 1u8), &mut frame).unwrap();
+    let results: Vec<_> = frame[..len]
+        .iter()
+        .filter_map(|&b| reader.push::<Request>(b))
+        .collect();
+    assert_eq!(results, [Err(FrameError::Unknown)]);
+    // A message too long to encode
+    assert_eq!(encode(&[7u8; 80][..], &mut frame), Err(FrameError::TooLong));
+}
+
+#[test]
+fn requests_end_with_done_or_error() {
+    let mut jig = MockJig::default();
+    let hello = serve_one(&mut jig, Request::Hello);
+    let Response::Hello { protocol, firmware } = &hello[0] else {
+        panic!("{hello:?}");
+    };
+    assert_eq!(*protocol, PROTOCOL_VERSION);
+    assert_eq!(firmware.as_str(), "a-mock-jig-with-a-very-long-name");
+    assert_eq!(hello[1], Response::Done);
+
+    assert_eq!(
+        serve_one(&mut jig, Request::StartRun),
+        [
+            Response::RunFinished(RunSummary {
+                run: 9,
+                attempted: 1,
+                failed: 0
+            }),
+            Response::Done
+        ]
+    );
+    assert_eq!(
+        serve_one(&mut jig, Request::ReadLog),
+        [
+            Response::LogRecord(RECORD),
+            Response::LogRecord(RECORD),
+            Response::Done
+        ]
+    );
+    assert_eq!(
+        serve_one(&mut jig, Request::WatchResults(true)),
+        [Response::Done]
+    );
+    assert!(jig.results);
+    assert_eq!(
+        serve_one(&mut jig, Request::WatchReadings(true)),
+        [Response::Error(CommandError::Unsupported)]
+    );
+    assert_eq!(serve_one(&mut jig, Request::Close), [Response::Done]);
+    assert!(!jig.results);
+}
+
+#[test]
+fn settings_are_checked_like_the_console() {
+    let mut jig = MockJig::default();
+    assert_eq!(
+        serve_one(&mut jig, Request::SetSetting(SettingId::RetryAttempts, 3)),
+        [
+            Response::Setting(SettingId::RetryAttempts, 3),
+            Response::Done
+        ]
+    );
+    assert_eq!(
+        serve_one(&mut jig, Request::SetSetting(SettingId::RetryAttempts, 0)),
+        [Response::Error(CommandError::Rejected)]
+    );
+    assert_eq!(jig.setting(SettingId::RetryAttempts), 3);
+    let all = serve_one(&mut jig, Request::GetSettings);
+    assert_eq!(all.len(), SettingId::ALL.len() + 1);
+    assert!(all.contains(&Response::Setting(SettingId::RetryAttempts, 3)));
+}
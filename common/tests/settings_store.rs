@@ -0,0 +1,277 @@
+//! Tests for the flash key/value store and the settings kept in it
+//!
+//! You can run this using `cargo test --test settings_store` from the `common` directory.
+
+use embassy_time::Duration;
+use jig_common::{
+    console::SettingId,
+    crc::crc32,
+    flash::{Flash, FlashError},
+    ram_flash::RamFlash,
+    settings::{
+        AddressPlan, ENCODED_LEN, Language, ProgramMode, SETTINGS_VERSION, Settings, SettingsFault,
+    },
+    storage::{Key, KvStore, StorageError},
+};
+
+/// Small sectors so a few writes fill one
+type TestFlash = RamFlash<256, 4>;
+
+fn open(flash: TestFlash) -> KvStore<TestFlash> {
+    KvStore::open(flash, 0, 4).unwrap()
+}
+
+#[test]
+fn blank_flash_has_default_settings() {
+    let mut store = open(TestFlash::new());
+    let mut buf = [0; 8];
+    assert_eq!(store.read(Key::Settings, &mut buf), Ok(None));
+    assert_eq!(Settings::load(&mut store), Ok(Settings::default()));
+}
+
+#[test]
+fn settings_survive_reopening() {
+    let mut store = open(TestFlash::new());
+    let settings = Settings {
+        led_brightness: 80,
+        language: Language::German,
+        address_plan: AddressPlan {
+            first: 0x20,
+            step: 2,
+        },
+        ..Settings::default()
+    };
+    settings.save(&mut store).unwrap();
+
+    let mut store = open(store.into_flash());
+    assert_eq!(Settings::load(&mut store), Ok(settings));
+}
+
+#[test]
+fn encoding_round_trips() {
+    let mut settings = Settings {
+        torch_brightness: 255,
+        ..Settings::default()
+    };
+    settings.timeouts.program = Duration::from_millis(2500);
+    settings.retry.attempts = 5;
+    let bytes = settings.to_bytes();
+    assert_eq!(bytes.len(), ENCODED_LEN);
+    assert_eq!(Settings::from_bytes(&bytes), Ok(settings));
+    assert!(Settings::from_bytes(&bytes[..ENCODED_LEN - 1]).is_err());
+}
+
+#[test]
+fn corrupt_settings_are_reported() {
+    let mut bytes = Settings::default().to_bytes();
+    bytes[2] ^= 0x01;
+    let mut store = open(TestFlash::new());
+    store.write(Key::Settings, &bytes).unwrap();
+    assert_eq!(Settings::load(&mut store), Err(SettingsFault::Corrupt));
+    // The stored settings are left alone
+    let mut stored = [0; ENCODED_LEN];
+    assert_eq!(
+        store.read(Key::Settings, &mut stored),
+        Ok(Some(ENCODED_LEN))
+    );
+    assert_eq!(stored, bytes);
+}
+
+#[test]
+fn invalid_value_is_corrupt() {
+    let mut bytes = Settings::default().to_bytes();
+    // An unknown language, with a CRC to match
+    bytes[7] = 200;
+    let crc = crc32(&bytes[..ENCODED_LEN - 4]);
+    bytes[ENCODED_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+    assert_eq!(Settings::from_bytes(&bytes), Err(SettingsFault::Corrupt));
+}
+
+#[test]
+fn newer_settings_are_reported() {
+    // A longer layout from a future firmware, ending in a CRC like every layout from 2 on
+    let mut bytes = [0u8; 40];
+    bytes[0..2].copy_from_slice(&(SETTINGS_VERSION + 1).to_le_bytes());
+    let crc = crc32(&bytes[..36]);
+    bytes[36..].copy_from_slice(&crc.to_le_bytes());
+    assert_eq!(
+        Settings::from_bytes(&bytes),
+        Err(SettingsFault::Newer(SETTINGS_VERSION + 1))
+    );
+
+    // Without a good CRC it is more likely to be corruption
+    bytes[10] = 1;
+    assert_eq!(Settings::from_bytes(&bytes), Err(SettingsFault::Corrupt));
+}
+
+#[test]
+fn version_1_settings_are_migrated() {
+    // Version 1 had the same values but no CRC
+    let mut v1 = [0u8; 24];
+    v1[0..2].copy_from_slice(&1u16.to_le_bytes());
+    v1[2] = 60;
+    v1[3] = 200;
+    v1[4] = 0x30;
+    v1[5] = 1;
+    v1[6] = 2;
+    v1[7] = Language::French as u8;
+    v1[8..12].copy_from_slice(&20u32.to_le_bytes());
+    v1[12..16].copy_from_slice(&100u32.to_le_bytes());
+    v1[16..20].copy_from_slice(&200u32.to_le_bytes());
+    v1[20..24].copy_from_slice(&300u32.to_le_bytes());
+
+    let mut store = open(TestFlash::new());
+    store.write(Key::Settings, &v1).unwrap();
+    let settings = Settings::load(&mut store).unwrap();
+    assert_eq!(settings.version, SETTINGS_VERSION);
+    assert_eq!(settings.led_brightness, 60);
+    assert_eq!(settings.torch_brightness, 200);
+    assert_eq!(
+        settings.address_plan,
+        AddressPlan {
+            first: 0x30,
+            step: 1
+        }
+    );
+    assert_eq!(settings.retry.attempts, 2);
+    assert_eq!(settings.language, Language::French);
+    assert_eq!(settings.retry.backoff, Duration::from_millis(20));
+    assert_eq!(settings.timeouts.verify, Duration::from_millis(300));
+
+    // Loading saved them again in the current layout
+    let mut stored = [0; ENCODED_LEN];
+    assert_eq!(
+        store.read(Key::Settings, &mut stored),
+        Ok(Some(ENCODED_LEN))
+    );
+    assert_eq!(stored, settings.to_bytes());
+}
+
+#[test]
+fn version_2_settings_are_migrated() {
+    // Version 2 was version 1 with a CRC, and had no programming mode
+    let mut v2 = [0u8; 28];
+    v2[0..24].copy_from_slice(&Settings::default().to_bytes()[0..24]);
+    v2[0..2].copy_from_slice(&2u16.to_le_bytes());
+    v2[2] = 70;
+    let crc = crc32(&v2[..24]);
+    v2[24..].copy_from_slice(&crc.to_le_bytes());
+
+    let settings = Settings::from_bytes(&v2).unwrap();
+    assert_eq!(settings.version, 2);
+    assert_eq!(settings.led_brightness, 70);
+    assert_eq!(settings.mode, ProgramMode::Fixture);
+    assert_eq!(settings.continuous, Settings::default().continuous);
+
+    // A version 2 blob failing its CRC is not migrated
+    v2[3] ^= 0x01;
+    assert_eq!(Settings::from_bytes(&v2), Err(SettingsFault::Corrupt));
+}
+
+#[test]
+fn crc_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn writes_rotate_through_every_sector() {
+    let mut store = open(TestFlash::new());
+    for i in 0..100u8 {
+        store.write(Key::Settings, &[i; 24]).unwrap();
+    }
+    let mut buf = [0; 24];
+    assert_eq!(store.read(Key::Settings, &mut buf), Ok(Some(24)));
+    assert_eq!(buf, [99; 24]);
+
+    let erases = *store.flash().erases();
+    let most = erases.iter().max().unwrap();
+    let least = erases.iter().min().unwrap();
+    assert!(*least > 0);
+    assert!(most - least <= 1);
+}
+
+#[test]
+fn values_of_any_length_are_read_in_whole_words() {
+    let mut flash = TestFlash::new();
+    let mut buf = [0; 3];
+    assert_eq!(flash.read(4, &mut buf), Err(FlashError::NotAligned));
+    assert_eq!(flash.read(2, &mut [0; 4]), Err(FlashError::NotAligned));
+
+    let mut store = open(flash);
+    store.write(Key::NextAddress, &[1, 2, 3]).unwrap();
+    store.write(Key::Counters, &[4, 5]).unwrap();
+    // Changing a value reads the old one first
+    store.write(Key::NextAddress, &[6, 7, 8]).unwrap();
+    assert_eq!(store.read(Key::NextAddress, &mut buf), Ok(Some(3)));
+    assert_eq!(buf, [6, 7, 8]);
+    assert_eq!(store.read(Key::Counters, &mut buf), Ok(Some(2)));
+    assert_eq!(buf[..2], [4, 5]);
+}
+
+#[test]
+fn one_sector_is_refused() {
+    assert!(matches!(
+        KvStore::open(TestFlash::new(), 0, 1),
+        Err(StorageError::Flash(FlashError::TooFewSectors))
+    ));
+}
+
+#[test]
+fn unchanged_value_is_not_written_again() {
+    let mut store = open(TestFlash::new());
+    store.write(Key::Settings, &[1, 2, 3, 4]).unwrap();
+    let before = *store.flash().sector(0);
+    store.write(Key::Settings, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(*store.flash().sector(0), before);
+}
+
+#[test]
+fn interrupted_write_keeps_old_value() {
+    let mut store = open(TestFlash::new());
+    store.write(Key::Settings, &[7; 8]).unwrap();
+    let mut flash = store.into_flash();
+    // Power was lost after a new value was written but before its record header
+    flash.corrupt(8 + 4 + 8 + 4, &[0; 8]);
+
+    let mut store = open(flash);
+    let mut buf = [0; 8];
+    assert_eq!(store.read(Key::Settings, &mut buf), Ok(Some(8)));
+    assert_eq!(buf, [7; 8]);
+    // New writes go to a clean sector rather than on top of the half written data
+    store.write(Key::Settings, &[9; 8]).unwrap();
+    let mut store = open(store.into_flash());
+    assert_eq!(store.read(Key::Settings, &mut buf), Ok(Some(8)));
+    assert_eq!(buf, [9; 8]);
+}
+
+#[test]
+fn address_plan_stays_in_range() {
+    let plan = AddressPlan {
+        first: 0x70,
+        step: 2,
+    };
+    assert_eq!(plan.address(0), Some(0x70));
+    assert_eq!(plan.address(3), Some(0x76));
+    assert_eq!(plan.address(4), None);
+    assert_eq!(AddressPlan { first: 0, step: 1 }.address(0), None);
+}
+
+#[test]
+fn console_values_match_the_settings() {
+    let mut settings = Settings::default();
+    assert!(settings.set_value(SettingId::Language, Language::French as u32));
+    assert!(settings.set_value(SettingId::VerifyTimeout, 750));
+    assert!(settings.set_value(SettingId::ContinuousLimit, 0x40));
+    assert_eq!(settings.language, Language::French);
+    assert_eq!(settings.timeouts.verify, Duration::from_millis(750));
+    assert_eq!(settings.continuous.limit, 0x40);
+    // Values that don't fit leave the setting alone
+    assert!(!settings.set_value(SettingId::Mode, 7));
+    assert!(!settings.set_value(SettingId::LedBrightness, 256));
+    assert_eq!(settings.mode, ProgramMode::Fixture);
+    for id in SettingId::ALL {
+        let mut copy = settings;
+        assert!(copy.set_value(id, settings.value(id)));
+        assert_eq!(copy, settings);
+    }
+}
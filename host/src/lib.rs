@@ -0,0 +1,6 @@
+//! Companion tool for driving the jig from a computer over its USB serial port, using the
+//! binary protocol in `jig-common`. A simulated jig on a pseudo-terminal stands in for the
+//! board, so everything can be tried and tested without one.
+
+pub mod link;
+pub mod simulator;
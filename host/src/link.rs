@@ -0,0 +1,157 @@
+//! Link module talks the binary protocol to a jig over a serial port.
+//!
+//! Opening a [`Link`] switches the port from the console to the protocol and checks the jig
+//! speaks the same protocol version. [`Link::request`] sends a request and hands back each
+//! response until the jig says it is done.
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use jig_common::protocol::{FrameReader, MAX_FRAME, PROTOCOL_VERSION, Request, Response, encode};
+use serialport::SerialPort;
+
+/// The USB serial port ignores the baud rate, but a real UART wouldn't
+const BAUD_RATE: u32 = 115_200;
+/// How long each read of the port waits, so deadlines are checked in between
+const READ_POLL: Duration = Duration::from_millis(50);
+/// How long to wait for the jig to answer a request
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A jig on the end of a serial port
+pub struct Link<P> {
+    port: P,
+    frames: FrameReader,
+    /// Firmware name and version from the jig's hello
+    firmware: String,
+}
+
+impl Link<Box<dyn SerialPort>> {
+    /// Open the serial port a jig is on
+    ///
+    /// # Arguments
+    /// * `path` - The port, such as `/dev/ttyACM0` or `COM3`, or a simulator's pseudo-terminal
+    pub fn open(path: &str) -> Result<Self> {
+        let port = serialport::new(path, BAUD_RATE)
+            .timeout(READ_POLL)
+            .open()
+            .with_context(|| format!("Failed to open {path}"))?;
+        Self::new(port)
+    }
+}
+
+impl<P: Read + Write> Link<P> {
+    /// Switch a port to the protocol and say hello
+    pub fn new(mut port: P) -> Result<Self> {
+        // A zero byte switches the console over, or ends any half sent frame
+        port.write_all(&[0])?;
+        let mut link = Self {
+            port,
+            frames: FrameReader::new(),
+            firmware: String::new(),
+        };
+        let mut hello = None;
+        link.request(Request::Hello, REPLY_TIMEOUT, |response| {
+            if let Response::Hello { protocol, firmware } = response {
+                hello = Some((protocol, firmware));
+            }
+            Ok(())
+        })?;
+        let Some((protocol, firmware)) = hello else {
+            bail!("The jig didn't say which protocol it speaks");
+        };
+        if protocol != PROTOCOL_VERSION {
+            bail!("The jig speaks protocol {protocol}, but this tool speaks {PROTOCOL_VERSION}");
+        }
+        link.firmware = firmware.to_string();
+        Ok(link)
+    }
+
+    /// The firmware name and version the jig gave when the link was opened
+    pub fn firmware(&self) -> &str {
+        &self.firmware
+    }
+
+    /// Send a request and pass on each response until the jig is done
+    ///
+    /// # Arguments
+    /// * `request` - What to ask for
+    /// * `timeout` - How long to wait for each response
+    /// * `each` - Called with every response but the last, including results and readings
+    ///   being watched. An error stops waiting
+    pub fn request(
+        &mut self,
+        request: Request,
+        timeout: Duration,
+        mut each: impl FnMut(Response) -> Result<()>,
+    ) -> Result<()> {
+        self.send(request)?;
+        loop {
+            match self.receive(Some(timeout))? {
+                Response::Done => return Ok(()),
+                Response::Error(e) => bail!("The jig couldn't {request:?}: {}", e.message()),
+                response => each(response)?,
+            }
+        }
+    }
+
+    /// Send a request without waiting for the jig to answer
+    pub fn send(&mut self, request: Request) -> Result<()> {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode(&request, &mut frame).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        self.port.write_all(&frame[..len])?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    /// Wait for the next response. Damaged frames are reported and skipped
+    ///
+    /// # Arguments
+    /// * `timeout` - How long to wait, or None to wait for as long as it takes
+    pub fn receive(&mut self, timeout: Option<Duration>) -> Result<Response> {
+        match self.receive_unless(timeout, || false)? {
+            Some(response) => Ok(response),
+            None => unreachable!("Nothing stops the wait"),
+        }
+    }
+
+    /// Wait for the next response, giving up early if asked to
+    ///
+    /// # Arguments
+    /// * `timeout` - How long to wait, or None to wait for as long as it takes
+    /// * `stop` - Checked between reads. Returns None once it says to stop, such as on Ctrl-C
+    pub fn receive_unless(
+        &mut self,
+        timeout: Option<Duration>,
+        stop: impl Fn() -> bool,
+    ) -> Result<Option<Response>> {
+        let start = Instant::now();
+        let mut byte = [0];
+        loop {
+            if stop() {
+                return Ok(None);
+            }
+            match self.port.read(&mut byte) {
+                Ok(0) => bail!("The port was closed"),
+                Ok(_) => match self.frames.push::<Response>(byte[0]) {
+                    Some(Ok(response)) => return Ok(Some(response)),
+                    Some(Err(e)) => eprintln!("Dropped a frame from the jig: {e:?}"),
+                    None => {}
+                },
+                // A signal cuts a read short, and the caller's stop check deals with it
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e).context("Failed to read from the jig"),
+            }
+            if timeout.is_some_and(|timeout| start.elapsed() > timeout) {
+                bail!("The jig didn't answer");
+            }
+        }
+    }
+
+    /// Stop watching and hand the port back to the console
+    pub fn close(mut self) -> Result<()> {
+        self.request(Request::Close, REPLY_TIMEOUT, |_| Ok(()))
+    }
+}
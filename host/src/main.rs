@@ -0,0 +1,270 @@
+//! Command line tool for driving the jig from a computer. Run `jig --help` for the commands.
+
+use std::{
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand, ValueEnum};
+use jig_common::{
+    console::SettingId,
+    protocol::{Request, Response},
+    record::{CSV_HEADER, ResultRecord},
+};
+use jig_host::{
+    link::{Link, REPLY_TIMEOUT},
+    simulator::Simulator,
+};
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use serialport::SerialPort;
+
+/// How long a programming run may take before giving up on the jig
+const RUN_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Drive the SingleTact programming jig over its USB serial port"
+)]
+struct Cli {
+    /// The jig's serial port, or the pseudo-terminal of a simulator
+    #[arg(short, long, global = true)]
+    port: Option<String>,
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Show the firmware and protocol version
+    Info,
+    /// Program every socket, printing each result as it is recorded
+    Run {
+        #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+    },
+    /// Print results as they are recorded, from runs started with the buttons too
+    Tail {
+        #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        /// Stop after this many results
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+    /// Print the whole result log, oldest first
+    Log {
+        #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+    },
+    /// Show one setting, or all of them
+    Get { setting: Option<String> },
+    /// Change and save a setting, typed as on the console
+    Set { setting: String, value: String },
+    /// Print live readings from the sensors
+    Readings {
+        /// Stop after this many readings
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+    /// Run a simulated jig on a pseudo-terminal, to use in place of the port
+    Simulate {
+        /// Start a run every so many seconds, as if the start button were pressed
+        #[arg(long, value_name = "SECONDS")]
+        press_start: Option<u64>,
+    },
+}
+
+/// How records are printed
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// A header line, then a line per record
+    Csv,
+    /// A JSON object per line
+    Json,
+}
+
+impl Format {
+    fn header(self) {
+        if let Format::Csv = self {
+            println!("{CSV_HEADER}");
+        }
+    }
+
+    fn print(self, record: &ResultRecord) -> Result<()> {
+        match self {
+            Format::Csv => {
+                let mut line = String::new();
+                record.write_csv(&mut line)?;
+                println!("{line}");
+            }
+            Format::Json => println!("{}", serde_json::to_string(record)?),
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Cmd::Simulate { press_start } = cli.command {
+        let simulator = Simulator::new()?;
+        println!("Simulated jig on {}", simulator.path().display());
+        return simulator.run(press_start.map(Duration::from_secs));
+    }
+    let port = cli.port.context("Give the jig's serial port with --port")?;
+    let mut link = Link::open(&port)?;
+    // Hand the port back to the console even if the command failed, so the jig isn't left
+    // in the protocol with results or readings still being sent
+    let result = carry_out(cli.command, &mut link);
+    let closed = link.close();
+    result.and(closed)
+}
+
+/// Set once Ctrl-C is pressed, so watching commands can stop the jig before exiting
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Catch Ctrl-C instead of being killed by it, for commands that watch until stopped
+fn catch_interrupts() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_interrupt),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    // SAFETY: The handler only stores to an atomic, which is safe in a signal handler
+    unsafe { sigaction(Signal::SIGINT, &action) }.context("Failed to catch Ctrl-C")?;
+    Ok(())
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+fn carry_out(command: Cmd, link: &mut Link<Box<dyn SerialPort>>) -> Result<()> {
+    match command {
+        Cmd::Info => println!("{}", link.firmware()),
+        Cmd::Run { format } => {
+            format.header();
+            link.request(Request::WatchResults(true), REPLY_TIMEOUT, |_| Ok(()))?;
+            link.request(Request::StartRun, RUN_TIMEOUT, |response| {
+                match response {
+                    Response::Result(record) => format.print(&record)?,
+                    Response::RunFinished(summary) => eprintln!(
+                        "Run {}: {} programmed, {} failed",
+                        summary.run,
+                        summary.attempted - summary.failed,
+                        summary.failed
+                    ),
+                    _ => {}
+                }
+                Ok(())
+            })?;
+            link.request(Request::WatchResults(false), REPLY_TIMEOUT, |_| Ok(()))?;
+        }
+        Cmd::Tail { format, count } => {
+            format.header();
+            catch_interrupts()?;
+            link.request(Request::WatchResults(true), REPLY_TIMEOUT, |_| Ok(()))?;
+            let watched = watch(link, count, None, |response| {
+                let Response::Result(record) = response else {
+                    return Ok(false);
+                };
+                format.print(&record)?;
+                Ok(true)
+            });
+            let stopped = link.request(Request::WatchResults(false), REPLY_TIMEOUT, |_| Ok(()));
+            watched.and(stopped)?;
+        }
+        Cmd::Log { format } => {
+            format.header();
+            link.request(Request::ReadLog, REPLY_TIMEOUT, |response| {
+                if let Response::LogRecord(record) = response {
+                    format.print(&record)?;
+                }
+                Ok(())
+            })?;
+        }
+        Cmd::Get { setting } => {
+            let only = setting.as_deref().map(setting_id).transpose()?;
+            link.request(Request::GetSettings, REPLY_TIMEOUT, |response| {
+                if let Response::Setting(id, value) = response
+                    && only.is_none_or(|only| only == id)
+                {
+                    print_setting(id, value)?;
+                }
+                Ok(())
+            })?;
+        }
+        Cmd::Set { setting, value } => {
+            let id = setting_id(&setting)?;
+            let value = id
+                .kind()
+                .parse(&value)
+                .map_err(|e| anyhow::anyhow!("{}: {}", e.message(), value))?;
+            link.request(Request::SetSetting(id, value), REPLY_TIMEOUT, |response| {
+                if let Response::Setting(id, value) = response {
+                    print_setting(id, value)?;
+                }
+                Ok(())
+            })?;
+        }
+        Cmd::Readings { count } => {
+            catch_interrupts()?;
+            link.request(Request::WatchReadings(true), REPLY_TIMEOUT, |_| Ok(()))?;
+            println!("position,value");
+            let watched = watch(link, count, Some(REPLY_TIMEOUT), |response| {
+                let Response::Reading(reading) = response else {
+                    return Ok(false);
+                };
+                println!("{},{}", reading.position, reading.value);
+                Ok(true)
+            });
+            let stopped = link.request(Request::WatchReadings(false), REPLY_TIMEOUT, |_| Ok(()));
+            watched.and(stopped)?;
+        }
+        Cmd::Simulate { .. } => unreachable!("The simulator doesn't open a port"),
+    }
+    Ok(())
+}
+
+/// Pass on responses until enough have been shown or Ctrl-C is pressed
+///
+/// # Arguments
+/// * `count` - How many to show, or None to carry on until Ctrl-C
+/// * `timeout` - How long to wait for each response, or None to wait for as long as it takes
+/// * `each` - Called with every response. Returns whether it was shown
+fn watch(
+    link: &mut Link<Box<dyn SerialPort>>,
+    count: Option<usize>,
+    timeout: Option<Duration>,
+    mut each: impl FnMut(Response) -> Result<bool>,
+) -> Result<()> {
+    let mut seen = 0;
+    while count.is_none_or(|count| seen < count) {
+        let Some(response) = link.receive_unless(timeout, interrupted)? else {
+            break;
+        };
+        if each(response)? {
+            seen += 1;
+        }
+    }
+    Ok(())
+}
+
+fn setting_id(name: &str) -> Result<SettingId> {
+    match SettingId::from_name(name) {
+        Some(id) => Ok(id),
+        None => bail!("No setting called {name}"),
+    }
+}
+
+fn print_setting(id: SettingId, value: u32) -> Result<()> {
+    let mut shown = String::new();
+    id.kind().write(value, &mut shown)?;
+    println!("{} = {}", id.name(), shown);
+    Ok(())
+}
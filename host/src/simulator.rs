@@ -0,0 +1,376 @@
+//! Simulator module stands in for the jig on a pseudo-terminal, so the tool can be tried and
+//! tested without a board.
+//!
+//! The pseudo-terminal behaves like the jig's USB serial port. It starts as the console, and a
+//! zero byte switches it to the binary protocol until a close request, or until the first frame
+//! is overdue. Commands and requests
+//! are carried out by the same `jig-common` code as on the board, on a fake jig whose sensors
+//! always answer and fail now and then.
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::fd::{AsFd, OwnedFd},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use embassy_futures::block_on;
+use jig_common::{
+    console::{
+        CommandError, Jig, LedHealth, LineBuffer, Reply, RunSummary, SettingId, dispatch, parse,
+        reply_parse_error,
+    },
+    counters::Counters,
+    protocol::{
+        FIRST_FRAME_TIMEOUT, FrameReader, MAX_FRAME, Reading, Request, Respond, Response, encode,
+        serve,
+    },
+    record::{ErrorCode, ResultRecord, Verdict},
+};
+use nix::{
+    poll::{PollFd, PollFlags, PollTimeout, poll},
+    pty::openpty,
+    sys::termios::{SetArg, cfmakeraw, tcgetattr, tcsetattr},
+    unistd::ttyname,
+};
+
+/// Sockets on the simulated jig, as many as the board has
+const SOCKETS: u8 = 8;
+/// How long programming each sensor takes
+const PROGRAM_TIME: Duration = Duration::from_millis(20);
+/// How often readings are sent while they are watched
+const READING_INTERVAL: Duration = Duration::from_millis(100);
+/// Every sensor whose serial number is a multiple of this fails to verify
+const FAIL_EVERY: u32 = 7;
+
+/// Writes to the pseudo-terminal
+struct Port(File);
+
+impl Port {
+    fn write(&mut self, bytes: &[u8]) {
+        // Nothing may have the other end open, which the real port shrugs off too
+        let _ = self.0.write_all(bytes);
+    }
+}
+
+impl Reply for Port {
+    async fn line(&mut self, text: &str) {
+        self.write(text.as_bytes());
+        self.write(b"\r\n");
+    }
+}
+
+impl Respond for Port {
+    async fn send(&mut self, response: Response) {
+        let mut frame = [0; MAX_FRAME];
+        if let Ok(len) = encode(&response, &mut frame) {
+            self.write(&frame[..len]);
+        }
+    }
+}
+
+/// A jig with sensors that always answer
+struct FakeJig {
+    /// Where watched results and readings are written
+    port: Port,
+    /// True while the port speaks the protocol rather than the console
+    binary: bool,
+    settings: [u32; SettingId::ALL.len()],
+    log: Vec<ResultRecord>,
+    /// Serial number of the last sensor programmed
+    serial: u32,
+    results: bool,
+    readings: bool,
+    /// Readings sent so far, which the values are made from
+    ticks: u32,
+    /// What the simulator has done since it started
+    counters: Counters,
+}
+
+impl FakeJig {
+    fn new(port: Port) -> Self {
+        let mut settings = [0; SettingId::ALL.len()];
+        // Every setting starts at the lowest value it takes, with a first address that works
+        for id in SettingId::ALL {
+            settings[id as usize] = id.kind().range().0;
+        }
+        settings[SettingId::AddressFirst as usize] = 0x08;
+        settings[SettingId::AddressStep as usize] = 1;
+        Self {
+            port,
+            binary: false,
+            settings,
+            log: Vec::new(),
+            serial: 1000,
+            results: false,
+            readings: false,
+            ticks: 0,
+            counters: Counters {
+                boots: 1,
+                ..Counters::default()
+            },
+        }
+    }
+
+    /// The address the plan gives a socket, kept to the 7 bit range
+    fn address(&self, position: u8) -> u8 {
+        let first = self.setting(SettingId::AddressFirst);
+        let step = self.setting(SettingId::AddressStep);
+        ((first + step * u32::from(position)) & 0x7f) as u8
+    }
+
+    /// Send a result as it is recorded, in whichever way the port is being used
+    fn send_result(&mut self, record: ResultRecord) {
+        if self.binary {
+            block_on(self.port.send(Response::Result(record)));
+        } else {
+            let mut line = String::new();
+            let _ = record.write_csv(&mut line);
+            block_on(self.port.line(&line));
+        }
+    }
+
+    /// Send a reading from each socket
+    fn send_readings(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+        for position in 0..SOCKETS {
+            // A slow saw tooth, shifted for each socket
+            let value = (self.ticks.wrapping_mul(37) + u32::from(position) * 500) % 1024;
+            let reading = Reading {
+                position,
+                value: value as u16,
+            };
+            block_on(self.port.send(Response::Reading(reading)));
+        }
+    }
+}
+
+impl Jig for FakeJig {
+    const VERSION: &'static str = concat!("simulator ", env!("CARGO_PKG_VERSION"));
+    type LogCursor = usize;
+
+    fn sockets(&self) -> u8 {
+        SOCKETS
+    }
+
+    async fn scan(&mut self, position: u8) -> Result<Option<u8>, CommandError> {
+        Ok(Some(self.address(position)))
+    }
+
+    async fn program(&mut self) -> Result<RunSummary, CommandError> {
+        let run = self.log.last().map_or(1, |record| record.run + 1);
+        self.counters.runs += 1;
+        let mut failed = 0;
+        for position in 0..SOCKETS {
+            thread::sleep(PROGRAM_TIME);
+            self.serial += 1;
+            let fails = self.serial.is_multiple_of(FAIL_EVERY);
+            failed += u8::from(fails);
+            let record = ResultRecord {
+                run,
+                position,
+                old_address: Some(0x04),
+                new_address: Some(self.address(position)),
+                serial: Some(self.serial),
+                verdict: if fails { Verdict::Fail } else { Verdict::Pass },
+                error: if fails {
+                    ErrorCode::VerifyFailed
+                } else {
+                    ErrorCode::NoError
+                },
+            };
+            self.log.push(record);
+            self.counters.record(&record);
+            if self.results {
+                self.send_result(record);
+            }
+        }
+        Ok(RunSummary {
+            run,
+            attempted: SOCKETS,
+            failed,
+        })
+    }
+
+    async fn verify(&mut self, _position: u8) -> Result<bool, CommandError> {
+        Ok(true)
+    }
+
+    async fn reset_default(&mut self, _position: u8) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn set_monitor(&mut self, on: bool) {
+        self.results = on;
+    }
+
+    fn watch_readings(&mut self, on: bool) -> Result<(), CommandError> {
+        self.readings = on;
+        Ok(())
+    }
+
+    fn setting(&self, id: SettingId) -> u32 {
+        self.settings[id as usize]
+    }
+
+    async fn set_setting(&mut self, id: SettingId, value: u32) -> Result<(), CommandError> {
+        self.settings[id as usize] = value;
+        Ok(())
+    }
+
+    fn next_record(&mut self, cursor: &mut usize) -> Result<Option<ResultRecord>, CommandError> {
+        let record = self.log.get(*cursor).copied();
+        *cursor += 1;
+        Ok(record)
+    }
+
+    // The simulator has no LEDs to drive
+    fn led_health(&self) -> LedHealth {
+        LedHealth::default()
+    }
+
+    fn session_counters(&self) -> Counters {
+        self.counters
+    }
+
+    // Nothing is kept from one start of the simulator to the next
+    fn lifetime_counters(&self) -> Option<Counters> {
+        Some(self.counters)
+    }
+
+    // The simulator only programs the fixture address plan
+    fn next_address(&self) -> Result<Option<u8>, CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    async fn restart_addresses(&mut self, _address: u8) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+}
+
+/// A simulated jig on a pseudo-terminal
+pub struct Simulator {
+    master: File,
+    /// Where replies and responses are written
+    out: Port,
+    /// Held open so the pseudo-terminal outlives the tools that open and close it
+    _slave: OwnedFd,
+    path: PathBuf,
+    jig: FakeJig,
+    /// When the port goes back to the console unless a frame decodes first
+    first_frame_by: Option<Instant>,
+}
+
+impl Simulator {
+    /// Create the pseudo-terminal
+    pub fn new() -> Result<Self> {
+        let pty = openpty(None, None).context("Failed to open a pseudo-terminal")?;
+        // Pass bytes through untouched, as the USB serial port does
+        let mut termios = tcgetattr(&pty.slave)?;
+        cfmakeraw(&mut termios);
+        tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+        let path = ttyname(pty.slave.as_fd())?;
+        let master = File::from(pty.master);
+        let jig = FakeJig::new(Port(master.try_clone()?));
+        Ok(Self {
+            out: Port(master.try_clone()?),
+            master,
+            _slave: pty.slave,
+            path,
+            jig,
+            first_frame_by: None,
+        })
+    }
+
+    /// The pseudo-terminal for tools to open in place of the jig's serial port
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Answer the console and protocol until the pseudo-terminal fails
+    ///
+    /// # Arguments
+    /// * `press_start` - How often to start a run as if the start button were pressed, if at all
+    pub fn run(mut self, press_start: Option<Duration>) -> Result<()> {
+        let mut lines = LineBuffer::new();
+        let mut frames = FrameReader::new();
+        let mut last_reading = Instant::now();
+        let mut last_run = Instant::now();
+        let mut bytes = [0; 64];
+        loop {
+            let timeout = PollTimeout::try_from(READING_INTERVAL).unwrap_or(PollTimeout::MAX);
+            let mut fds = [PollFd::new(self.master.as_fd(), PollFlags::POLLIN)];
+            poll(&mut fds, timeout)?;
+            if self.first_frame_by.is_some_and(|by| Instant::now() >= by) {
+                // The zero byte was a stray one typed at a terminal, so bytes from now on are typed
+                self.jig.binary = false;
+                self.first_frame_by = None;
+                frames = FrameReader::new();
+            }
+            if fds[0]
+                .revents()
+                .is_some_and(|events| events.contains(PollFlags::POLLIN))
+            {
+                let len = self.master.read(&mut bytes)?;
+                for &byte in &bytes[..len] {
+                    self.receive(byte, &mut lines, &mut frames);
+                }
+            }
+            if self.jig.readings && last_reading.elapsed() >= READING_INTERVAL {
+                last_reading = Instant::now();
+                self.jig.send_readings();
+            }
+            if press_start.is_some_and(|every| last_run.elapsed() >= every) {
+                last_run = Instant::now();
+                // The button's runs only show on the port as the results they record
+                let _ = block_on(self.jig.program());
+            }
+        }
+    }
+
+    /// Handle a byte from the tool, the way the firmware's console task does
+    fn receive(&mut self, byte: u8, lines: &mut LineBuffer, frames: &mut FrameReader) {
+        let Self {
+            jig,
+            out,
+            first_frame_by,
+            ..
+        } = self;
+        if jig.binary {
+            match frames.push::<Request>(byte) {
+                Some(Ok(request)) => {
+                    *first_frame_by = None;
+                    if request == Request::Close {
+                        jig.binary = false;
+                    }
+                    block_on(serve(request, jig, out));
+                }
+                Some(Err(e)) => eprintln!("Simulator dropped a frame: {e:?}"),
+                None => {}
+            }
+            return;
+        }
+        if byte == 0 {
+            jig.binary = true;
+            *first_frame_by =
+                Some(Instant::now() + Duration::from_millis(FIRST_FRAME_TIMEOUT.as_millis()));
+            *lines = LineBuffer::new();
+            return;
+        }
+        let line = lines.push(byte);
+        match (byte, &line) {
+            (_, Some(_)) => out.write(b"\r\n"),
+            (0x08 | 0x7f, None) => out.write(b"\x08 \x08"),
+            (b' '..=b'~', None) => out.write(&[byte]),
+            _ => {}
+        }
+        match line.map(|line| line.and_then(|line| parse(&line))) {
+            Some(Ok(command)) => block_on(dispatch(command, jig, out)),
+            Some(Err(e)) => block_on(reply_parse_error(e, out)),
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Tests for the link, run against the simulated jig on a pseudo-terminal
+//!
+//! You can run this using `cargo test --test simulator` from the `host` directory.
+
+use std::{
+    io::{Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use jig_common::{
+    console::{CommandError, SettingId},
+    protocol::{FIRST_FRAME_TIMEOUT, Request, Response},
+    record::ResultRecord,
+};
+use jig_host::{
+    link::{Link, REPLY_TIMEOUT},
+    simulator::Simulator,
+};
+
+/// Start a simulated jig and open a link to it
+fn connect() -> (String, Link<Box<dyn serialport::SerialPort>>) {
+    let simulator = Simulator::new().unwrap();
+    let path = simulator.path().to_str().unwrap().to_string();
+    thread::spawn(move || simulator.run(None));
+    let link = Link::open(&path).unwrap();
+    (path, link)
+}
+
+/// Send a request and keep every response but the last
+fn responses(
+    link: &mut Link<Box<dyn serialport::SerialPort>>,
+    request: Request,
+) -> anyhow::Result<Vec<Response>> {
+    let mut all = Vec::new();
+    link.request(request, REPLY_TIMEOUT, |response| {
+        all.push(response);
+        Ok(())
+    })?;
+    Ok(all)
+}
+
+#[test]
+fn hello_names_the_simulator() {
+    let (_, link) = connect();
+    assert!(link.firmware().starts_with("simulator "));
+    link.close().unwrap();
+}
+
+#[test]
+fn run_results_are_watched_and_logged() {
+    let (_, mut link) = connect();
+    // Results aren't sent until they are watched
+    let unwatched = responses(&mut link, Request::StartRun).unwrap();
+    assert!(matches!(unwatched[..], [Response::RunFinished(_)]));
+
+    responses(&mut link, Request::WatchResults(true)).unwrap();
+    let watched = responses(&mut link, Request::StartRun).unwrap();
+    let Some((Response::RunFinished(summary), results)) = watched.split_last() else {
+        panic!("{watched:?}");
+    };
+    assert_eq!(summary.run, 2);
+    assert_eq!(results.len(), usize::from(summary.attempted));
+    let results: Vec<ResultRecord> = results
+        .iter()
+        .map(|response| match response {
+            Response::Result(record) => *record,
+            other => panic!("{other:?}"),
+        })
+        .collect();
+    let failed = results
+        .iter()
+        .filter(|record| record.serial.unwrap().is_multiple_of(7));
+    assert_eq!(failed.count(), usize::from(summary.failed));
+
+    let log = responses(&mut link, Request::ReadLog).unwrap();
+    assert_eq!(log.len(), results.len() * 2);
+    assert!(
+        log[results.len()..]
+            .iter()
+            .zip(&results)
+            .all(|(logged, result)| *logged == Response::LogRecord(*result))
+    );
+    link.close().unwrap();
+}
+
+#[test]
+fn settings_are_changed_and_checked() {
+    let (_, mut link) = connect();
+    let changed = responses(&mut link, Request::SetSetting(SettingId::ScanTimeout, 750));
+    assert_eq!(
+        changed.unwrap(),
+        [Response::Setting(SettingId::ScanTimeout, 750)]
+    );
+    let rejected = responses(
+        &mut link,
+        Request::SetSetting(SettingId::AddressFirst, 0x80),
+    );
+    assert!(
+        rejected
+            .unwrap_err()
+            .to_string()
+            .ends_with(CommandError::Rejected.message())
+    );
+    let all = responses(&mut link, Request::GetSettings).unwrap();
+    assert_eq!(all.len(), SettingId::ALL.len());
+    assert!(all.contains(&Response::Setting(SettingId::ScanTimeout, 750)));
+    link.close().unwrap();
+}
+
+#[test]
+fn readings_stream_while_watched() {
+    let (_, mut link) = connect();
+    responses(&mut link, Request::WatchReadings(true)).unwrap();
+    for _ in 0..10 {
+        let response = link.receive(Some(REPLY_TIMEOUT)).unwrap();
+        assert!(matches!(response, Response::Reading(reading) if reading.value < 1024));
+    }
+    // Closing stops them, and readings still on their way are passed over
+    link.close().unwrap();
+}
+
+/// Send a console command and collect the reply up to its final `ok`
+fn console(port: &mut Box<dyn serialport::SerialPort>, command: &[u8]) -> Vec<u8> {
+    port.write_all(command).unwrap();
+    let mut text = Vec::new();
+    let mut byte = [0];
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    while !text.ends_with(b"ok\r\n") {
+        assert!(
+            Instant::now() < deadline,
+            "The console didn't answer: {text:?}"
+        );
+        if port.read(&mut byte).is_ok() {
+            text.push(byte[0]);
+        }
+    }
+    text
+}
+
+#[test]
+fn closing_hands_the_port_back_to_the_console() {
+    let (path, link) = connect();
+    link.close().unwrap();
+    let mut port = serialport::new(&path, 115_200)
+        .timeout(Duration::from_millis(50))
+        .open()
+        .unwrap();
+    assert_eq!(
+        console(&mut port, b"get mode\r"),
+        b"get mode\r\nmode = fixture\r\nok\r\n"
+    );
+}
+
+#[test]
+fn stray_zero_byte_goes_back_to_the_console() {
+    let simulator = Simulator::new().unwrap();
+    let path = simulator.path().to_str().unwrap().to_string();
+    thread::spawn(move || simulator.run(None));
+    let mut port = serialport::new(&path, 115_200)
+        .timeout(Duration::from_millis(50))
+        .open()
+        .unwrap();
+    // As if Ctrl-@ were typed at a terminal, with no frame following
+    port.write_all(&[0]).unwrap();
+    thread::sleep(Duration::from_millis(FIRST_FRAME_TIMEOUT.as_millis() + 100));
+    assert_eq!(
+        console(&mut port, b"get mode\r"),
+        b"get mode\r\nmode = fixture\r\nok\r\n"
+    );
+}
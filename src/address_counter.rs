@@ -0,0 +1,104 @@
+//! Address counter module hands out addresses one after another in continuous mode, carrying on
+//! from where the last session stopped.
+//!
+//! The next address is kept in the flash store under [`Key::NextAddress`] as a little endian
+//! u32, a whole flash word. Earlier firmware kept it as a u16, which is still read.
+//!
+//! An address is used up as soon as it is handed out: the counter moves on in flash before the
+//! address is returned, so a reset part way through programming, or a sensor that fails after it
+//! may have taken its new address, can never lead to the same address being handed out twice.
+//! Only wrapping at the limit of the [`ContinuousPlan`] starts the addresses again.
+
+use defmt::Format;
+
+use crate::{
+    drivers::flash::Flash,
+    settings::{AddressPlan, AtLimit, ContinuousPlan},
+    storage::{Key, KvStore, StorageError},
+};
+
+/// Why no address could be handed out
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum AddressError {
+    /// Every address up to the limit has been handed out and the plan says to stop
+    UsedUp,
+    /// The plan has no valid addresses
+    InvalidPlan,
+    /// The counter couldn't be saved, so the address can't be handed out safely
+    Storage(StorageError),
+}
+
+impl From<StorageError> for AddressError {
+    fn from(error: StorageError) -> Self {
+        AddressError::Storage(error)
+    }
+}
+
+/// The persisted next address for continuous mode
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub struct AddressCounter {
+    /// The next address to hand out, if it falls within the plan. Below the plan's first
+    /// address starts from there and above its limit means every address has been handed out
+    next: u32,
+}
+
+impl AddressCounter {
+    /// Load the counter from the store. If no address has been handed out yet, the first comes
+    /// from the plan
+    pub fn load<F: Flash>(store: &mut KvStore<F>) -> Result<Self, StorageError> {
+        let mut bytes = [0; 4];
+        let next = match store.read(Key::NextAddress, &mut bytes)? {
+            Some(4) => u32::from_le_bytes(bytes),
+            Some(2) => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            _ => 0,
+        };
+        Ok(Self { next })
+    }
+
+    /// The address that [`Self::take`] would hand out next under `plan`, or None if there
+    /// isn't one
+    pub fn peek(&self, plan: &ContinuousPlan) -> Option<u8> {
+        let (first, limit) = Self::range(plan)?;
+        match self.next.max(first) {
+            next if next <= limit => Some(next as u8),
+            _ if plan.at_limit == AtLimit::Wrap => Some(first as u8),
+            _ => None,
+        }
+    }
+
+    /// The first and last addresses of `plan` that are valid I2C addresses, or None if there
+    /// are none
+    fn range(plan: &ContinuousPlan) -> Option<(u32, u32)> {
+        let first = plan.first.max(*AddressPlan::VALID.start()) as u32;
+        let limit = plan.limit.min(*AddressPlan::VALID.end()) as u32;
+        (first <= limit).then_some((first, limit))
+    }
+
+    /// Hand out the next address, saving the counter before returning it
+    pub fn take<F: Flash>(
+        &mut self,
+        store: &mut KvStore<F>,
+        plan: &ContinuousPlan,
+    ) -> Result<u8, AddressError> {
+        if Self::range(plan).is_none() {
+            return Err(AddressError::InvalidPlan);
+        }
+        let address = self.peek(plan).ok_or(AddressError::UsedUp)?;
+        let next = address as u32 + 1;
+        store.write(Key::NextAddress, &next.to_le_bytes())?;
+        self.next = next;
+        Ok(address)
+    }
+
+    /// Start handing out addresses from `address` again. Only for when the sensors given the
+    /// addresses from there on are known to be gone
+    pub fn restart<F: Flash>(
+        &mut self,
+        store: &mut KvStore<F>,
+        address: u8,
+    ) -> Result<(), StorageError> {
+        store.write(Key::NextAddress, &(address as u32).to_le_bytes())?;
+        self.next = address as u32;
+        Ok(())
+    }
+}
@@ -2,20 +2,31 @@
 //!
 //! This module contains implementations for various LED animations including:
 //! - Sparkle animations that create random brightness variations of a single colour
+//! - Breathe animations that smoothly ramp a single colour between two brightness levels
+//! - Fade animations that ramp from one colour to another
+//! - Sequence animations that play a table of [`Keyframe`]s, for blink codes and the like
 //! - Presence animations that display and rotate colours representing visible souls
 
-use crate::drivers::neopixel::LedBuffer;
+use crate::{
+    ANIMATION_UPDATE, RgbRate,
+    drivers::leds::{LedBuffer, PixelRange},
+};
 use defmt::{Format, Formatter, write};
 use embassy_time::{Duration, Instant};
 use smart_leds::RGB8;
 
-/// Represents different types of animations that can be displayed on the LED strip
-#[derive(Clone)]
+/// Represents different types of animations that can be displayed on the LED strip. Two
+/// animations are equal if they are configured the same, however far through they are
+#[derive(Clone, PartialEq)]
 pub enum Animation {
     /// Animation that creates a sparkling effect with random brightness variations
     Sparkle(SparkleAnimation),
-    // /// Animation that oscillates brightness to create a breathing effect
-    // Breathe(BreatheAnimation),
+    /// Animation that oscillates brightness to create a breathing effect
+    Breathe(BreatheAnimation),
+    /// Animation that ramps from one colour to another
+    Fade(FadeAnimation),
+    /// Animation that plays a table of keyframes
+    Sequence(SequenceAnimation),
 }
 
 /// Checks if the given animation can be interrupted
@@ -28,7 +39,9 @@ pub enum Animation {
 pub fn is_interruptable(anim: &Animation) -> bool {
     match anim {
         Animation::Sparkle(s) => s.is_interruptable(),
-        // Animation::Breathe(s) => s.is_interruptable(),
+        Animation::Breathe(s) => s.is_interruptable(),
+        Animation::Fade(s) => s.is_interruptable(),
+        Animation::Sequence(s) => s.is_interruptable(),
     }
 }
 
@@ -43,7 +56,25 @@ pub fn is_interruptable(anim: &Animation) -> bool {
 pub fn next_buffer(anim: &mut Animation) -> Option<LedBuffer> {
     match anim {
         Animation::Sparkle(s) => s.next(),
-        // Animation::Breathe(s) => s.next(),
+        Animation::Breathe(s) => s.next(),
+        Animation::Fade(s) => s.next(),
+        Animation::Sequence(s) => s.next(),
+    }
+}
+
+/// Helper function to get the pixels an animation draws to regardless of animation
+///
+/// # Arguments
+/// * `anim` - Reference to the Animation to check
+///
+/// # Returns
+/// The range of pixels in the LED string that the animation lights
+pub fn pixels(anim: &Animation) -> PixelRange {
+    match anim {
+        Animation::Sparkle(s) => s.pixels,
+        Animation::Breathe(s) => s.pixels,
+        Animation::Fade(s) => s.pixels,
+        Animation::Sequence(s) => s.pixels,
     }
 }
 
@@ -51,7 +82,9 @@ impl Format for Animation {
     fn format(&self, fmt: Formatter) {
         match self {
             Animation::Sparkle(_) => write!(fmt, "Sparkle"),
-            // Animation::Breathe(_) => write!(fmt, "Breathe"),
+            Animation::Breathe(_) => write!(fmt, "Breathe"),
+            Animation::Fade(_) => write!(fmt, "Fade"),
+            Animation::Sequence(_) => write!(fmt, "Sequence"),
         }
     }
 }
@@ -59,8 +92,8 @@ impl Format for Animation {
 pub trait Interruptable {
     /// If this is true then the animation is interruptable before its iterator returns None
     /// If a new soul arrives, we want it to sparkle for a few seconds and not be interrupted
-    /// by a new arrival. Those can sit in the queue until this one is done. Be careful here
-    /// as this could block all future animations sitting in the queue.
+    /// by a new arrival. Those can sit in the queue until this one is done, unless they have a
+    /// higher [`crate::queue::Priority`].
     fn is_interruptable(&self) -> bool;
 }
 
@@ -76,6 +109,8 @@ pub struct SparkleAnimation {
     expires: Option<Instant>,
     /// Random number generator for the sparkle effect
     rng: fastrand::Rng,
+    /// The pixels to sparkle. Pixels outside this range are left off
+    pixels: PixelRange,
 }
 
 impl Iterator for SparkleAnimation {
@@ -90,7 +125,7 @@ impl Iterator for SparkleAnimation {
 
         if !done {
             let mut buffer = LedBuffer::default();
-            for led in buffer.iter_mut() {
+            for led in buffer[self.pixels.indices()].iter_mut() {
                 let b = self.rng.u8(0..255);
                 *led = set_brightness(b, self.colour);
             }
@@ -101,6 +136,12 @@ impl Iterator for SparkleAnimation {
     }
 }
 
+impl PartialEq for SparkleAnimation {
+    fn eq(&self, other: &Self) -> bool {
+        self.colour == other.colour && self.expires == other.expires && self.pixels == other.pixels
+    }
+}
+
 impl Interruptable for SparkleAnimation {
     fn is_interruptable(&self) -> bool {
         self.expires.is_none()
@@ -123,86 +164,457 @@ impl SparkleAnimation {
             colour,
             expires,
             rng: fastrand::Rng::with_seed(seed),
+            pixels: PixelRange::ALL,
+        }
+    }
+
+    /// Restrict the animation to the given pixels. By default it covers the whole string
+    pub fn on(mut self, pixels: PixelRange) -> Self {
+        self.pixels = pixels;
+        self
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Ramps one colour up and down between a minimum and maximum brightness level. The speed of
+/// the ramp is set by an [`RgbRate`], which is the brightness step applied on every animation
+/// tick. Like [`SparkleAnimation`] it returns `Some(buffer)` until the expiry time is reached
+/// if one was specified
+#[derive(Clone)]
+pub struct BreatheAnimation {
+    /// The colour to breathe
+    colour: RGB8,
+    /// Current brightness value
+    brightness: u8,
+    /// Current direction of brightness change
+    direction: Direction,
+    /// Amount to change brightness by in each iteration
+    step: i16,
+    /// Minimum brightness value to not go below
+    min: u8,
+    /// Maximum brightness value to not go above
+    max: u8,
+    /// The system time at which the animation should expire. If it is None, the animation
+    /// will run but will mark itself as interruptable.
+    expires: Option<Instant>,
+    /// The pixels to breathe. Pixels outside this range are left off
+    pixels: PixelRange,
+}
+
+impl BreatheAnimation {
+    /// Create a BreatheAnimation starting at the minimum brightness and ramping up.
+    ///
+    /// # Arguments
+    /// * `colour` - The base RGB colour to breathe
+    /// * `min` - Minimum brightness value to not go below
+    /// * `max` - Maximum brightness value to not go above
+    /// * `rate` - How quickly the brightness ramps between `min` and `max`
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    ///
+    /// The animation will be interruptible if no ttl is provided
+    pub(crate) fn new(
+        colour: RGB8,
+        min: u8,
+        max: u8,
+        rate: RgbRate,
+        ttl: Option<Duration>,
+    ) -> Self {
+        let (min, max) = if min <= max { (min, max) } else { (max, min) };
+        let expires = ttl.map(|t| Instant::now() + t);
+        Self {
+            colour,
+            brightness: min,
+            direction: Direction::Up,
+            step: rate as i16,
+            min,
+            max,
+            expires,
+            pixels: PixelRange::ALL,
+        }
+    }
+
+    /// Restrict the animation to the given pixels. By default it covers the whole string
+    pub fn on(mut self, pixels: PixelRange) -> Self {
+        self.pixels = pixels;
+        self
+    }
+
+    /// Create a breather starting at a random brightness between `min` and `max` in a random
+    /// direction, so several breathers started together do not pulse in step.
+    ///
+    /// # Arguments
+    /// * `colour` - The base RGB colour to breathe
+    /// * `min` - Minimum brightness value to not go below
+    /// * `max` - Maximum brightness value to not go above
+    /// * `rate` - How quickly the brightness ramps between `min` and `max`
+    /// * `ttl` - Optional Duration that specifies how long the animation should run. None implies indefinitely
+    #[allow(unused)]
+    pub fn new_random(
+        colour: RGB8,
+        min: u8,
+        max: u8,
+        rate: RgbRate,
+        ttl: Option<Duration>,
+    ) -> Self {
+        let seed = Instant::now().as_ticks();
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let mut breathe = Self::new(colour, min, max, rate, ttl);
+        breathe.brightness = rng.u8(breathe.min..=breathe.max);
+        breathe.direction = if rng.bool() {
+            Direction::Up
+        } else {
+            Direction::Down
+        };
+        breathe
+    }
+
+    /// Next brightness value for this breathe animation
+    fn next_brightness(&mut self) -> u8 {
+        match self.direction {
+            Direction::Up => {
+                self.brightness = clip_max(self.brightness as i16 + self.step, self.max);
+                if self.brightness == self.max {
+                    self.direction = Direction::Down;
+                }
+            }
+            Direction::Down => {
+                self.brightness = clip_min(self.brightness as i16 - self.step, self.min);
+                if self.brightness == self.min {
+                    self.direction = Direction::Up;
+                }
+            }
+        };
+        self.brightness
+    }
+}
+
+impl Iterator for BreatheAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let done = match self.expires {
+            Some(exp) if Instant::now() < exp => false, // Have expiration but not expired so not done
+            None => false,                              // No expiration is never done
+            _ => true,                                  // All other cases are done
+        };
+
+        if !done {
+            let b = self.next_brightness();
+            let mut buffer = LedBuffer::default();
+            buffer[self.pixels.indices()].fill(set_brightness(b, self.colour));
+            Some(buffer)
+        } else {
+            None
         }
     }
 }
 
-// #[derive(Clone)]
-// pub enum Direction {
-//     Up,
-//     Down,
-// }
-
-// #[derive(Clone)]
-// pub struct BreatheAnimation {
-//     brightness: u8,
-//     direction: Direction,
-//     step: i16,
-//     min: u8,
-// }
-
-// impl BreatheAnimation {
-//     /// Create a BreatheAnimation.
-//     ///
-//     /// # Parameters
-//     /// * `brightness` - Initial brightness value (0-255)
-//     /// * `direction` - Initial direction of brightness change (Up or Down)
-//     /// * `step` - Amount to change brightness by in each iteration
-//     /// * `min` - Minimum brightness value to not go below
-//     #[allow(unused)]
-//     pub(crate) fn new(brightness: u8, direction: Direction, step: u8, min: u8) -> Self {
-//         Self {
-//             brightness,
-//             direction,
-//             step: step as i16,
-//             min,
-//         }
-//     }
-
-//     /// Create a throbber starting at a random brightness and vary it with a random step in a
-//     /// random direction.
-//     ///
-//     /// # Parameters
-//     /// * `min` - Minimum brightness value to not go below
-//     #[allow(unused)]
-//     pub fn new_random(min: u8) -> Self {
-//         let seed = Instant::now().as_ticks();
-//         let mut rng = fastrand::Rng::with_seed(seed);
-//         Self {
-//             brightness: rng.u8(min..),
-//             direction: if rng.bool() {
-//                 Direction::Up
-//             } else {
-//                 Direction::Down
-//             },
-//             step: rng.i16(8..64),
-//             min,
-//         }
-//     }
-// }
-
-// impl Iterator for BreatheAnimation {
-//     type Item = u8;
-
-//     /// Next brightness value for this breathe animation
-//     fn next(&mut self) -> Option<Self::Item> {
-//         match self.direction {
-//             Direction::Up => {
-//                 self.brightness = clip(self.brightness as i16 + self.step);
-//                 if self.brightness == 255 {
-//                     self.direction = Direction::Down;
-//                 }
-//             }
-//             Direction::Down => {
-//                 self.brightness = clip_min(self.brightness as i16 - self.step, self.min);
-//                 if self.brightness == self.min {
-//                     self.direction = Direction::Up;
-//                 }
-//             }
-//         };
-//         Some(self.brightness)
-//     }
-// }
+impl PartialEq for BreatheAnimation {
+    fn eq(&self, other: &Self) -> bool {
+        self.colour == other.colour
+            && self.step == other.step
+            && self.min == other.min
+            && self.max == other.max
+            && self.expires == other.expires
+            && self.pixels == other.pixels
+    }
+}
+
+impl Interruptable for BreatheAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.expires.is_none()
+    }
+}
+
+/// Ramps from one colour to another over a fixed duration, then finishes. The colours are mixed
+/// in linear light so the ramp looks even once the driver applies gamma correction
+#[derive(Clone)]
+pub struct FadeAnimation {
+    /// The colour at the start of the ramp
+    from: RGB8,
+    /// The colour at the end of the ramp
+    to: RGB8,
+    /// The number of animation ticks already shown
+    step: u32,
+    /// The number of animation ticks the ramp takes
+    steps: u32,
+    /// The pixels to fade. Pixels outside this range are left off
+    pixels: PixelRange,
+}
+
+impl FadeAnimation {
+    /// Create a new FadeAnimation.
+    ///
+    /// # Arguments
+    /// * `from` - The colour at the start of the ramp
+    /// * `to` - The colour at the end of the ramp
+    /// * `duration` - How long the ramp takes. It is rounded to whole animation ticks
+    pub fn new(from: RGB8, to: RGB8, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            step: 0,
+            steps: ticks(duration),
+            pixels: PixelRange::ALL,
+        }
+    }
+
+    /// Restrict the animation to the given pixels. By default it covers the whole string
+    pub fn on(mut self, pixels: PixelRange) -> Self {
+        self.pixels = pixels;
+        self
+    }
+}
+
+impl Iterator for FadeAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.step < self.steps {
+            self.step += 1;
+            let t = progress(self.step, self.steps);
+            let mut buffer = LedBuffer::default();
+            buffer[self.pixels.indices()].fill(lerp_linear(self.from, self.to, t));
+            Some(buffer)
+        } else {
+            None
+        }
+    }
+}
+
+impl PartialEq for FadeAnimation {
+    fn eq(&self, other: &Self) -> bool {
+        self.from == other.from
+            && self.to == other.to
+            && self.steps == other.steps
+            && self.pixels == other.pixels
+    }
+}
+
+impl Interruptable for FadeAnimation {
+    /// A fade is short and ends on its own, so let it finish
+    fn is_interruptable(&self) -> bool {
+        false
+    }
+}
+
+/// One step of a [`SequenceAnimation`]. The colour fades in from the previous keyframe's colour,
+/// then holds. Times are rounded down to whole animation ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// The colour to fade to and hold
+    pub colour: RGB8,
+    /// How long to fade from the previous colour. Zero switches straight to the new colour
+    pub fade: Duration,
+    /// How long to hold the colour once the fade is done
+    pub hold: Duration,
+}
+
+impl Keyframe {
+    /// Switch straight to `colour` and hold it for `hold_ms` milliseconds
+    pub const fn hold(colour: [u8; 3], hold_ms: u64) -> Self {
+        Self::fade(colour, 0, hold_ms)
+    }
+
+    /// Fade to `colour` over `fade_ms` milliseconds, then hold it for `hold_ms` milliseconds
+    pub const fn fade(colour: [u8; 3], fade_ms: u64, hold_ms: u64) -> Self {
+        Self {
+            colour: RGB8::new(colour[0], colour[1], colour[2]),
+            fade: Duration::from_millis(fade_ms),
+            hold: Duration::from_millis(hold_ms),
+        }
+    }
+}
+
+/// Plays a table of [`Keyframe`]s from start to end, then repeats it. New blink codes can be
+/// declared as a const table rather than a new animation type, for example two red blinks and a
+/// pause:
+/// ```ignore
+/// const TWO_RED_BLINKS: [Keyframe; 4] = [
+///     Keyframe::hold([255, 0, 0], 250),
+///     Keyframe::hold([0, 0, 0], 250),
+///     Keyframe::hold([255, 0, 0], 250),
+///     Keyframe::hold([0, 0, 0], 1000),
+/// ];
+/// ```
+#[derive(Clone)]
+pub struct SequenceAnimation {
+    /// The keyframes to play in order
+    frames: &'static [Keyframe],
+    /// How many times to play the whole table. If it is None, the animation will repeat forever
+    /// but will mark itself as interruptable.
+    repeats: Option<u16>,
+    /// The number of times the whole table has been played
+    played: u16,
+    /// Index of the keyframe being played
+    index: usize,
+    /// The number of animation ticks already shown of the current keyframe
+    tick: u32,
+    /// The colour the current keyframe fades from
+    from: RGB8,
+    /// The pixels to draw. Pixels outside this range are left off
+    pixels: PixelRange,
+}
+
+impl SequenceAnimation {
+    /// Create a new SequenceAnimation.
+    ///
+    /// # Arguments
+    /// * `frames` - The keyframes to play in order. The first one fades in from off
+    /// * `repeats` - How many times to play the whole table. None implies forever
+    ///
+    /// The animation will be interruptible if it repeats forever
+    pub fn new(frames: &'static [Keyframe], repeats: Option<u16>) -> Self {
+        Self {
+            frames,
+            repeats,
+            played: 0,
+            index: 0,
+            tick: 0,
+            from: RGB8::default(),
+            pixels: PixelRange::ALL,
+        }
+    }
+
+    /// Restrict the animation to the given pixels. By default it covers the whole string
+    pub fn on(mut self, pixels: PixelRange) -> Self {
+        self.pixels = pixels;
+        self
+    }
+}
+
+impl Iterator for SequenceAnimation {
+    type Item = LedBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        if self.index >= self.frames.len() {
+            // Finished a pass through the table, so go round again if we have any repeats left
+            self.played = self.played.saturating_add(1);
+            if self.repeats.is_some_and(|r| self.played >= r) {
+                return None;
+            }
+            self.index = 0;
+        }
+
+        let frame = &self.frames[self.index];
+        let fade = whole_ticks(frame.fade);
+        // Every keyframe shows for at least one tick so a table of zero times can't spin
+        let total = (fade + whole_ticks(frame.hold)).max(1);
+        self.tick += 1;
+        let colour = if self.tick <= fade {
+            lerp_linear(self.from, frame.colour, progress(self.tick, fade))
+        } else {
+            frame.colour
+        };
+        if self.tick >= total {
+            self.from = frame.colour;
+            self.index += 1;
+            self.tick = 0;
+        }
+
+        let mut buffer = LedBuffer::default();
+        buffer[self.pixels.indices()].fill(colour);
+        Some(buffer)
+    }
+}
+
+impl PartialEq for SequenceAnimation {
+    fn eq(&self, other: &Self) -> bool {
+        self.frames == other.frames && self.repeats == other.repeats && self.pixels == other.pixels
+    }
+}
+
+impl Interruptable for SequenceAnimation {
+    fn is_interruptable(&self) -> bool {
+        self.repeats.is_none()
+    }
+}
+
+/// Cross-fades from the last frame shown to whatever is shown next. The display task starts one
+/// whenever the current animation is replaced, so changes never jump straight to the new colour.
+#[derive(Clone)]
+pub struct CrossFade {
+    /// The frame shown when the cross-fade started
+    from: LedBuffer,
+    /// The number of animation ticks already shown
+    step: u32,
+    /// The number of animation ticks the cross-fade takes
+    steps: u32,
+}
+
+impl CrossFade {
+    /// Create a new CrossFade.
+    ///
+    /// # Arguments
+    /// * `from` - The outgoing frame
+    /// * `duration` - How long the cross-fade takes. It is rounded to whole animation ticks
+    pub fn new(from: LedBuffer, duration: Duration) -> Self {
+        Self {
+            from,
+            step: 0,
+            steps: ticks(duration),
+        }
+    }
+
+    /// Mix the outgoing frame into the incoming one for this tick.
+    ///
+    /// # Returns
+    /// False once the cross-fade is complete and `incoming` was left untouched
+    pub fn apply(&mut self, incoming: &mut LedBuffer) -> bool {
+        if self.step >= self.steps {
+            return false;
+        }
+        self.step += 1;
+        let t = progress(self.step, self.steps);
+        for (pixel, from) in incoming.iter_mut().zip(self.from.iter()) {
+            *pixel = lerp_linear(*from, *pixel, t);
+        }
+        true
+    }
+}
+
+/// The number of whole animation ticks in `duration`, at least one
+fn ticks(duration: Duration) -> u32 {
+    whole_ticks(duration).max(1)
+}
+
+/// The number of whole animation ticks in `duration`, rounded down
+fn whole_ticks(duration: Duration) -> u32 {
+    (duration.as_millis() / ANIMATION_UPDATE) as u32
+}
+
+/// How far through `steps` we are after `step`, from 0 to 255
+fn progress(step: u32, steps: u32) -> u8 {
+    (step.min(steps) * 255 / steps) as u8
+}
+
+/// Convert a gamma encoded channel value to linear light. This models the LED response as a
+/// square law, which is close to the gamma correction the driver applies and needs no floats.
+pub fn to_linear(v: u8) -> u16 {
+    v as u16 * v as u16
+}
+
+/// Convert a linear light value back to a gamma encoded channel value
+pub fn from_linear(v: u16) -> u8 {
+    v.isqrt() as u8
+}
+
+/// Mix from colour `a` to colour `b` by `t` in linear light, where 0 gives `a` and 255 gives `b`
+pub fn lerp_linear(a: RGB8, b: RGB8, t: u8) -> RGB8 {
+    let mix = |a: u8, b: u8| {
+        let (a, b) = (to_linear(a) as u32, to_linear(b) as u32);
+        from_linear(((a * (255 - t as u32) + b * t as u32) / 255) as u16)
+    };
+    RGB8::new(mix(a.r, b.r), mix(a.g, b.g), mix(a.b, b.b))
+}
 
 pub fn set_brightness(brightness: u8, pixel: RGB8) -> RGB8 {
     if brightness == 0 {
@@ -233,3 +645,8 @@ pub fn clip(v: i16) -> u8 {
 pub fn clip_min(v: i16, min: u8) -> u8 {
     if v < min as i16 { min } else { v as u8 }
 }
+
+/// Clip to a maximum value
+pub fn clip_max(v: i16, max: u8) -> u8 {
+    if v > max as i16 { max } else { clip(v) }
+}
@@ -7,40 +7,101 @@
 )]
 
 // use alloc::{boxed::Box, rc::Rc};
-use defmt::info;
+use defmt::{Format, error, info, warn};
 use embassy_executor::Spawner;
-use embassy_futures::select::{Either, select};
+use embassy_futures::select::{Either, Either4, select, select4};
 use embassy_sync::channel::Channel;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Ticker, Timer};
+#[cfg(feature = "rgb-led")]
+use esp_hal::gpio::{Level, Output, OutputConfig};
 use esp_hal::{
     Async,
     Config,
     clock::CpuClock,
     gpio::{Input, InputConfig, Pull},
     i2c::master::{Config as I2cConfig, I2c},
-    rmt::Rmt,
     // rng::Rng,
-    time::Rate,
     timer::{systimer::SystemTimer /*timg::TimerGroup,*/},
+    usb_serial_jtag::UsbSerialJtag,
 };
-use panic_rtt_target as _;
+use jig_common::{
+    console::{Command, CommandError, Jig, LedHealth, RunSummary, SettingId, dispatch},
+    protocol::{Request, serve},
+};
+#[cfg(feature = "rgb-led")]
+use singletact_programing_jig::drivers::rgb_led::RgbLedOutput;
+#[cfg(not(feature = "rgb-led"))]
+use singletact_programing_jig::drivers::ws2812::Ws2812Output;
 use singletact_programing_jig::{
-    drivers::{button::wait_for_press, neopixel::LedDriver},
+    SOCKET_COUNT,
+    address_counter::AddressCounter,
+    compositor::Overlay,
+    counters::Counters,
+    drivers::{
+        LedBackend,
+        button::{ActiveLevel, DebounceConfig, DebouncedButton},
+        flash::EspFlash,
+        leds::{LedConfig, LedDriver, led_diagnostics},
+    },
+    gestures::{GestureTimings, Navigation},
+    panic_record::{self, PanicRecord},
+    result_log::{
+        ErrorCode, LogCursor, RESULTS_PARTITION_OFFSET, RESULTS_PARTITION_SECTORS, ResultLog,
+        ResultRecord, Verdict,
+    },
+    settings::{ContinuousPlan, ProgramMode, Settings, SettingsFault},
+    status::{JigStatus, SocketState},
+    storage::{KvStore, STORE_PARTITION_OFFSET, STORE_PARTITION_SECTORS},
+    tasks::button::{
+        ButtonChannel, ButtonEvent, ButtonId, EdgeChannel, Gesture, button_task, gesture_task,
+    },
+    tasks::console::{
+        CommandChannel, ConsoleReply, Incoming, Output, ReplyChannel, ReplyChannelSender,
+        console_task,
+    },
     tasks::display::{
-        DisplayChannel, DisplayChannelReceiver, /*DisplayChannelSender, */ DisplayState,
-        display_task,
+        DisplayChannel, DisplayChannelReceiver, DisplayChannelSender, DisplayState, display_task,
+    },
+    tasks::inputs::{
+        ExternalInput, InputChannel, InputChannelReceiver, InputEvent, InputRole, input_task,
+        interlock_closed,
     },
 };
+#[cfg(feature = "rotary-encoder")]
+use singletact_programing_jig::{drivers::encoder::RotaryEncoder, tasks::button::encoder_task};
 
 use static_cell::StaticCell;
 
+#[cfg(all(feature = "rgb-led", feature = "rotary-encoder"))]
+compile_error!("The rgb-led and rotary-encoder features both use GPIO0/1/4");
+
 /// Communicate with the display task using this channel and the DisplayState enum
 // static DISPLAY_SENDER: StaticCell<DisplayChannelSender> = StaticCell::new();
 static DISPLAY_RECEIVER: StaticCell<DisplayChannelReceiver> = StaticCell::new();
 static DISPLAY_CHANNEL: StaticCell<DisplayChannel> = StaticCell::new();
 
 /// Our LED driver that underlies the display task
-static LED_DRIVER: StaticCell<LedDriver> = StaticCell::new();
+static LED_DRIVER: StaticCell<LedDriver<Option<LedBackend>>> = StaticCell::new();
+
+/// Button presses and releases from the button tasks
+static EDGE_CHANNEL: StaticCell<EdgeChannel> = StaticCell::new();
+
+/// Button events from the gesture task
+static BUTTON_CHANNEL: StaticCell<ButtonChannel> = StaticCell::new();
+
+/// Events from the foot pedal, abort and interlock inputs
+static INPUT_CHANNEL: StaticCell<InputChannel> = StaticCell::new();
+
+/// Commands and protocol requests from the USB serial port
+static COMMAND_CHANNEL: StaticCell<CommandChannel> = StaticCell::new();
+
+/// Output for the console task to write to the USB serial port
+static REPLY_CHANNEL: StaticCell<ReplyChannel> = StaticCell::new();
+
+/// The last panic, kept in RAM that survives the reset after it until the next boot moves it
+/// to flash. See [`PanicRecord`]
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut PANIC_SLOT: [u8; panic_record::ENCODED_LEN] = [0; panic_record::ENCODED_LEN];
 
 /// I2c bus shared between display and sensors
 static I2C_BUS: StaticCell<I2cBus> = StaticCell::new(); // I2c<'static, Async>
@@ -67,10 +128,27 @@ async fn main(spawner: Spawner) {
     let receiver = DISPLAY_RECEIVER.init(display_channel.receiver());
     // let mut rng = Rng::new(peripherals.RNG);
 
-    let rmt = Rmt::new(peripherals.RMT, Rate::from_mhz(80))
-        .expect("Failed to initialise RMT0")
-        .into_async();
-    let led_driver = LED_DRIVER.init(LedDriver::new(rmt, peripherals.GPIO2));
+    // The LEDs only show status, so a broken string must not stop the jig programming sensors
+    #[cfg(not(feature = "rgb-led"))]
+    let backend = Ws2812Output::new(peripherals.RMT, peripherals.GPIO2.into())
+        .inspect_err(|e| {
+            error!(
+                "MAIN: Failed to initialise LED driver, carrying on without it: {}",
+                e
+            )
+        })
+        .ok();
+    #[cfg(feature = "rgb-led")]
+    let backend = {
+        let config = OutputConfig::default();
+        Some(RgbLedOutput::new(
+            Output::new(peripherals.GPIO0, Level::Low, config),
+            Output::new(peripherals.GPIO1, Level::Low, config),
+            Output::new(peripherals.GPIO4, Level::Low, config),
+            false,
+        ))
+    };
+    let led_driver = LED_DRIVER.init(LedDriver::new(backend, LedConfig::default()));
     let i2c = I2C_BUS.init(I2cBus::new(
         I2c::new(peripherals.I2C0, I2cConfig::default())
             .unwrap()
@@ -84,29 +162,771 @@ async fn main(spawner: Spawner) {
         .expect("Failed to spawn display task");
 
     // Set up buttons for the functions we need
+    let edge_channel = EDGE_CHANNEL.init(Channel::new());
+    let button_channel = BUTTON_CHANNEL.init(Channel::new());
+    // Both buttons switch to ground against the internal pull-up
     let config = InputConfig::default().with_pull(Pull::Up);
-    let mut button0 = Input::new(peripherals.GPIO9, config);
-    let mut button1 = Input::new(peripherals.GPIO3, config);
+    let debounce = DebounceConfig::default().with_active(ActiveLevel::Low);
+    spawner
+        .spawn(button_task(
+            ButtonId::Button0,
+            DebouncedButton::new(Input::new(peripherals.GPIO9, config), debounce),
+            edge_channel.sender(),
+        ))
+        .expect("Failed to spawn button task");
+    spawner
+        .spawn(button_task(
+            ButtonId::Button1,
+            DebouncedButton::new(Input::new(peripherals.GPIO3, config), debounce),
+            edge_channel.sender(),
+        ))
+        .expect("Failed to spawn button task");
+    let timings = GestureTimings::default();
+    spawner
+        .spawn(gesture_task(
+            edge_channel.receiver(),
+            timings,
+            button_channel.sender(),
+        ))
+        .expect("Failed to spawn gesture task");
+    #[cfg(feature = "rotary-encoder")]
+    spawner
+        .spawn(encoder_task(
+            RotaryEncoder::new(
+                Input::new(peripherals.GPIO0, config),
+                Input::new(peripherals.GPIO1, config),
+                4,
+            ),
+            DebouncedButton::new(Input::new(peripherals.GPIO4, config), debounce),
+            timings.long_press,
+            button_channel.sender(),
+        ))
+        .expect("Failed to spawn encoder task");
+    let buttons = button_channel.receiver();
+
+    // External inputs, all switching to ground against the internal pull-up
+    let input_channel = INPUT_CHANNEL.init(Channel::new());
+    let pedal = DebounceConfig::default().with_window(Duration::from_millis(50));
+    spawner
+        .spawn(input_task(
+            ExternalInput::new(
+                InputRole::Start,
+                DebouncedButton::new(Input::new(peripherals.GPIO7, config), pedal),
+            ),
+            input_channel.sender(),
+        ))
+        .expect("Failed to spawn input task");
+    spawner
+        .spawn(input_task(
+            ExternalInput::new(
+                InputRole::Abort,
+                DebouncedButton::new(Input::new(peripherals.GPIO10, config), debounce),
+            ),
+            input_channel.sender(),
+        ))
+        .expect("Failed to spawn input task");
+    // The lid switch closes when the lid is shut
+    #[cfg(feature = "lid-interlock")]
+    spawner
+        .spawn(input_task(
+            ExternalInput::new(
+                InputRole::Interlock,
+                DebouncedButton::new(Input::new(peripherals.GPIO20, config), debounce),
+            ),
+            input_channel.sender(),
+        ))
+        .expect("Failed to spawn input task");
+    let inputs = input_channel.receiver();
+
+    // Command console on the USB serial port
+    let command_channel = COMMAND_CHANNEL.init(Channel::new());
+    let reply_channel = REPLY_CHANNEL.init(Channel::new());
+    spawner
+        .spawn(console_task(
+            UsbSerialJtag::new(peripherals.USB_DEVICE).into_async(),
+            command_channel.sender(),
+            reply_channel.receiver(),
+        ))
+        .expect("Failed to spawn console task");
+    let commands = command_channel.receiver();
+    let replies = reply_channel.sender();
+
+    let mut store = KvStore::open(
+        EspFlash::new(),
+        STORE_PARTITION_OFFSET,
+        STORE_PARTITION_SECTORS,
+    );
+    let loaded = match &mut store {
+        Ok(store) => Settings::load(store),
+        Err(e) => Err(SettingsFault::Unreadable(*e)),
+    };
+    // The jig still programs sensors without a log, it just can't keep a record of them
+    let log = ResultLog::open(
+        EspFlash::new(),
+        RESULTS_PARTITION_OFFSET,
+        RESULTS_PARTITION_SECTORS,
+    )
+    .inspect_err(|e| warn!("MAIN: Failed to open result log: {}", e))
+    .ok();
+    // A panic before the last reset left its record in retained RAM. Move it to flash, where
+    // it stays until cleared
+    // Safety: the panic handler is the only other user of the slot, and it never returns
+    let retained = unsafe { (&raw const PANIC_SLOT).read() };
+    unsafe { (&raw mut PANIC_SLOT).write([0; panic_record::ENCODED_LEN]) };
+    let panicked = PanicRecord::from_bytes(&retained);
+    if let Some(record) = &panicked {
+        warn!("MAIN: Reset after a panic: {}", record);
+        if let Ok(store) = &mut store
+            && let Err(e) = record.save(store)
+        {
+            warn!("MAIN: Failed to save panic record: {}", e);
+        }
+    }
+    let mut history = History::new(store.ok(), log);
 
     info!("MAIN: Starting main loop");
     sender.send(DisplayState::Init).await;
+    let mut settings = match loaded {
+        Ok(settings) => settings,
+        Err(fault) => {
+            // Leave the warning on the OLED until the first run replaces it
+            sender.send(DisplayState::SettingsWarning(fault)).await;
+            Settings::default()
+        }
+    };
+    sender.send(DisplayState::Configure(settings)).await;
+    sender.send(DisplayState::Status(JigStatus::Idle)).await;
     let mut torch = false;
+    // The page on the OLED, while the operator is looking at one
+    let mut page: Option<Page> = None;
+    if panicked.is_some() {
+        sender.send(DisplayState::Panic(panicked)).await;
+        page = Some(Page::Panic);
+    }
+    let mut hours = Ticker::every(Duration::from_secs(60 * 60));
     loop {
-        match select(wait_for_press(&mut button0), wait_for_press(&mut button1)).await {
-            Either::First(_) => {
-                info!("MAIN: Toggling torch mode {}", torch);
-                torch ^= true;
-                sender.send(DisplayState::Torch(torch)).await;
+        let start = match select4(
+            buttons.receive(),
+            inputs.receive(),
+            hours.next(),
+            commands.receive(),
+        )
+        .await
+        {
+            Either4::First(event) if page.is_some() => {
+                let mut start = false;
+                page = match (page, event.navigation()) {
+                    (_, Some(Navigation::Back)) => {
+                        sender.send(DisplayState::Init).await;
+                        None
+                    }
+                    (Some(Page::Log(age)), Some(navigation)) => {
+                        browse_log(&sender, &mut history.log, Some(age), navigation).await
+                    }
+                    // Clearing takes a deliberate chord, so a start press can't lose the record
+                    (Some(Page::Panic), _) if event == ButtonEvent::Chord(Gesture::LongPress) => {
+                        info!("MAIN: Clearing last panic");
+                        history.clear_panic();
+                        sender.send(DisplayState::Panic(None)).await;
+                        page
+                    }
+                    // A start press leaves the record to be looked at again and starts the run
+                    (Some(Page::Panic), _)
+                        if event == ButtonEvent::Single(ButtonId::Button1, Gesture::Click) =>
+                    {
+                        sender.send(DisplayState::Init).await;
+                        start = true;
+                        None
+                    }
+                    (page, _) => page,
+                };
+                start
+            }
+            Either4::First(event) => {
+                let start = match event {
+                    ButtonEvent::Single(ButtonId::Button0, Gesture::Click) => {
+                        info!("MAIN: Toggling torch mode {}", torch);
+                        torch ^= true;
+                        sender.send(DisplayState::Torch(torch)).await;
+                        false
+                    }
+                    ButtonEvent::Single(ButtonId::Button1, Gesture::Click) => true,
+                    ButtonEvent::Chord(Gesture::Click) => {
+                        info!("MAIN: Browsing result log");
+                        page =
+                            browse_log(&sender, &mut history.log, None, Navigation::Select).await;
+                        false
+                    }
+                    ButtonEvent::Single(ButtonId::Button0, Gesture::LongPress) => {
+                        info!("MAIN: Showing statistics");
+                        sender
+                            .send(DisplayState::Stats(
+                                history.session.first_pass_yield(),
+                                history
+                                    .lifetime
+                                    .as_ref()
+                                    .and_then(Counters::first_pass_yield),
+                            ))
+                            .await;
+                        page = Some(Page::Stats);
+                        false
+                    }
+                    ButtonEvent::Single(ButtonId::Button1, Gesture::DoubleClick) => {
+                        info!("MAIN: Showing last panic");
+                        sender.send(DisplayState::Panic(history.last_panic())).await;
+                        page = Some(Page::Panic);
+                        false
+                    }
+                    ButtonEvent::Chord(Gesture::LongPress) => {
+                        info!("MAIN: Clearing run results");
+                        for i in 0..SOCKET_COUNT as u8 {
+                            sender.send(DisplayState::Socket(i, SocketState::Off)).await;
+                        }
+                        sender.send(DisplayState::Status(JigStatus::Idle)).await;
+                        false
+                    }
+                    _ => false,
+                };
+                info!("MAIN: Handled {}", event);
+                start
             }
-            Either::Second(_) => {
-                info!("MAIN: Starting device programming");
-                for i in 0..8 {
-                    sender.send(DisplayState::SetAddress(i)).await;
-                    Timer::after(Duration::from_secs(1)).await;
+            Either4::Second(event) => {
+                if event == InputEvent::InterlockClosed {
+                    sender.send(DisplayState::Status(JigStatus::Idle)).await;
+                }
+                info!("MAIN: Handled {}", event);
+                event == InputEvent::Start
+            }
+            Either4::Third(_) => {
+                history.hour_passed();
+                false
+            }
+            Either4::Fourth(incoming) => {
+                if matches!(
+                    incoming,
+                    Incoming::Command(Command::Program) | Incoming::Request(Request::StartRun)
+                ) {
+                    page = None;
+                }
+                let mut jig = ConsoleJig {
+                    display: &sender,
+                    inputs: &inputs,
+                    settings: &mut settings,
+                    history: &mut history,
+                    replies,
+                };
+                let mut reply = ConsoleReply(replies);
+                match incoming {
+                    Incoming::Command(command) => dispatch(command, &mut jig, &mut reply).await,
+                    Incoming::Request(request) => serve(request, &mut jig, &mut reply).await,
                 }
-                sender.send(DisplayState::Init).await;
+                info!("MAIN: Handled {}", incoming);
+                false
+            }
+        };
+        if start {
+            page = None;
+            // The display shows how the run ended
+            let _ = start_run(&sender, &inputs, &settings, &mut history).await;
+        }
+    }
+}
+
+/// Why a programming run stopped before it finished
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+enum RunAborted {
+    /// The operator pressed abort
+    Requested,
+    /// The lid was opened
+    InterlockOpen,
+}
+
+impl RunAborted {
+    const fn error_code(&self) -> ErrorCode {
+        match self {
+            RunAborted::Requested => ErrorCode::AbortRequested,
+            RunAborted::InterlockOpen => ErrorCode::InterlockOpen,
+        }
+    }
+
+    const fn command_error(&self) -> CommandError {
+        match self {
+            RunAborted::Requested => CommandError::Aborted,
+            RunAborted::InterlockOpen => CommandError::InterlockOpen,
+        }
+    }
+}
+
+/// The flash backed log of programming results, if it could be opened
+type Log = Option<ResultLog<EspFlash>>;
+
+/// A page shown on the OLED in place of the run status
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Page {
+    /// A result log record, by age
+    Log(u32),
+    /// First pass yield statistics
+    Stats,
+    /// The last panic, which a long press of both buttons clears
+    Panic,
+}
+
+/// Everything the jig remembers about the sensors it has programmed
+struct History {
+    /// The flash store the lifetime counters are kept in, if it could be opened
+    store: Option<KvStore<EspFlash>>,
+    log: Log,
+    /// The number of the newest run
+    run: u32,
+    /// Counters since the jig was switched on
+    session: Counters,
+    /// Counters over the life of the jig, saved at the end of each run and every hour. None if
+    /// the stored ones couldn't be read, so they aren't overwritten
+    lifetime: Option<Counters>,
+    /// The next address for continuous mode, if it could be read
+    addresses: Option<AddressCounter>,
+    /// Where to send each result as it is recorded, while the console is monitoring or the
+    /// host is watching results
+    monitor: Option<ReplyChannelSender>,
+}
+
+impl History {
+    /// Pick up the run number and lifetime counters where they were left and count the boot
+    fn new(mut store: Option<KvStore<EspFlash>>, log: Log) -> Self {
+        let mut lifetime = store.as_mut().and_then(|store| {
+            Counters::load(store)
+                .inspect_err(|e| warn!("MAIN: Failed to read counters, not saving them: {}", e))
+                .ok()
+        });
+        let addresses = store.as_mut().and_then(|store| {
+            AddressCounter::load(store)
+                .inspect_err(|e| warn!("MAIN: Failed to read next address: {}", e))
+                .ok()
+        });
+        if let Some(lifetime) = &mut lifetime {
+            lifetime.boots = lifetime.boots.saturating_add(1);
+        }
+        let mut history = Self {
+            store,
+            run: log.as_ref().and_then(|log| log.last_run()).unwrap_or(0),
+            log,
+            session: Counters {
+                boots: 1,
+                ..Counters::default()
+            },
+            lifetime,
+            addresses,
+            monitor: None,
+        };
+        history.save();
+        history
+    }
+
+    /// Count a new run
+    ///
+    /// # Returns
+    /// The run number to log its results under
+    fn start_run(&mut self) -> u32 {
+        self.run = self.run.wrapping_add(1);
+        self.session.runs += 1;
+        if let Some(lifetime) = &mut self.lifetime {
+            lifetime.runs = lifetime.runs.saturating_add(1);
+        }
+        self.run
+    }
+
+    /// Add a sensor result to the log and the counters. A failed log write is only reported,
+    /// as losing the record is better than stopping the run
+    fn record(&mut self, record: &ResultRecord) {
+        self.session.record(record);
+        if let Some(lifetime) = &mut self.lifetime {
+            lifetime.record(record);
+        }
+        if let Some(log) = &mut self.log
+            && let Err(e) = log.append(record)
+        {
+            warn!("MAIN: Failed to log result {}: {}", record, e);
+        }
+        if let Some(replies) = &self.monitor {
+            // Drop the result rather than hold up the run if the console is behind
+            let _ = replies.try_send(Output::Result(*record));
+        }
+    }
+
+    fn hour_passed(&mut self) {
+        self.session.uptime_hours += 1;
+        if let Some(lifetime) = &mut self.lifetime {
+            lifetime.uptime_hours = lifetime.uptime_hours.saturating_add(1);
+        }
+        self.save();
+    }
+
+    /// Hand out the next address in continuous mode. It is used up even if the sensor then
+    /// fails, so it can never be given to two sensors
+    fn take_address(&mut self, plan: &ContinuousPlan) -> Option<u8> {
+        let (Some(store), Some(addresses)) = (&mut self.store, &mut self.addresses) else {
+            warn!("MAIN: No flash store, so no address can be handed out safely");
+            return None;
+        };
+        addresses
+            .take(store, plan)
+            .inspect_err(|e| warn!("MAIN: No address to hand out: {}", e))
+            .ok()
+    }
+
+    /// The last panic, if it hasn't been cleared
+    fn last_panic(&mut self) -> Option<PanicRecord> {
+        let store = self.store.as_mut()?;
+        PanicRecord::load(store)
+            .inspect_err(|e| warn!("MAIN: Failed to read panic record: {}", e))
+            .ok()
+            .flatten()
+    }
+
+    fn clear_panic(&mut self) {
+        if let Some(store) = &mut self.store
+            && let Err(e) = PanicRecord::clear(store)
+        {
+            warn!("MAIN: Failed to clear panic record: {}", e);
+        }
+    }
+
+    /// Save the lifetime counters
+    fn save(&mut self) {
+        if let (Some(store), Some(lifetime)) = (&mut self.store, &self.lifetime)
+            && let Err(e) = lifetime.save(store)
+        {
+            warn!("MAIN: Failed to save counters: {}", e);
+        }
+    }
+}
+
+/// Start a programming run if the interlock allows it and report how it ended
+async fn start_run(
+    display: &DisplayChannelSender,
+    inputs: &InputChannelReceiver,
+    settings: &Settings,
+    history: &mut History,
+) -> Result<RunSummary, RunAborted> {
+    if !interlock_closed() {
+        warn!("MAIN: Lid is open, refusing to start programming");
+        display
+            .send(DisplayState::Status(JigStatus::NeedsAttention))
+            .await;
+        return Err(RunAborted::InterlockOpen);
+    }
+
+    info!("MAIN: Starting device programming");
+    display
+        .send(DisplayState::Overlay(Overlay::acknowledge()))
+        .await;
+    let run = history.start_run();
+    let result = program_sockets(display, inputs, settings, history, run).await;
+    // Once per run, so the counters don't wear the flash
+    history.save();
+    match result {
+        Ok(RunSummary { failed: 0, .. }) => {
+            display.send(DisplayState::Status(JigStatus::Pass)).await;
+            display.send(DisplayState::Init).await;
+        }
+        Ok(RunSummary { failed, .. }) => {
+            warn!("MAIN: {} sensors failed", failed);
+            display.send(DisplayState::Status(JigStatus::Fail)).await;
+            display.send(DisplayState::Init).await;
+        }
+        Err(reason) => {
+            warn!("MAIN: Programming aborted: {}", reason);
+            display
+                .send(DisplayState::Status(JigStatus::NeedsAttention))
+                .await;
+        }
+    }
+    result
+}
+
+/// Program every socket in turn, stopping as soon as an abort is requested or the lid opens.
+/// Every socket that is attempted gets a record in the result log and is counted.
+///
+/// # Returns
+/// The run and how many sensors were tried and failed
+async fn program_sockets(
+    display: &DisplayChannelSender,
+    inputs: &InputChannelReceiver,
+    settings: &Settings,
+    history: &mut History,
+    run: u32,
+) -> Result<RunSummary, RunAborted> {
+    let mut failed = 0;
+    // Continuous mode programs one sensor at a time in the first socket
+    let sockets = match settings.mode {
+        ProgramMode::Fixture => SOCKET_COUNT as u8,
+        ProgramMode::Continuous => 1,
+    };
+    display
+        .send(DisplayState::Status(JigStatus::Scanning))
+        .await;
+    for i in 0..sockets {
+        display
+            .send(DisplayState::Socket(i, SocketState::Pending))
+            .await;
+    }
+    for i in 0..sockets {
+        // The interlock may have opened while the last event was being handled
+        if !interlock_closed() {
+            clear_sockets(display, i).await;
+            return Err(RunAborted::InterlockOpen);
+        }
+        display
+            .send(DisplayState::Status(JigStatus::Programming(i)))
+            .await;
+        display
+            .send(DisplayState::Socket(i, SocketState::Active))
+            .await;
+        let mut record = ResultRecord {
+            run,
+            position: i,
+            old_address: None,
+            new_address: match settings.mode {
+                ProgramMode::Fixture => settings.address_plan.address(i),
+                ProgramMode::Continuous => history.take_address(&settings.continuous),
+            },
+            serial: None,
+            verdict: Verdict::Pass,
+            error: ErrorCode::NoError,
+        };
+        let Some(address) = record.new_address else {
+            warn!("MAIN: No valid address for position {}", i);
+            record.verdict = Verdict::Fail;
+            record.error = ErrorCode::NoAddress;
+            history.record(&record);
+            display
+                .send(DisplayState::Socket(i, SocketState::Fail))
+                .await;
+            failed += 1;
+            continue;
+        };
+        display.send(DisplayState::SetAddress(i, address)).await;
+        match select(Timer::after(Duration::from_secs(1)), wait_for_abort(inputs)).await {
+            Either::First(_) => {
+                history.record(&record);
+                display.send(DisplayState::Socket(i, SocketState::Ok)).await
+            }
+            Either::Second(reason) => {
+                record.verdict = Verdict::Aborted;
+                record.error = reason.error_code();
+                history.record(&record);
+                display
+                    .send(DisplayState::Socket(i, SocketState::Fail))
+                    .await;
+                clear_sockets(display, i + 1).await;
+                return Err(reason);
             }
+        }
+    }
+    Ok(RunSummary {
+        run,
+        attempted: sockets,
+        failed,
+    })
+}
+
+/// The jig as the USB serial console and protocol see it. Commands and requests are carried
+/// out with the same operations as the buttons
+struct ConsoleJig<'a> {
+    display: &'a DisplayChannelSender,
+    inputs: &'a InputChannelReceiver,
+    settings: &'a mut Settings,
+    history: &'a mut History,
+    /// Where monitored results are sent
+    replies: ReplyChannelSender,
+}
+
+impl Jig for ConsoleJig<'_> {
+    const VERSION: &'static str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+    type LogCursor = LogCursor;
+
+    fn sockets(&self) -> u8 {
+        SOCKET_COUNT as u8
+    }
+
+    // Talking to the sensors themselves isn't written yet, only the programming run that
+    // stands in for it
+    async fn scan(&mut self, _position: u8) -> Result<Option<u8>, CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    async fn program(&mut self) -> Result<RunSummary, CommandError> {
+        start_run(self.display, self.inputs, self.settings, self.history)
+            .await
+            .map_err(|reason| reason.command_error())
+    }
+
+    async fn verify(&mut self, _position: u8) -> Result<bool, CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    async fn reset_default(&mut self, _position: u8) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn set_monitor(&mut self, on: bool) {
+        self.history.monitor = on.then_some(self.replies);
+    }
+
+    // There is no sensor driver to take readings with yet
+    fn watch_readings(&mut self, _on: bool) -> Result<(), CommandError> {
+        Err(CommandError::Unsupported)
+    }
+
+    fn setting(&self, id: SettingId) -> u32 {
+        self.settings.value(id)
+    }
+
+    async fn set_setting(&mut self, id: SettingId, value: u32) -> Result<(), CommandError> {
+        if !self.settings.set_value(id, value) {
+            return Err(CommandError::Rejected);
+        }
+        info!("MAIN: Changed {} to {}", id, value);
+        self.display
+            .send(DisplayState::Configure(*self.settings))
+            .await;
+        let Some(store) = &mut self.history.store else {
+            return Err(CommandError::NotSaved);
+        };
+        self.settings.save(store).map_err(|e| {
+            warn!("MAIN: Failed to save settings: {}", e);
+            CommandError::NotSaved
+        })
+    }
+
+    fn next_record(
+        &mut self,
+        cursor: &mut LogCursor,
+    ) -> Result<Option<ResultRecord>, CommandError> {
+        let Some(log) = &mut self.history.log else {
+            return Err(CommandError::LogUnreadable);
+        };
+        log.next(cursor).map_err(|e| {
+            warn!("MAIN: Failed to read result log: {}", e);
+            CommandError::LogUnreadable
+        })
+    }
+
+    fn led_health(&self) -> LedHealth {
+        let d = led_diagnostics();
+        LedHealth {
+            frames: d.frames,
+            retries: d.retries,
+            reinits: d.reinits,
+            failures: d.failures,
+            last_error: d.last_error.map(|e| e.message()),
+        }
+    }
+
+    fn session_counters(&self) -> Counters {
+        self.history.session
+    }
+
+    fn lifetime_counters(&self) -> Option<Counters> {
+        self.history.lifetime
+    }
+
+    fn next_address(&self) -> Result<Option<u8>, CommandError> {
+        let addresses = self
+            .history
+            .addresses
+            .as_ref()
+            .ok_or(CommandError::AddressesUnavailable)?;
+        Ok(addresses.peek(&self.settings.continuous))
+    }
+
+    async fn restart_addresses(&mut self, address: u8) -> Result<(), CommandError> {
+        let (Some(store), Some(addresses)) = (&mut self.history.store, &mut self.history.addresses)
+        else {
+            return Err(CommandError::AddressesUnavailable);
         };
-        info!("MAIN: Button pressed");
+        addresses.restart(store, address).map_err(|e| {
+            warn!("MAIN: Failed to restart the addresses: {}", e);
+            CommandError::AddressesUnavailable
+        })?;
+        info!("MAIN: Continuous addresses start again from {}", address);
+        Ok(())
+    }
+}
+
+/// Move through the result log on the OLED, newest record first
+///
+/// # Arguments
+/// * `age` - The age of the record on show, or None when starting to browse
+/// * `navigation` - Next shows an older record and Previous a newer one
+///
+/// # Returns
+/// The page now on show
+async fn browse_log(
+    display: &DisplayChannelSender,
+    log: &mut Log,
+    age: Option<u32>,
+    navigation: Navigation,
+) -> Option<Page> {
+    let Some(log) = log else {
+        display.send(DisplayState::LogEmpty).await;
+        return Some(Page::Log(0));
+    };
+    let age = match (age, navigation) {
+        (None, _) => 0,
+        (Some(age), Navigation::Next) => age.saturating_add(1),
+        (Some(age), Navigation::Previous) => age.saturating_sub(1),
+        (Some(age), _) => age,
+    };
+    // Stay on the oldest record when there are none older
+    for age in [age, age.saturating_sub(1)] {
+        match log.newest(age) {
+            Ok(Some(record)) => {
+                display.send(DisplayState::LogRecord(record)).await;
+                return Some(Page::Log(age));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("MAIN: Failed to read result log: {}", e);
+                break;
+            }
+        }
+    }
+    display.send(DisplayState::LogEmpty).await;
+    Some(Page::Log(0))
+}
+
+/// Wait for an input event that stops a run, ignoring any others
+async fn wait_for_abort(inputs: &InputChannelReceiver) -> RunAborted {
+    loop {
+        match inputs.receive().await {
+            InputEvent::Abort => return RunAborted::Requested,
+            InputEvent::InterlockOpened => return RunAborted::InterlockOpen,
+            _ => {}
+        }
+    }
+}
+
+/// Turn off the socket LEDs from `first` onwards, for sockets a stopped run never reached
+async fn clear_sockets(display: &DisplayChannelSender, first: u8) {
+    for i in first..SOCKET_COUNT as u8 {
+        display
+            .send(DisplayState::Socket(i, SocketState::Off))
+            .await;
     }
 }
+
+/// Record the panic where the next boot will find it, then reset so the jig carries on
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    error!("{}", defmt::Display2Format(info));
+    let uptime = esp_hal::time::Instant::now()
+        .duration_since_epoch()
+        .as_millis();
+    let record = PanicRecord::capture(info, Duration::from_millis(uptime));
+    // Safety: nothing else runs after a panic, and the slot is only read again after the reset
+    unsafe { (&raw mut PANIC_SLOT).write(record.to_bytes()) };
+    // Give an attached probe time to read the message before it is lost
+    esp_hal::delay::Delay::new().delay_millis(100);
+    esp_hal::system::software_reset()
+}
@@ -0,0 +1,122 @@
+//! Compositor module draws short-lived overlay animations on top of a base layer.
+//!
+//! The base layer is whatever the display task is currently showing. Overlays, such as a short
+//! "button acknowledged" flash, are blended over it each frame and removed as soon as their
+//! animation ends. The base animation keeps running underneath the whole time, so when an
+//! overlay expires the base shows through again exactly where it would have been.
+
+use crate::{
+    MAX_OVERLAYS, RgbRate,
+    animations::{Animation, BreatheAnimation, next_buffer, pixels},
+    drivers::leds::LedBuffer,
+};
+use defmt::Format;
+use embassy_time::Duration;
+use heapless::Vec;
+use smart_leds::RGB8;
+
+/// How an overlay is combined with the layers beneath it
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Blend {
+    /// Mix the overlay with the layers beneath by the given opacity, 0 (invisible) to 255 (opaque)
+    Alpha(u8),
+    /// Overlay pixels that are lit replace the layers beneath, black pixels let them show through
+    Mask,
+}
+
+/// An animation drawn over the base layer
+#[derive(Clone)]
+pub struct Overlay {
+    /// The animation to draw. The overlay is removed when this returns None
+    animation: Animation,
+    /// How to combine the animation with the layers beneath
+    blend: Blend,
+}
+
+impl Overlay {
+    /// Create a new overlay.
+    ///
+    /// # Arguments
+    /// * `animation` - The animation to draw. Give it a ttl so the overlay expires on its own
+    /// * `blend` - How to combine the animation with the layers beneath
+    pub fn new(animation: Animation, blend: Blend) -> Self {
+        Self { animation, blend }
+    }
+
+    /// A brief white flash over the whole string to acknowledge a button press
+    pub fn acknowledge() -> Self {
+        let flash = BreatheAnimation::new(
+            RGB8::new(255, 255, 255),
+            255,
+            255,
+            RgbRate::VerySlow,
+            Some(Duration::from_millis(300)),
+        );
+        Self::new(Animation::Breathe(flash), Blend::Alpha(192))
+    }
+}
+
+/// Holds the stack of overlays drawn above the base layer. Overlays added later are drawn on
+/// top of those added earlier
+#[derive(Default)]
+pub struct Compositor {
+    overlays: Vec<Overlay, MAX_OVERLAYS>,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push an overlay on top of the stack.
+    ///
+    /// # Returns
+    /// The overlay back as an error if the stack is already full
+    pub fn push(&mut self, overlay: Overlay) -> Result<(), Overlay> {
+        self.overlays.push(overlay)
+    }
+
+    /// Remove all overlays, revealing the base layer
+    pub fn clear(&mut self) {
+        self.overlays.clear();
+    }
+
+    /// True if there are no overlays to draw
+    pub fn is_empty(&self) -> bool {
+        self.overlays.is_empty()
+    }
+
+    /// Advance every overlay by one frame and blend it into `frame`, which holds the base layer.
+    /// Overlays whose animation has finished are dropped.
+    pub fn compose(&mut self, frame: &mut LedBuffer) {
+        self.overlays
+            .retain_mut(|overlay| match next_buffer(&mut overlay.animation) {
+                Some(layer) => {
+                    for i in pixels(&overlay.animation).indices() {
+                        frame[i] = blend_pixel(frame[i], layer[i], overlay.blend);
+                    }
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+/// Combine one overlay pixel with the pixel beneath it
+pub fn blend_pixel(below: RGB8, above: RGB8, blend: Blend) -> RGB8 {
+    match blend {
+        Blend::Alpha(alpha) => RGB8::new(
+            mix(below.r, above.r, alpha),
+            mix(below.g, above.g, alpha),
+            mix(below.b, above.b, alpha),
+        ),
+        Blend::Mask if above == RGB8::default() => below,
+        Blend::Mask => above,
+    }
+}
+
+/// Linear mix from `a` to `b` by `t`, where 0 gives `a` and 255 gives `b`
+pub fn mix(a: u8, b: u8, t: u8) -> u8 {
+    // Use u16 for the multiplication to avoid overflow before the division.
+    ((a as u16 * (255 - t) as u16 + b as u16 * t as u16) / 255) as u8
+}
@@ -1,8 +1,183 @@
-use embassy_time::{Duration, Timer};
+use defmt::Format;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::gpio::Input;
 
-/// Simple debounced button press detection
-pub async fn wait_for_press(button: &mut Input<'_>) {
-    button.wait_for_rising_edge().await;
-    Timer::after(Duration::from_millis(100)).await; // debounce
+/// The level an input reads while its button is pressed
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum ActiveLevel {
+    /// Pressed reads low, as for a button to ground with a pull-up
+    Low,
+    /// Pressed reads high, as for a button to 3.3V with a pull-down
+    High,
+}
+
+impl ActiveLevel {
+    /// True if an input reading `high` is pressed
+    pub const fn is_pressed(&self, high: bool) -> bool {
+        match self {
+            ActiveLevel::Low => !high,
+            ActiveLevel::High => high,
+        }
+    }
+}
+
+/// How a button input is debounced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebounceConfig {
+    /// The level the input reads while pressed
+    pub active: ActiveLevel,
+    /// How often the input is sampled while it is settling
+    pub sample_period: Duration,
+    /// How long the input must read the same level before the change is accepted
+    pub window: Duration,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self {
+            active: ActiveLevel::Low,
+            sample_period: Duration::from_millis(1),
+            window: Duration::from_millis(20),
+        }
+    }
+}
+
+impl DebounceConfig {
+    pub fn with_active(self, active: ActiveLevel) -> Self {
+        Self { active, ..self }
+    }
+
+    pub fn with_window(self, window: Duration) -> Self {
+        Self { window, ..self }
+    }
+
+    pub fn with_sample_period(self, sample_period: Duration) -> Self {
+        Self {
+            sample_period,
+            ..self
+        }
+    }
+}
+
+/// A clean change of a debounced button
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub struct Transition {
+    /// True if the button was pressed, false if it was released
+    pub pressed: bool,
+    /// When the input first read the new level
+    pub at: Instant,
+}
+
+/// Debounces a stream of level samples. A change is only accepted once every sample over the
+/// window agrees, so bounce on either edge is ignored.
+pub struct Debouncer {
+    config: DebounceConfig,
+    /// The accepted state of the button
+    pressed: bool,
+    /// When the input started reading a level different to the accepted state
+    changed_at: Option<Instant>,
+}
+
+impl Debouncer {
+    /// Create a new debouncer.
+    ///
+    /// # Arguments
+    /// * `config` - How to debounce the input
+    /// * `pressed` - The state to start in, usually taken from the first sample
+    pub fn new(config: DebounceConfig, pressed: bool) -> Self {
+        Self {
+            config,
+            pressed,
+            changed_at: None,
+        }
+    }
+
+    /// Feed in a sample of the input.
+    ///
+    /// # Arguments
+    /// * `high` - True if the input read high
+    /// * `at` - When the sample was taken
+    ///
+    /// # Returns
+    /// The transition if this sample completes one
+    pub fn sample(&mut self, high: bool, at: Instant) -> Option<Transition> {
+        let pressed = self.config.active.is_pressed(high);
+        if pressed == self.pressed {
+            // Bounced back before the window was up
+            self.changed_at = None;
+            return None;
+        }
+        let changed_at = *self.changed_at.get_or_insert(at);
+        if at - changed_at < self.config.window {
+            return None;
+        }
+        self.pressed = pressed;
+        self.changed_at = None;
+        Some(Transition {
+            pressed,
+            at: changed_at,
+        })
+    }
+
+    /// The accepted state of the button
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// True while the input reads differently to the accepted state and needs sampling
+    pub fn is_settling(&self) -> bool {
+        self.changed_at.is_some()
+    }
+
+    pub fn config(&self) -> &DebounceConfig {
+        &self.config
+    }
+}
+
+/// A button input with debouncing
+pub struct DebouncedButton {
+    input: Input<'static>,
+    debouncer: Debouncer,
+}
+
+impl DebouncedButton {
+    /// Create a new debounced button. A button already held down is taken as pressed without
+    /// producing a transition.
+    ///
+    /// # Arguments
+    /// * `input` - The button input, configured with whatever pull it needs
+    /// * `config` - How to debounce the input
+    pub fn new(input: Input<'static>, config: DebounceConfig) -> Self {
+        let pressed = config.active.is_pressed(input.is_high());
+        Self {
+            input,
+            debouncer: Debouncer::new(config, pressed),
+        }
+    }
+
+    /// Wait for the next clean press or release. While the input is steady this waits on the
+    /// pin rather than sampling.
+    pub async fn wait_for_transition(&mut self) -> Transition {
+        loop {
+            if let Some(transition) = self.debouncer.sample(self.input.is_high(), Instant::now()) {
+                return transition;
+            }
+            if self.debouncer.is_settling() {
+                Timer::after(self.debouncer.config().sample_period).await;
+            } else {
+                // Wait for the level that would change the accepted state
+                let config = self.debouncer.config();
+                if config.active.is_pressed(true) != self.debouncer.is_pressed() {
+                    self.input.wait_for_high().await;
+                } else {
+                    self.input.wait_for_low().await;
+                }
+            }
+        }
+    }
+
+    /// The accepted state of the button
+    pub fn is_pressed(&self) -> bool {
+        self.debouncer.is_pressed()
+    }
 }
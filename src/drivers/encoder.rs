@@ -0,0 +1,98 @@
+//! Quadrature rotary encoder decoding.
+//!
+//! The ESP32-C3 has no PCNT peripheral, so the encoder is decoded from GPIO interrupts on both
+//! channels. Each edge is run through a state table that only accepts moves to a neighbouring
+//! Gray code state. Contact bounce moves back and forth between two neighbouring states and
+//! cancels out, and impossible jumps are ignored.
+
+use defmt::Format;
+use embassy_futures::select::select;
+use esp_hal::gpio::Input;
+
+/// Which way the encoder was turned
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Direction {
+    Clockwise,
+    AntiClockwise,
+}
+
+/// Steps for each move between states, indexed by `previous << 2 | current` where a state is
+/// `a << 1 | b`. Clockwise runs 00, 01, 11, 10. Invalid and unchanged moves count zero.
+const STEPS: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+
+/// Turns samples of the two encoder channels into whole detents
+pub struct QuadratureDecoder {
+    /// The last state, `a << 1 | b`
+    state: u8,
+    /// Steps counted since the last detent, positive clockwise
+    count: i8,
+    /// How many steps make up one detent, usually 4
+    steps_per_detent: i8,
+}
+
+impl QuadratureDecoder {
+    /// Create a new decoder.
+    ///
+    /// # Arguments
+    /// * `a`, `b` - The current levels of the two channels
+    /// * `steps_per_detent` - How many state changes the encoder makes per click, usually 4
+    pub fn new(a: bool, b: bool, steps_per_detent: u8) -> Self {
+        Self {
+            state: Self::state(a, b),
+            count: 0,
+            steps_per_detent: steps_per_detent.clamp(1, i8::MAX as u8) as i8,
+        }
+    }
+
+    const fn state(a: bool, b: bool) -> u8 {
+        ((a as u8) << 1) | b as u8
+    }
+
+    /// Feed in the levels of the two channels after an edge.
+    ///
+    /// # Returns
+    /// The direction if this completes a detent
+    pub fn update(&mut self, a: bool, b: bool) -> Option<Direction> {
+        let state = Self::state(a, b);
+        self.count += STEPS[((self.state << 2) | state) as usize];
+        self.state = state;
+        if self.count >= self.steps_per_detent {
+            self.count = 0;
+            Some(Direction::Clockwise)
+        } else if self.count <= -self.steps_per_detent {
+            self.count = 0;
+            Some(Direction::AntiClockwise)
+        } else {
+            None
+        }
+    }
+}
+
+/// A quadrature rotary encoder on two GPIO inputs
+pub struct RotaryEncoder {
+    a: Input<'static>,
+    b: Input<'static>,
+    decoder: QuadratureDecoder,
+}
+
+impl RotaryEncoder {
+    /// Create a new rotary encoder. Swap the inputs if it counts the wrong way.
+    ///
+    /// # Arguments
+    /// * `a`, `b` - The two channel inputs, configured with whatever pull they need
+    /// * `steps_per_detent` - How many state changes the encoder makes per click, usually 4
+    pub fn new(a: Input<'static>, b: Input<'static>, steps_per_detent: u8) -> Self {
+        let decoder = QuadratureDecoder::new(a.is_high(), b.is_high(), steps_per_detent);
+        Self { a, b, decoder }
+    }
+
+    /// Wait for the encoder to be turned by one detent
+    pub async fn wait_for_step(&mut self) -> Direction {
+        loop {
+            select(self.a.wait_for_any_edge(), self.b.wait_for_any_edge()).await;
+            if let Some(direction) = self.decoder.update(self.a.is_high(), self.b.is_high()) {
+                return direction;
+            }
+        }
+    }
+}
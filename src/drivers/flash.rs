@@ -0,0 +1,46 @@
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use esp_storage::FlashStorage;
+
+pub use jig_common::flash::{Flash, FlashError, WRITE_SIZE, check_aligned};
+
+/// The ESP32-C3's own SPI flash, shared with the firmware image. Only write inside the data
+/// partitions listed in `partitions.csv`.
+pub struct EspFlash {
+    storage: FlashStorage,
+}
+
+impl EspFlash {
+    pub fn new() -> Self {
+        Self {
+            storage: FlashStorage::new(),
+        }
+    }
+}
+
+impl Default for EspFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flash for EspFlash {
+    const SECTOR_SIZE: u32 = <FlashStorage as NorFlash>::ERASE_SIZE as u32;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        check_aligned(offset, buf.len())?;
+        ReadNorFlash::read(&mut self.storage, offset, buf).map_err(|_| FlashError::Hardware)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        check_aligned(offset, data.len())?;
+        NorFlash::write(&mut self.storage, offset, data).map_err(|_| FlashError::Hardware)
+    }
+
+    fn erase(&mut self, offset: u32) -> Result<(), FlashError> {
+        if !offset.is_multiple_of(Self::SECTOR_SIZE) {
+            return Err(FlashError::NotAligned);
+        }
+        NorFlash::erase(&mut self.storage, offset, offset + Self::SECTOR_SIZE)
+            .map_err(|_| FlashError::Hardware)
+    }
+}
@@ -1,2 +1,15 @@
 pub mod button;
-pub mod neopixel;
+pub mod encoder;
+pub mod flash;
+pub mod rgb_led;
+pub mod ws2812;
+
+pub use jig_common::{leds, ram_flash, recording};
+
+/// The LED output fitted to this jig variant
+#[cfg(not(feature = "rgb-led"))]
+pub type LedBackend = ws2812::Ws2812Output;
+
+/// The LED output fitted to this jig variant
+#[cfg(feature = "rgb-led")]
+pub type LedBackend = rgb_led::RgbLedOutput;
@@ -0,0 +1,48 @@
+use crate::{
+    STATUS_PIXEL,
+    drivers::leds::{LedBuffer, LedError, LedOutput},
+};
+use esp_hal::gpio::{Level, Output};
+
+/// Channel values at or above this turn the channel on
+const ON_THRESHOLD: u8 = 128;
+
+/// Shows the status pixel on a plain RGB LED with one GPIO per channel, for cheaper jig variants
+/// without a WS2812. Each channel is either on or off, so colours are reduced to the eight the
+/// LED can show and everything but the status pixel is ignored.
+pub struct RgbLedOutput {
+    /// The red, green and blue channel pins
+    pins: [Output<'static>; 3],
+    /// True for a common anode LED, where driving a pin low turns the channel on
+    active_low: bool,
+}
+
+impl RgbLedOutput {
+    /// Create a new RGB LED output.
+    ///
+    /// # Parameters
+    /// * `red`, `green`, `blue` - The pins driving each channel of the LED
+    /// * `active_low` - True for a common anode LED, where driving a pin low turns the channel on
+    pub fn new(
+        red: Output<'static>,
+        green: Output<'static>,
+        blue: Output<'static>,
+        active_low: bool,
+    ) -> Self {
+        Self {
+            pins: [red, green, blue],
+            active_low,
+        }
+    }
+}
+
+impl LedOutput for RgbLedOutput {
+    async fn show(&mut self, frame: &LedBuffer) -> Result<(), LedError> {
+        let pixel = frame[STATUS_PIXEL];
+        for (pin, value) in self.pins.iter_mut().zip([pixel.r, pixel.g, pixel.b]) {
+            let on = value >= ON_THRESHOLD;
+            pin.set_level(Level::from(on != self.active_low));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    LED_STRING_SIZE,
+    drivers::leds::{LedBuffer, LedError, LedOutput, update_diagnostics},
+};
+use defmt::warn;
+use embassy_time::{Duration, Instant};
+use esp_hal::{
+    gpio::AnyPin,
+    peripherals::RMT,
+    rmt::{ConstChannelAccess, Rmt, Tx},
+    time::Rate,
+};
+use esp_hal_smartled::{SmartLedsAdapterAsync, buffer_size_async};
+use smart_leds::SmartLedsWriteAsync;
+
+/// The RMT clock frequency used to generate the WS2812 waveform
+const RMT_FREQUENCY_MHZ: u32 = 80;
+
+/// How many times to retry a failed transmission before reinitialising the RMT channel
+const MAX_RETRIES: u8 = 2;
+
+/// The shortest time between attempts to reinitialise the RMT channel, so a broken LED string
+/// does not stall the display task
+const REINIT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// We must know what the LED TX buffer size is as a constant for the types involved here
+const LED_INTERNAL_BUF_LEN: usize = buffer_size_async(LED_STRING_SIZE);
+
+/// Driver for the led array. We have to size it here to exactly what we will get back from
+/// the `SmartLedsAdapterAsync::new()` function when we set up the driver below
+type LedAdapter = SmartLedsAdapterAsync<ConstChannelAccess<Tx, 0>, LED_INTERNAL_BUF_LEN>;
+
+/// Shows frames on a WS2812 string driven from RMT channel 0
+pub struct Ws2812Output {
+    /// Driver for the led array. None if reinitialising the RMT channel failed
+    led: Option<LedAdapter>,
+    /// The GPIO number the LED string is connected to, kept so the channel can be reinitialised
+    pin: u8,
+    /// When we last reinitialised the RMT channel
+    last_reinit: Option<Instant>,
+}
+
+impl Ws2812Output {
+    /// Create a new WS2812 output.
+    ///
+    /// # Parameters
+    /// * `rmt` - The RMT peripheral device to use for driving the LED strip
+    /// * `pin` - The GPIO pin to which the LED strip is connected
+    pub fn new(rmt: RMT<'static>, pin: AnyPin<'static>) -> Result<Self, LedError> {
+        let number = pin.number();
+        Ok(Self {
+            led: Some(adapter(rmt, pin)?),
+            pin: number,
+            last_reinit: None,
+        })
+    }
+
+    /// Throw away the RMT channel and set it up again from scratch
+    fn reinit(&mut self) -> Result<(), LedError> {
+        self.last_reinit = Some(Instant::now());
+        update_diagnostics(|d| d.reinits += 1);
+        // Drop the old adapter first so it releases the RMT channel and pin
+        self.led = None;
+        // SAFETY: The adapter we just dropped held the only other handles to the RMT peripheral and
+        // the LED pin, both of which were given to this output for its sole use
+        let (rmt, pin) = unsafe { (RMT::steal(), AnyPin::steal(self.pin)) };
+        self.led = Some(adapter(rmt, pin)?);
+        Ok(())
+    }
+}
+
+impl LedOutput for Ws2812Output {
+    /// Send the frame to the string, retrying and then reinitialising the RMT channel if the
+    /// transmission fails
+    async fn show(&mut self, frame: &LedBuffer) -> Result<(), LedError> {
+        for _ in 0..=MAX_RETRIES {
+            if let Some(led) = self.led.as_mut() {
+                if led.write(*frame).await.is_ok() {
+                    return Ok(());
+                }
+                update_diagnostics(|d| d.retries += 1);
+            }
+        }
+
+        if self
+            .last_reinit
+            .is_some_and(|at| Instant::now() < at + REINIT_BACKOFF)
+        {
+            return Err(LedError::Transmission);
+        }
+        warn!("LED: Transmission failed, reinitialising RMT channel");
+        self.reinit()?;
+        match self.led.as_mut() {
+            Some(led) if led.write(*frame).await.is_ok() => Ok(()),
+            _ => Err(LedError::Transmission),
+        }
+    }
+}
+
+/// Set up the RMT peripheral and wrap channel 0 in a WS2812 adapter on `pin`
+fn adapter(rmt: RMT<'static>, pin: AnyPin<'static>) -> Result<LedAdapter, LedError> {
+    let rmt = Rmt::new(rmt, Rate::from_mhz(RMT_FREQUENCY_MHZ))
+        .map_err(|_| LedError::Init)?
+        .into_async();
+    let buffer = [0_u32; buffer_size_async(LED_STRING_SIZE)];
+    Ok(SmartLedsAdapterAsync::new(rmt.channel0, pin, buffer))
+}
@@ -1,23 +1,54 @@
 #![no_std]
 
+pub mod address_counter;
 pub mod animations;
+pub mod compositor;
 pub mod drivers;
+pub mod panic_record;
+pub mod queue;
+pub mod result_log;
+pub mod status;
+pub mod storage;
 pub mod tasks;
 
+pub use jig_common::{counters, crc, gestures, settings};
 pub use tasks::*;
 
 /// The display animation update interval in milliseconds
 pub const ANIMATION_UPDATE: u64 = 250;
 
+/// The default time in milliseconds to cross-fade from one animation to the next
+pub const DEFAULT_CROSSFADE: u64 = 500;
+
 /// The default colour for the LED strip (green)
 pub const DEFAULT_COLOUR: [u8; 3] = [0, 255, 0];
 
-/// The number of LEDs in the string we are driving
-pub const LED_STRING_SIZE: usize = 1;
+/// The number of sensor sockets on the fixture
+pub const SOCKET_COUNT: usize = counters::SOCKET_COUNT;
+
+/// The index of the overall status pixel in the LED string
+pub const STATUS_PIXEL: usize = 0;
+
+/// The number of LEDs in the string we are driving, which the `socket-leds` feature sets
+pub use drivers::leds::LED_STRING_SIZE;
+
+/// The LED string index of the pixel next to the socket at the given position, if the fixture
+/// has per-socket LEDs
+pub const fn socket_pixel(pos: u8) -> Option<usize> {
+    let index = STATUS_PIXEL + 1 + pos as usize;
+    if (pos as usize) < SOCKET_COUNT && index < LED_STRING_SIZE {
+        Some(index)
+    } else {
+        None
+    }
+}
 
 /// The maximum number of pending animations in the animation queue
 pub const MAX_PENDING_ANIMATIONS: usize = 20;
 
+/// The maximum number of overlay animations drawn over the base animation at once
+pub const MAX_OVERLAYS: usize = 4;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RgbBrightness {
     Low = 10,
@@ -0,0 +1,169 @@
+//! Panic record module keeps the reason for the last panic, so it can be seen without a probe.
+//!
+//! The panic handler encodes a [`PanicRecord`] into a slot of RAM that survives the reset that
+//! follows. At the next boot the record is moved from that slot into the flash store under
+//! [`Key::LastPanic`], where it stays until it is cleared. The same [`ENCODED_LEN`] byte layout
+//! is used in both places, all little endian:
+//! - 0..8: uptime in milliseconds
+//! - 8..12: line
+//! - 12: file length, 13: message length, 14..16: reserved
+//! - 16..56: file, keeping the end of longer paths
+//! - 56..124: message, keeping the start of longer messages
+//! - 124..128: CRC-32 of everything before it
+//!
+//! Retained RAM holds random bytes after power on, so the CRC is what tells a real record apart.
+
+use core::fmt::{Display, Write};
+
+use defmt::Format;
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::{
+    crc::crc32,
+    drivers::flash::Flash,
+    storage::{Key, KvStore, MAX_VALUE_LEN, StorageError},
+};
+
+/// The length of an encoded record in bytes
+pub const ENCODED_LEN: usize = 128;
+
+/// The longest file name kept
+pub const FILE_LEN: usize = 40;
+/// The longest message kept
+pub const MESSAGE_LEN: usize = 68;
+
+const FILE_OFFSET: usize = 16;
+const MESSAGE_OFFSET: usize = FILE_OFFSET + FILE_LEN;
+const CRC_OFFSET: usize = ENCODED_LEN - 4;
+
+/// Why and where the firmware last panicked
+#[derive(Debug, Clone, PartialEq, Format)]
+pub struct PanicRecord {
+    /// How long the jig had been running
+    pub uptime: Duration,
+    /// The source file the panic came from, without the start of the path if it was too long
+    pub file: String<FILE_LEN>,
+    pub line: u32,
+    /// The panic message, cut short if it was too long
+    pub message: String<MESSAGE_LEN>,
+}
+
+/// Formats into a string, dropping whatever doesn't fit rather than failing
+struct Truncating<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy text to a string, keeping the end if it doesn't fit
+fn tail<const N: usize>(text: &str) -> String<N> {
+    let mut start = text.len().saturating_sub(N);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut out = String::new();
+    // Can't fail, as what is left fits
+    let _ = out.push_str(&text[start..]);
+    out
+}
+
+/// Read text written by [`PanicRecord::to_bytes`], or None if it isn't valid UTF-8
+fn text<const N: usize>(bytes: &[u8], len: u8) -> Option<String<N>> {
+    let text = core::str::from_utf8(bytes.get(..len as usize)?).ok()?;
+    let mut out = String::new();
+    out.push_str(text).ok()?;
+    Some(out)
+}
+
+impl PanicRecord {
+    /// Create a record from the parts of a panic, shortening the file and message to fit
+    ///
+    /// # Arguments
+    /// * `message` - The panic message, usually `PanicInfo::message`
+    /// * `file` - The source file of the panic, or "" if not known
+    /// * `line` - The source line of the panic, or 0 if not known
+    /// * `uptime` - How long the jig had been running
+    pub fn new(message: &dyn Display, file: &str, line: u32, uptime: Duration) -> Self {
+        let mut text = String::new();
+        let _ = write!(Truncating(&mut text), "{}", message);
+        Self {
+            uptime,
+            file: tail(file),
+            line,
+            message: text,
+        }
+    }
+
+    /// Create a record from inside the panic handler
+    pub fn capture(info: &core::panic::PanicInfo, uptime: Duration) -> Self {
+        let (file, line) = info
+            .location()
+            .map_or(("", 0), |location| (location.file(), location.line()));
+        Self::new(&info.message(), file, line, uptime)
+    }
+
+    /// The file name without its directories
+    pub fn file_name(&self) -> &str {
+        self.file.rsplit('/').next().unwrap_or_default()
+    }
+
+    pub fn to_bytes(&self) -> [u8; ENCODED_LEN] {
+        let mut bytes = [0; ENCODED_LEN];
+        bytes[0..8].copy_from_slice(&self.uptime.as_millis().to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.line.to_le_bytes());
+        bytes[12] = self.file.len() as u8;
+        bytes[13] = self.message.len() as u8;
+        bytes[FILE_OFFSET..FILE_OFFSET + self.file.len()].copy_from_slice(self.file.as_bytes());
+        bytes[MESSAGE_OFFSET..MESSAGE_OFFSET + self.message.len()]
+            .copy_from_slice(self.message.as_bytes());
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a record, or None if the bytes don't hold one
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != ENCODED_LEN
+            || crc32(&bytes[..CRC_OFFSET]).to_le_bytes() != bytes[CRC_OFFSET..]
+        {
+            return None;
+        }
+        let mut uptime = [0; 8];
+        uptime.copy_from_slice(&bytes[0..8]);
+        Some(Self {
+            uptime: Duration::from_millis(u64::from_le_bytes(uptime)),
+            line: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            file: text(&bytes[FILE_OFFSET..MESSAGE_OFFSET], bytes[12])?,
+            message: text(&bytes[MESSAGE_OFFSET..CRC_OFFSET], bytes[13])?,
+        })
+    }
+
+    /// Load the last panic from the store
+    ///
+    /// # Returns
+    /// The record, or None if there has been no panic since it was last cleared
+    pub fn load<F: Flash>(store: &mut KvStore<F>) -> Result<Option<Self>, StorageError> {
+        let mut bytes = [0; MAX_VALUE_LEN];
+        Ok(store
+            .read(Key::LastPanic, &mut bytes)?
+            .and_then(|len| Self::from_bytes(&bytes[..len])))
+    }
+
+    /// Save the record to the store as the last panic
+    pub fn save<F: Flash>(&self, store: &mut KvStore<F>) -> Result<(), StorageError> {
+        store.write(Key::LastPanic, &self.to_bytes())
+    }
+
+    /// Forget the last panic
+    pub fn clear<F: Flash>(store: &mut KvStore<F>) -> Result<(), StorageError> {
+        store.write(Key::LastPanic, &[])
+    }
+}
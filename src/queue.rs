@@ -0,0 +1,183 @@
+//! Queue module holds pending animations in priority order.
+//!
+//! Every entry has a [`Priority`]. Entries are kept highest priority first and in arrival order
+//! within the same priority, so an urgent alert never waits behind cosmetic animations.
+//!
+//! The display task uses the priority of the running animation to decide when the head of the
+//! queue may start:
+//! - A running animation that never ends (see [`crate::animations::Interruptable`]) is replaced by
+//!   a pending entry of the same or higher priority. A lower priority entry waits, so a critical
+//!   alert isn't covered up by a cosmetic animation.
+//! - A running animation with a fixed lifetime is only preempted by a pending entry of strictly
+//!   higher priority. Otherwise the pending entry waits until it finishes.
+//!
+//! [`preempts`] makes this decision.
+//!
+//! Pushing an entry that is already pending is a no-op, except that the pending entry is promoted
+//! if the new one has a higher priority. When the queue is full the [`FullPolicy`] decides whether
+//! the new entry is rejected or evicts the newest entry of the lowest priority.
+//!
+//! Only the latest jig status matters, so a new status replaces the previous one outright. The
+//! display task removes any pending status with [`PriorityQueue::retain`] before pushing the new
+//! one. Otherwise a stale status would keep its priority, and a warning followed quickly by idle
+//! would leave idle waiting behind the steady warning.
+
+use defmt::Format;
+use heapless::Vec;
+
+/// How urgent a queued entry is. Higher priorities are shown first and may preempt lower ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum Priority {
+    /// Cosmetic animations shown when nothing else is happening
+    Background,
+    /// Normal progress information
+    Info,
+    /// Something the operator should notice
+    Warning,
+    /// Something the operator must notice straight away
+    Critical,
+}
+
+/// Check whether the entry at the head of the queue may replace the running one now
+///
+/// # Arguments
+/// * `running` - The priority of the running entry
+/// * `interruptable` - True if the running entry never ends on its own
+/// * `pending` - The priority of the entry at the head of the queue
+pub fn preempts(running: Priority, interruptable: bool, pending: Priority) -> bool {
+    if interruptable {
+        pending >= running
+    } else {
+        pending > running
+    }
+}
+
+/// What to do when pushing to a full queue
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum FullPolicy {
+    /// Evict the newest entry of the lowest priority if the new entry has a higher priority,
+    /// otherwise reject the new entry
+    DropLowest,
+    /// Always reject the new entry
+    Reject,
+}
+
+/// The result of a successful push
+#[derive(Debug, PartialEq, Format)]
+pub enum Pushed<T> {
+    /// The entry was added to the queue
+    Queued,
+    /// An identical entry was already pending so the new one was dropped
+    Duplicate,
+    /// The entry was added and this one was evicted to make room
+    Evicted(T),
+}
+
+/// A bounded queue that keeps its entries in priority order
+pub struct PriorityQueue<T, const N: usize> {
+    /// Pending entries, highest priority first and oldest first within a priority
+    entries: Vec<(Priority, T), N>,
+    /// What to do when the queue is full
+    policy: FullPolicy,
+}
+
+impl<T: PartialEq, const N: usize> PriorityQueue<T, N> {
+    /// Create an empty queue.
+    ///
+    /// # Arguments
+    /// * `policy` - What to do when pushing to a full queue
+    pub const fn new(policy: FullPolicy) -> Self {
+        Self {
+            entries: Vec::new(),
+            policy,
+        }
+    }
+
+    /// Add an entry behind all pending entries of the same or higher priority.
+    ///
+    /// # Arguments
+    /// * `item` - The entry to add
+    /// * `priority` - How urgent the entry is
+    ///
+    /// # Returns
+    /// What happened to the entry, or the entry back as an error if the queue is full and the
+    /// policy rejected it
+    pub fn push(&mut self, item: T, priority: Priority) -> Result<Pushed<T>, T> {
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|(_, pending)| *pending == item)
+        {
+            if self.entries[index].0 >= priority {
+                return Ok(Pushed::Duplicate);
+            }
+            // Promote the pending entry by re-inserting it at the higher priority
+            self.entries.remove(index);
+        }
+
+        let mut evicted = None;
+        if self.entries.is_full() {
+            match (self.policy, self.entries.last()) {
+                (FullPolicy::DropLowest, Some((lowest, _))) if *lowest < priority => {
+                    // Entries are sorted so the last one is the newest of the lowest priority
+                    evicted = self.entries.pop().map(|(_, item)| item);
+                }
+                _ => return Err(item),
+            }
+        }
+
+        let index = self
+            .entries
+            .iter()
+            .position(|(pending, _)| *pending < priority)
+            .unwrap_or(self.entries.len());
+        // Infallible because we made sure there was room above
+        let _ = self.entries.insert(index, (priority, item));
+
+        Ok(match evicted {
+            Some(item) => Pushed::Evicted(item),
+            None => Pushed::Queued,
+        })
+    }
+
+    /// The entry that would be returned by [`Self::pop`], without removing it
+    pub fn peek(&self) -> Option<(Priority, &T)> {
+        self.entries
+            .first()
+            .map(|(priority, item)| (*priority, item))
+    }
+
+    /// Remove and return the highest priority entry
+    pub fn pop(&mut self) -> Option<(Priority, T)> {
+        if self.entries.is_empty() {
+            None
+        } else {
+            Some(self.entries.remove(0))
+        }
+    }
+
+    /// The number of pending entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if nothing is pending
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Keep only the pending entries that pass a test, in the same order
+    ///
+    /// # Arguments
+    /// * `keep` - Called with the priority and entry of each pending entry. Returns false to
+    ///   remove it
+    pub fn retain(&mut self, mut keep: impl FnMut(Priority, &T) -> bool) {
+        self.entries
+            .retain(|(priority, item)| keep(*priority, item));
+    }
+
+    /// Remove all pending entries
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,296 @@
+//! Result log module keeps a record of every sensor the jig has programmed, for traceability.
+//!
+//! [`ResultLog`] is a ring of fixed size slots spread over the sectors of its own flash
+//! partition, one [`ResultRecord`] per position per run. Slots are filled in order. When the
+//! next slot starts a sector, that sector is erased first, dropping the oldest records, so the
+//! log always holds at least all but one sector's worth of the newest records.
+//!
+//! Slot layout, all little endian, [`SLOT_LEN`] bytes:
+//! - 0..4: sequence number, one higher than the record before
+//! - 4..8: run number
+//! - 8: position, 9: old address, 10: new address, 11: verdict
+//! - 12..16: serial number
+//! - 16..18: error code
+//! - 18..28: reserved, left erased
+//! - 28..32: CRC-32 of everything before it
+//!
+//! Unknown addresses and serial numbers are stored as all ones. Each record is written with a
+//! single flash write and checked against its CRC when read, so a record cut short by a power
+//! loss is skipped rather than trusted. The slot it was written to is not reused until its
+//! sector comes round again.
+
+use defmt::Format;
+
+use crate::{
+    crc::crc32,
+    drivers::flash::{Flash, FlashError},
+};
+
+/// The record kept for each sensor, shared with the host tools
+pub use jig_common::record::{CSV_HEADER, ErrorCode, ResultRecord, Verdict};
+
+/// Offset of the `results` partition in `partitions.csv`
+pub const RESULTS_PARTITION_OFFSET: u32 = 0x31_4000;
+/// Number of 4 KiB sectors in the `results` partition
+pub const RESULTS_PARTITION_SECTORS: u32 = 16;
+
+/// The length of one slot in bytes
+pub const SLOT_LEN: u32 = 32;
+
+const CRC_OFFSET: usize = SLOT_LEN as usize - 4;
+/// Stored in place of an unknown address or serial number
+const UNKNOWN: u8 = 0xff;
+
+/// Encode a record into a slot with the given sequence number
+fn encode(record: &ResultRecord, sequence: u32) -> [u8; SLOT_LEN as usize] {
+    let mut slot = [0xff; SLOT_LEN as usize];
+    slot[0..4].copy_from_slice(&sequence.to_le_bytes());
+    slot[4..8].copy_from_slice(&record.run.to_le_bytes());
+    slot[8] = record.position;
+    slot[9] = record.old_address.unwrap_or(UNKNOWN);
+    slot[10] = record.new_address.unwrap_or(UNKNOWN);
+    slot[11] = record.verdict as u8;
+    slot[12..16].copy_from_slice(&record.serial.unwrap_or(u32::MAX).to_le_bytes());
+    slot[16..18].copy_from_slice(&(record.error as u16).to_le_bytes());
+    let crc = crc32(&slot[..CRC_OFFSET]);
+    slot[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+    slot
+}
+
+/// Decode a slot
+///
+/// # Returns
+/// The sequence number and record, or None if the slot is erased or fails its CRC
+fn decode(slot: &[u8; SLOT_LEN as usize]) -> Option<(u32, ResultRecord)> {
+    let crc = u32::from_le_bytes([slot[28], slot[29], slot[30], slot[31]]);
+    if crc != crc32(&slot[..CRC_OFFSET]) {
+        return None;
+    }
+    let known = |address: u8| (address != UNKNOWN).then_some(address);
+    let serial = u32::from_le_bytes([slot[12], slot[13], slot[14], slot[15]]);
+    let record = ResultRecord {
+        run: u32::from_le_bytes([slot[4], slot[5], slot[6], slot[7]]),
+        position: slot[8],
+        old_address: known(slot[9]),
+        new_address: known(slot[10]),
+        serial: (serial != u32::MAX).then_some(serial),
+        verdict: Verdict::from_u8(slot[11])?,
+        error: ErrorCode::from_u16(u16::from_le_bytes([slot[16], slot[17]]))?,
+    };
+    Some((
+        u32::from_le_bytes([slot[0], slot[1], slot[2], slot[3]]),
+        record,
+    ))
+}
+
+/// What a slot holds
+enum Slot {
+    Erased,
+    Record(u32, ResultRecord),
+    /// Written but unreadable, most likely cut short by a power loss
+    Damaged,
+}
+
+/// A place in the log for reading it a record at a time with [`ResultLog::next`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Format)]
+pub struct LogCursor {
+    /// The slot reading started at, which was the head at the time
+    start: Option<u32>,
+    /// How many slots after the start the next read starts at
+    ahead: u32,
+    /// The sequence number of the last record read
+    last: Option<u32>,
+}
+
+/// A ring log of [`ResultRecord`]s in a flash partition
+pub struct ResultLog<F: Flash> {
+    flash: F,
+    /// Offset of the first sector of the partition
+    base: u32,
+    /// The number of slots in the partition
+    slots: u32,
+    /// The slot the next record goes in
+    head: u32,
+    /// The sequence number of the next record
+    sequence: u32,
+    /// The number of readable records
+    len: u32,
+    /// The run of the newest record
+    last_run: Option<u32>,
+}
+
+impl<F: Flash> ResultLog<F> {
+    /// The number of slots in each sector
+    const SLOTS_PER_SECTOR: u32 = F::SECTOR_SIZE / SLOT_LEN;
+
+    /// Open the log in a partition. Nothing is written until the first record is appended.
+    ///
+    /// # Arguments
+    /// * `flash` - The flash the partition is on
+    /// * `base` - Offset of the partition, aligned to a sector
+    /// * `sectors` - The number of sectors in the partition, at least two
+    pub fn open(flash: F, base: u32, sectors: u32) -> Result<Self, FlashError> {
+        // Starting a sector erases the oldest records, so one sector alone would lose them all
+        if sectors < 2 {
+            return Err(FlashError::TooFewSectors);
+        }
+        let mut log = Self {
+            flash,
+            base,
+            slots: sectors * Self::SLOTS_PER_SECTOR,
+            head: 0,
+            sequence: 0,
+            len: 0,
+            last_run: None,
+        };
+
+        let mut newest: Option<(u32, u32, ResultRecord)> = None;
+        for index in 0..log.slots {
+            if let Slot::Record(sequence, record) = log.slot(index)? {
+                log.len += 1;
+                if newest.is_none_or(|(_, newest, _)| sequence > newest) {
+                    newest = Some((index, sequence, record));
+                }
+            }
+        }
+
+        if let Some((index, sequence, record)) = newest {
+            log.sequence = sequence.wrapping_add(1);
+            log.last_run = Some(record.run);
+            // Skip any slots after the newest record that a cut short write left damaged
+            log.head = index + 1;
+            while !log.head.is_multiple_of(Self::SLOTS_PER_SECTOR)
+                && !matches!(log.slot(log.head)?, Slot::Erased)
+            {
+                log.head += 1;
+            }
+            log.head %= log.slots;
+        }
+        Ok(log)
+    }
+
+    /// Add a record to the log, dropping the oldest sector of records if the log is full
+    pub fn append(&mut self, record: &ResultRecord) -> Result<(), FlashError> {
+        if self.head.is_multiple_of(Self::SLOTS_PER_SECTOR) {
+            // Always erase, as an erase cut short may have left the sector half cleared
+            for index in self.head..self.head + Self::SLOTS_PER_SECTOR {
+                if matches!(self.slot(index)?, Slot::Record(..)) {
+                    self.len -= 1;
+                }
+            }
+            self.flash.erase(self.slot_offset(self.head))?;
+        }
+        self.flash
+            .write(self.slot_offset(self.head), &encode(record, self.sequence))?;
+        self.head = (self.head + 1) % self.slots;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.len += 1;
+        self.last_run = Some(record.run);
+        Ok(())
+    }
+
+    /// The number of records in the log
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The run number of the newest record, or None if the log is empty
+    pub fn last_run(&self) -> Option<u32> {
+        self.last_run
+    }
+
+    /// Read a record by age.
+    ///
+    /// # Arguments
+    /// * `age` - How many records are newer than the one wanted, so 0 is the newest
+    ///
+    /// # Returns
+    /// The record, or None if the log holds no more than `age` records
+    pub fn newest(&mut self, age: u32) -> Result<Option<ResultRecord>, FlashError> {
+        let mut remaining = age;
+        let mut last = None;
+        for back in 1..=self.slots {
+            let index = (self.head + self.slots - back) % self.slots;
+            match self.slot(index)? {
+                Slot::Erased => break,
+                Slot::Damaged => continue,
+                Slot::Record(sequence, record) => {
+                    // Sequence numbers only go down from the newest record
+                    if last.is_some_and(|last| sequence >= last) {
+                        break;
+                    }
+                    last = Some(sequence);
+                    if remaining == 0 {
+                        return Ok(Some(record));
+                    }
+                    remaining -= 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the record after `cursor`, oldest first. Start from [`LogCursor::default`] to read
+    /// the whole log as it was then. Records appended while reading are left out, or end it
+    /// early if they replace records not yet read, so no record is read twice
+    ///
+    /// # Returns
+    /// The record, or None once there are no more
+    pub fn next(&mut self, cursor: &mut LogCursor) -> Result<Option<ResultRecord>, FlashError> {
+        let start = *cursor.start.get_or_insert(self.head);
+        while cursor.ahead < self.slots {
+            let index = (start + cursor.ahead) % self.slots;
+            cursor.ahead += 1;
+            if let Slot::Record(sequence, record) = self.slot(index)? {
+                // Sequence numbers only go up from the oldest record
+                if cursor.last.is_some_and(|last| sequence <= last) {
+                    break;
+                }
+                cursor.last = Some(sequence);
+                return Ok(Some(record));
+            }
+        }
+        cursor.ahead = self.slots;
+        Ok(None)
+    }
+
+    /// Call `f` with every record in the log, oldest first
+    pub fn for_each(&mut self, mut f: impl FnMut(&ResultRecord)) -> Result<(), FlashError> {
+        let mut cursor = LogCursor::default();
+        while let Some(record) = self.next(&mut cursor)? {
+            f(&record);
+        }
+        Ok(())
+    }
+
+    /// The flash the log is kept in
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// Close the log, giving back the flash
+    pub fn into_flash(self) -> F {
+        self.flash
+    }
+
+    /// Offset of a slot in flash
+    fn slot_offset(&self, index: u32) -> u32 {
+        self.base + index * SLOT_LEN
+    }
+
+    fn slot(&mut self, index: u32) -> Result<Slot, FlashError> {
+        let mut bytes = [0; SLOT_LEN as usize];
+        self.flash.read(self.slot_offset(index), &mut bytes)?;
+        Ok(if bytes.iter().all(|byte| *byte == 0xff) {
+            Slot::Erased
+        } else if let Some((sequence, record)) = decode(&bytes) {
+            Slot::Record(sequence, record)
+        } else {
+            Slot::Damaged
+        })
+    }
+}
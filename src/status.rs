@@ -0,0 +1,218 @@
+//! Status module maps what the jig is doing onto what the status LED shows.
+//!
+//! Every [`JigStatus`] has a [`StatusStyle`] giving it a colour and a [`Pattern`]. The operator
+//! can then read the state of the jig from the LED without looking at the OLED. To change how
+//! a state looks, edit its arm in [`JigStatus::style`].
+//!
+//! Fixtures with per-socket LEDs also show a [`SocketState`] next to each sensor socket, styled
+//! by [`SocketState::style`] in the same way.
+
+use crate::{
+    RgbRate, STATUS_PIXEL,
+    animations::{Animation, BreatheAnimation, Keyframe, SequenceAnimation, SparkleAnimation},
+    drivers::leds::PixelRange,
+    queue::Priority,
+    socket_pixel,
+};
+use defmt::Format;
+use smart_leds::RGB8;
+
+/// The states of the jig that have their own LED signal
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum JigStatus {
+    /// Waiting for the operator to start a run
+    Idle,
+    /// Looking for sensors on the bus
+    Scanning,
+    /// Programming the sensor at the given position
+    Programming(u8),
+    /// The last run completed and all sensors verified
+    Pass,
+    /// The last run completed with at least one failed sensor
+    Fail,
+    /// The operator needs to do something before the jig can continue
+    NeedsAttention,
+    /// Something went wrong inside the jig itself
+    InternalError,
+}
+
+/// How a status colour is animated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// Constant colour at the given brightness
+    Solid(u8),
+    /// Random brightness variations of the colour
+    Sparkle,
+    /// Ramp the colour between a minimum and maximum brightness at the given rate
+    Breathe { min: u8, max: u8, rate: RgbRate },
+    /// Play a table of keyframes forever. The keyframes carry their own colours
+    Sequence(&'static [Keyframe]),
+}
+
+/// Two red blinks then a pause
+pub const FAIL_BLINKS: [Keyframe; 4] = [
+    Keyframe::hold([255, 0, 0], 250),
+    Keyframe::hold([0, 0, 0], 250),
+    Keyframe::hold([255, 0, 0], 250),
+    Keyframe::hold([0, 0, 0], 1000),
+];
+
+/// The LED signal for one [`JigStatus`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusStyle {
+    /// The colour shown for this status
+    pub colour: [u8; 3],
+    /// How the colour is animated
+    pub pattern: Pattern,
+}
+
+/// The state of a single sensor socket
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum SocketState {
+    /// Nothing to show for this socket
+    Off,
+    /// The socket is waiting to be programmed in this run
+    Pending,
+    /// The sensor in this socket is being programmed
+    Active,
+    /// The sensor in this socket was programmed and verified
+    Ok,
+    /// The sensor in this socket failed
+    Fail,
+}
+
+impl StatusStyle {
+    /// Build the animation for this style on the given pixels. Status animations run until
+    /// they are replaced so they are always interruptable.
+    pub fn animation(&self, pixels: PixelRange) -> Animation {
+        let colour = RGB8::from(self.colour);
+        match self.pattern {
+            Pattern::Solid(level) => Animation::Breathe(
+                BreatheAnimation::new(colour, level, level, RgbRate::VerySlow, None).on(pixels),
+            ),
+            Pattern::Sparkle => Animation::Sparkle(SparkleAnimation::new(colour, None).on(pixels)),
+            Pattern::Breathe { min, max, rate } => {
+                Animation::Breathe(BreatheAnimation::new(colour, min, max, rate, None).on(pixels))
+            }
+            Pattern::Sequence(frames) => {
+                Animation::Sequence(SequenceAnimation::new(frames, None).on(pixels))
+            }
+        }
+    }
+}
+
+impl JigStatus {
+    /// The LED signal for this status
+    pub const fn style(&self) -> StatusStyle {
+        match self {
+            // Slow dim green breathe
+            JigStatus::Idle => StatusStyle {
+                colour: [0, 255, 0],
+                pattern: Pattern::Breathe {
+                    min: 10,
+                    max: 120,
+                    rate: RgbRate::Slow,
+                },
+            },
+            // Blue sparkle
+            JigStatus::Scanning => StatusStyle {
+                colour: [0, 0, 255],
+                pattern: Pattern::Sparkle,
+            },
+            // Fast cyan breathe
+            JigStatus::Programming(_) => StatusStyle {
+                colour: [0, 255, 255],
+                pattern: Pattern::Breathe {
+                    min: 40,
+                    max: 255,
+                    rate: RgbRate::Fast,
+                },
+            },
+            // Solid green
+            JigStatus::Pass => StatusStyle {
+                colour: [0, 255, 0],
+                pattern: Pattern::Solid(255),
+            },
+            // Two red blinks and a pause
+            JigStatus::Fail => StatusStyle {
+                colour: [255, 0, 0],
+                pattern: Pattern::Sequence(&FAIL_BLINKS),
+            },
+            // Amber breathe
+            JigStatus::NeedsAttention => StatusStyle {
+                colour: [255, 120, 0],
+                pattern: Pattern::Breathe {
+                    min: 0,
+                    max: 255,
+                    rate: RgbRate::Moderate,
+                },
+            },
+            // Very fast red breathe, reads as flashing
+            JigStatus::InternalError => StatusStyle {
+                colour: [255, 0, 0],
+                pattern: Pattern::Breathe {
+                    min: 0,
+                    max: 255,
+                    rate: RgbRate::VeryFast,
+                },
+            },
+        }
+    }
+
+    /// How urgently this status should replace whatever is showing
+    pub const fn priority(&self) -> Priority {
+        match self {
+            JigStatus::Idle => Priority::Background,
+            JigStatus::Scanning | JigStatus::Programming(_) | JigStatus::Pass => Priority::Info,
+            JigStatus::Fail | JigStatus::NeedsAttention => Priority::Warning,
+            JigStatus::InternalError => Priority::Critical,
+        }
+    }
+
+    /// Build the animation that shows this status on the status pixel
+    pub fn animation(&self) -> Animation {
+        self.style().animation(PixelRange::single(STATUS_PIXEL))
+    }
+}
+
+impl SocketState {
+    /// The LED signal for this socket state
+    pub const fn style(&self) -> StatusStyle {
+        match self {
+            SocketState::Off => StatusStyle {
+                colour: [0, 0, 0],
+                pattern: Pattern::Solid(0),
+            },
+            // Dim white
+            SocketState::Pending => StatusStyle {
+                colour: [255, 255, 255],
+                pattern: Pattern::Solid(40),
+            },
+            // Fast cyan breathe
+            SocketState::Active => StatusStyle {
+                colour: [0, 255, 255],
+                pattern: Pattern::Breathe {
+                    min: 40,
+                    max: 255,
+                    rate: RgbRate::Fast,
+                },
+            },
+            // Solid green
+            SocketState::Ok => StatusStyle {
+                colour: [0, 255, 0],
+                pattern: Pattern::Solid(255),
+            },
+            // Solid red
+            SocketState::Fail => StatusStyle {
+                colour: [255, 0, 0],
+                pattern: Pattern::Solid(255),
+            },
+        }
+    }
+
+    /// Build the animation that shows this state next to the socket at `pos`. Returns None if
+    /// the fixture has no LED for that socket.
+    pub fn animation(&self, pos: u8) -> Option<Animation> {
+        socket_pixel(pos).map(|index| self.style().animation(PixelRange::single(index)))
+    }
+}
@@ -0,0 +1,9 @@
+//! Storage module keeps small values in flash so they survive a reset. The store itself is
+//! [`jig_common::storage`], so it can be tested on the host.
+
+pub use jig_common::storage::{Key, KvStore, MAX_VALUE_LEN, StorageError};
+
+/// Offset of the `store` partition in `partitions.csv`
+pub const STORE_PARTITION_OFFSET: u32 = 0x31_0000;
+/// Number of 4 KiB sectors in the `store` partition
+pub const STORE_PARTITION_SECTORS: u32 = 4;
@@ -1,79 +1,147 @@
-use core::pin;
-
-// use embassy_futures::select::{Either, select};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use defmt::{Format, debug};
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
 use embassy_time::{Duration, Instant, Timer};
-use esp_hal::{
-    gpio::{self, InputConfig, OutputConfig, Pull},
-    peripherals::{GPIO3, GPIO9},
+
+use crate::drivers::{
+    button::DebouncedButton,
+    encoder::{Direction, RotaryEncoder},
 };
-use futures::future::{Either, select};
-use smart_leds::RGB8;
+pub use crate::gestures::{ButtonEvent, ButtonId, EncoderAction, Gesture};
+use crate::gestures::{GestureRecognizer, GestureTimings};
+
+/// A button being pressed or released
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub struct ButtonEdge {
+    pub button: ButtonId,
+    /// True if the button was pressed, false if it was released
+    pub pressed: bool,
+    pub at: Instant,
+}
 
-use crate::{RGB_CONFIG, RgbMode};
+const EDGE_QUEUE_SIZE: usize = 8;
+/// Channel types for the button tasks to send edges to the gesture task.
+pub type EdgeChannel = Channel<CriticalSectionRawMutex, ButtonEdge, EDGE_QUEUE_SIZE>;
+pub type EdgeChannelSender = Sender<'static, CriticalSectionRawMutex, ButtonEdge, EDGE_QUEUE_SIZE>;
+pub type EdgeChannelReceiver =
+    Receiver<'static, CriticalSectionRawMutex, ButtonEdge, EDGE_QUEUE_SIZE>;
 
-pub static BUTTON_STATE: Signal<CriticalSectionRawMutex, ButtonEvent> = Signal::new();
+const BUTTON_QUEUE_SIZE: usize = 4;
+/// Channel types for the gesture task to publish button events.
+pub type ButtonChannel = Channel<CriticalSectionRawMutex, ButtonEvent, BUTTON_QUEUE_SIZE>;
+pub type ButtonChannelSender =
+    Sender<'static, CriticalSectionRawMutex, ButtonEvent, BUTTON_QUEUE_SIZE>;
+pub type ButtonChannelReceiver =
+    Receiver<'static, CriticalSectionRawMutex, ButtonEvent, BUTTON_QUEUE_SIZE>;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum ButtonEvent {
-    Press,
-    HoldHalfSecond,
-    HoldFullSecond,
+/// Button watcher task. One instance runs per button, publishing a [`ButtonEdge`] each time the
+/// button is pressed or released.
+///
+/// # Parameters
+/// * `id` - Which button this instance watches
+/// * `button` - The debounced button input
+/// * `sender` - Where to publish the edges
+#[embassy_executor::task(pool_size = 2)]
+pub async fn button_task(id: ButtonId, mut button: DebouncedButton, sender: EdgeChannelSender) {
+    loop {
+        let transition = button.wait_for_transition().await;
+        sender
+            .send(ButtonEdge {
+                button: id,
+                pressed: transition.pressed,
+                at: transition.at,
+            })
+            .await;
+    }
 }
 
+/// Gesture task. Turns the edges from both button tasks into [`ButtonEvent`]s.
+///
+/// # Parameters
+/// * `edges` - Where the button tasks publish their edges
+/// * `timings` - The times that separate one gesture from another
+/// * `sender` - Where to publish the button events
 #[embassy_executor::task]
-pub async fn handle_button(led_pin: GPIO3<'static>, button_pin: GPIO9<'static>) {
-    let mut led = gpio::Output::new(led_pin, gpio::Level::Low, OutputConfig::default());
-    let mut button = gpio::Input::new(button_pin, InputConfig::default().with_pull(Pull::Up));
+pub async fn gesture_task(
+    edges: EdgeChannelReceiver,
+    timings: GestureTimings,
+    sender: ButtonChannelSender,
+) {
+    let mut recognizer = GestureRecognizer::new(timings);
     loop {
-        button.wait_for_low().await;
-        let time_down = Instant::now();
-        led.set_high();
-        let wait_for_high = pin::pin!(button.wait_for_high());
-        let res = select(wait_for_high, Timer::after_millis(500)).await;
-        match res {
-            Either::Left((_value1, _future2)) => {}
-            Either::Right((_value2, button_release)) => {
-                // In this case, the button is being held, so set colour and wait for release
+        let edge = match recognizer.deadline() {
+            Some(deadline) => match select(edges.receive(), Timer::at(deadline)).await {
+                Either::First(edge) => Some(edge),
+                Either::Second(_) => None,
+            },
+            None => Some(edges.receive().await),
+        };
 
-                let previous_mode: RgbMode;
-                {
-                    let mut config = RGB_CONFIG.lock().await;
-                    previous_mode = config.rgb_mode.clone();
-                    // "White"
-                    config.set_mode(RgbMode::Static(RGB8::new(190, 240, 255)));
-                }
-                match select(button_release, Timer::after_millis(500)).await {
-                    // Button released before next 0.5s
-                    Either::Left(_) => {}
-                    Either::Right((_, button_release)) => {
-                        {
-                            RGB_CONFIG
-                                .lock()
-                                .await
-                                .set_mode(RgbMode::Static(RGB8::new(0, 0, 255)))
-                        }
-                        button_release.await;
-                    }
-                }
-                RGB_CONFIG.lock().await.set_mode(previous_mode)
-            }
+        if let Some(edge) = edge
+            && let Some(event) = recognizer.edge(edge.button, edge.pressed, edge.at)
+        {
+            debug!("BUTTON_TASK: {}", event);
+            sender.send(event).await;
         }
+        while let Some(event) = recognizer.poll(Instant::now()) {
+            debug!("BUTTON_TASK: {}", event);
+            sender.send(event).await;
+        }
+    }
+}
 
-        let duration_pressed = Instant::now() - time_down;
-        led.set_low();
-        let button_event = if duration_pressed > Duration::from_ticks(25000) {
-            if duration_pressed > Duration::from_millis(1000) {
-                ButtonEvent::HoldFullSecond
-            } else if duration_pressed > Duration::from_millis(500) {
-                ButtonEvent::HoldHalfSecond
-            } else {
-                ButtonEvent::Press
+/// Rotary encoder task. Publishes a [`ButtonEvent::Encoder`] for each detent turned and for each
+/// click or long press of the push switch.
+///
+/// # Parameters
+/// * `encoder` - The encoder's quadrature inputs
+/// * `switch` - The encoder's debounced push switch
+/// * `long_press` - How long the switch must be held to be a long press
+/// * `sender` - Where to publish the button events
+#[embassy_executor::task]
+pub async fn encoder_task(
+    mut encoder: RotaryEncoder,
+    mut switch: DebouncedButton,
+    long_press: Duration,
+    sender: ButtonChannelSender,
+) {
+    // When the switch went down, until it is released or held long enough
+    let mut pressed_at: Option<Instant> = None;
+    loop {
+        let long_press_due = async move {
+            match pressed_at {
+                Some(at) => Timer::at(at + long_press).await,
+                None => core::future::pending().await,
+            }
+        };
+        let action = match select3(
+            encoder.wait_for_step(),
+            switch.wait_for_transition(),
+            long_press_due,
+        )
+        .await
+        {
+            Either3::First(Direction::Clockwise) => EncoderAction::Clockwise,
+            Either3::First(Direction::AntiClockwise) => EncoderAction::AntiClockwise,
+            Either3::Second(transition) if transition.pressed => {
+                pressed_at = Some(transition.at);
+                continue;
+            }
+            Either3::Second(_) => match pressed_at.take() {
+                Some(_) => EncoderAction::Click,
+                // Already reported as a long press
+                None => continue,
+            },
+            Either3::Third(_) => {
+                pressed_at = None;
+                EncoderAction::LongPress
             }
-        } else {
-            continue;
         };
-        defmt::dbg!("Button Press: ", &button_event);
-        BUTTON_STATE.signal(button_event);
+        let event = ButtonEvent::Encoder(action);
+        debug!("BUTTON_TASK: {}", event);
+        sender.send(event).await;
     }
 }
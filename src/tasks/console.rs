@@ -0,0 +1,223 @@
+use defmt::{Format, info, warn};
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{Channel, Receiver, Sender},
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::{Read, Write};
+use esp_hal::{
+    Async,
+    usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagTx},
+};
+use jig_common::{
+    console::{
+        Command, CommandError, LineBuffer, Reply, ReplyLine, parse, reply_command_error,
+        reply_parse_error,
+    },
+    protocol::{FIRST_FRAME_TIMEOUT, FrameReader, MAX_FRAME, Request, Respond, Response, encode},
+    record::ResultRecord,
+};
+
+/// Something for the main task to carry out
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum Incoming {
+    /// A command typed at the console
+    Command(Command),
+    /// A request from a host speaking the binary protocol
+    Request(Request),
+}
+
+/// Something for the console task to write to the port
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    /// A line of a reply to a console command
+    Line(ReplyLine),
+    /// A response to a protocol request
+    Response(Response),
+    /// A result being monitored, written in whichever way the port is being used
+    Result(ResultRecord),
+}
+
+const COMMAND_QUEUE_SIZE: usize = 2;
+/// Channel types for commands and requests from the console task.
+pub type CommandChannel = Channel<CriticalSectionRawMutex, Incoming, COMMAND_QUEUE_SIZE>;
+pub type CommandChannelSender =
+    Sender<'static, CriticalSectionRawMutex, Incoming, COMMAND_QUEUE_SIZE>;
+pub type CommandChannelReceiver =
+    Receiver<'static, CriticalSectionRawMutex, Incoming, COMMAND_QUEUE_SIZE>;
+
+const REPLY_QUEUE_SIZE: usize = 8;
+/// Channel types for output to write to the console.
+pub type ReplyChannel = Channel<CriticalSectionRawMutex, Output, REPLY_QUEUE_SIZE>;
+pub type ReplyChannelSender = Sender<'static, CriticalSectionRawMutex, Output, REPLY_QUEUE_SIZE>;
+pub type ReplyChannelReceiver =
+    Receiver<'static, CriticalSectionRawMutex, Output, REPLY_QUEUE_SIZE>;
+
+/// How long to wait for the host to take a line. Nothing reads the port while it isn't plugged
+/// into a computer, and the line is dropped rather than holding up the replies behind it
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Sends the lines of a reply to the console task
+pub struct ConsoleReply(pub ReplyChannelSender);
+
+impl Reply for ConsoleReply {
+    async fn line(&mut self, text: &str) {
+        let mut line = ReplyLine::new();
+        // Keep what fits of a line that is too long
+        for c in text.chars() {
+            if line.push(c).is_err() {
+                break;
+            }
+        }
+        self.0.send(Output::Line(line)).await
+    }
+}
+
+impl Respond for ConsoleReply {
+    async fn send(&mut self, response: Response) {
+        self.0.send(Output::Response(response)).await
+    }
+}
+
+/// Writes straight to the port, for output from the console task itself
+struct Port<'a>(&'a mut UsbSerialJtagTx<'static, Async>);
+
+impl Port<'_> {
+    async fn write(&mut self, bytes: &[u8]) {
+        let write = async {
+            // The port can't fail, only wait for the host
+            let _ = self.0.write_all(bytes).await;
+            let _ = self.0.flush().await;
+        };
+        if with_timeout(WRITE_TIMEOUT, write).await.is_err() {
+            warn!("CONSOLE_TASK: Dropped output, nothing is reading the port");
+        }
+    }
+
+    /// Write output in the way the port is being used
+    ///
+    /// # Arguments
+    /// * `output` - What to write
+    /// * `binary` - True if the port is speaking the protocol rather than the console
+    async fn output(&mut self, output: Output, binary: bool) {
+        match output {
+            Output::Line(line) => self.line(&line).await,
+            Output::Response(response) => self.send(response).await,
+            Output::Result(record) if binary => self.send(Response::Result(record)).await,
+            Output::Result(record) => {
+                let mut line = ReplyLine::new();
+                let _ = record.write_csv(&mut line);
+                self.line(&line).await
+            }
+        }
+    }
+}
+
+impl Reply for Port<'_> {
+    async fn line(&mut self, text: &str) {
+        self.write(text.as_bytes()).await;
+        self.write(b"\r\n").await;
+    }
+}
+
+impl Respond for Port<'_> {
+    async fn send(&mut self, response: Response) {
+        let mut frame = [0; MAX_FRAME];
+        match encode(&response, &mut frame) {
+            Ok(len) => self.write(&frame[..len]).await,
+            Err(e) => warn!("CONSOLE_TASK: Failed to encode {}: {}", response, e),
+        }
+    }
+}
+
+/// Console task. Reads lines from the USB serial port, parses them and sends the commands to
+/// the main task to carry out, then writes the reply lines it gets back. Typed characters are
+/// echoed so the console can be used from a terminal.
+///
+/// A zero byte switches the port to the binary protocol, where framed requests are read
+/// instead of lines, until a [`Request::Close`] switches it back. If no frame decodes within
+/// [`FIRST_FRAME_TIMEOUT`] the zero byte was a stray one, and the port goes back to the console.
+///
+/// # Parameters
+/// * `port` - The USB Serial/JTAG port
+/// * `commands` - Where to send the commands and requests
+/// * `replies` - The output to write back
+#[embassy_executor::task]
+pub async fn console_task(
+    port: UsbSerialJtag<'static, Async>,
+    commands: CommandChannelSender,
+    replies: ReplyChannelReceiver,
+) {
+    let (mut rx, mut tx) = port.split();
+    let mut buffer = LineBuffer::new();
+    let mut frames = FrameReader::new();
+    let mut binary = false;
+    // When the port goes back to the console unless a frame decodes first
+    let mut first_frame_by = None;
+    let mut bytes = [0; 16];
+    loop {
+        let gave_up = Timer::at(first_frame_by.unwrap_or(Instant::MAX));
+        match select3(rx.read(&mut bytes), replies.receive(), gave_up).await {
+            Either3::First(result) => {
+                // Reading the port can't fail
+                let Ok(len) = result;
+                let mut port = Port(&mut tx);
+                for &byte in &bytes[..len] {
+                    if binary {
+                        match frames.push::<Request>(byte) {
+                            Some(Ok(request)) => {
+                                info!("CONSOLE_TASK: {}", request);
+                                first_frame_by = None;
+                                if request == Request::Close {
+                                    info!("CONSOLE_TASK: Back to the console");
+                                    binary = false;
+                                }
+                                // Never wait on the main task, as it may be waiting to reply
+                                if commands.try_send(Incoming::Request(request)).is_err() {
+                                    port.send(Response::Error(CommandError::Busy)).await;
+                                }
+                            }
+                            // The host times out waiting for an answer and asks again
+                            Some(Err(e)) => warn!("CONSOLE_TASK: Dropped a frame: {}", e),
+                            None => {}
+                        }
+                        continue;
+                    }
+                    if byte == 0 {
+                        info!("CONSOLE_TASK: Switched to the protocol");
+                        binary = true;
+                        first_frame_by = Some(Instant::now() + FIRST_FRAME_TIMEOUT);
+                        buffer = LineBuffer::new();
+                        continue;
+                    }
+                    let line = buffer.push(byte);
+                    match (byte, &line) {
+                        (_, Some(_)) => port.write(b"\r\n").await,
+                        (0x08 | 0x7f, None) => port.write(b"\x08 \x08").await,
+                        (b' '..=b'~', None) => port.write(&[byte]).await,
+                        _ => {}
+                    }
+                    match line.map(|line| line.and_then(|line| parse(&line))) {
+                        Some(Ok(command)) => {
+                            info!("CONSOLE_TASK: {}", command);
+                            // Never wait on the main task, as it may be waiting to reply
+                            if commands.try_send(Incoming::Command(command)).is_err() {
+                                reply_command_error(CommandError::Busy, &mut port).await;
+                            }
+                        }
+                        Some(Err(e)) => reply_parse_error(e, &mut port).await,
+                        None => {}
+                    }
+                }
+            }
+            Either3::Second(output) => Port(&mut tx).output(output, binary).await,
+            Either3::Third(()) => {
+                info!("CONSOLE_TASK: No frame came, back to the console");
+                binary = false;
+                first_frame_by = None;
+                frames = FrameReader::new();
+            }
+        }
+    }
+}
@@ -1,9 +1,18 @@
-use crate::animations::{Animation, SparkleAnimation, is_interruptable, next_buffer};
+use crate::animations::{Animation, CrossFade, is_interruptable, next_buffer, pixels};
 use crate::{
-    ANIMATION_UPDATE, DEFAULT_COLOUR, MAX_PENDING_ANIMATIONS,
-    drivers::neopixel::{LedBuffer, LedDriver},
+    ANIMATION_UPDATE, DEFAULT_CROSSFADE, MAX_PENDING_ANIMATIONS, SOCKET_COUNT, STATUS_PIXEL,
+    compositor::{self, Compositor},
+    drivers::{
+        LedBackend,
+        leds::{LedBuffer, LedDriver, LedError, LedOutput, PixelRange},
+    },
+    panic_record::PanicRecord,
+    queue::{FullPolicy, Priority, PriorityQueue, Pushed, preempts},
+    result_log::ResultRecord,
+    settings::{Settings, SettingsFault},
+    status::{JigStatus, SocketState},
 };
-use defmt::{debug, error, info};
+use defmt::{debug, error, info, warn};
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_futures::{
     select::{Either, select},
@@ -13,7 +22,7 @@ use embassy_sync::{
     blocking_mutex::raw::CriticalSectionRawMutex,
     channel::{Channel, Receiver, Sender},
 };
-use embassy_time::{Duration, Ticker};
+use embassy_time::{Duration, Instant, Ticker};
 use embedded_graphics::{
     Drawable,
     mono_font::{MonoTextStyleBuilder, iso_8859_9::FONT_10X20},
@@ -22,8 +31,6 @@ use embedded_graphics::{
     text::{Baseline, Text},
 };
 use esp_hal::{Async, i2c::master::I2c};
-use heapless::spsc::Queue;
-use smart_leds::RGB8;
 use ssd1306::{
     I2CDisplayInterface, Ssd1306Async, mode::DisplayConfigAsync, prelude::DisplayRotation,
     size::DisplaySize128x64,
@@ -45,10 +52,31 @@ pub enum DisplayState {
     Init,
     /// Enable/disable torch function
     Torch(bool),
+    /// Apply the display related settings
+    Configure(Settings),
+    /// Warn that the stored settings couldn't be used
+    SettingsWarning(SettingsFault),
     /// Set the display brightness
     Brightness(u8),
-    /// Set the address of the sensor at the given position
-    SetAddress(u8),
+    /// Show the address being given to the sensor at the given position
+    SetAddress(u8, u8),
+    /// Show a record from the result log
+    LogRecord(ResultRecord),
+    /// Show that the result log has no records to browse
+    LogEmpty,
+    /// Show the first pass yield for the session and for the jig's lifetime, in tenths of a
+    /// percent, or None where no sensors have been tried
+    Stats(Option<u16>, Option<u16>),
+    /// Show the last panic, or that there hasn't been one since it was cleared
+    Panic(Option<PanicRecord>),
+    /// Show the given jig status on the LED
+    Status(JigStatus),
+    /// Show the state of the socket at the given position on its LED, if it has one
+    Socket(u8, SocketState),
+    /// Draw a short-lived animation over the current one without interrupting it
+    Overlay(compositor::Overlay),
+    /// Set how long to cross-fade from one animation to the next
+    CrossFadeTime(Duration),
 }
 
 const DISPLAY_QUEUE_SIZE: usize = 10;
@@ -60,6 +88,51 @@ pub type DisplayChannelReceiver =
     Receiver<'static, CriticalSectionRawMutex, DisplayState, DISPLAY_QUEUE_SIZE>;
 pub type I2cBus = I2cDevice<'static, CriticalSectionRawMutex, I2c<'static, Async>>;
 
+/// Write a yield in tenths of a percent as a percentage, or a dash if there isn't one
+fn write_yield(msg: &mut heapless::String<48>, label: &str, permille: Option<u16>) {
+    match permille {
+        Some(y) => ufmt::uwrite!(msg, "{} {}.{}%\n", label, y / 10, y % 10),
+        None => ufmt::uwrite!(msg, "{} -\n", label),
+    }
+    .unwrap();
+}
+
+/// The shortest time between warnings about failed LED updates, so a dead string doesn't log a
+/// warning every frame
+const LED_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Logs failed LED updates. The LED only shows status, so the jig carries on without it
+#[derive(Default)]
+struct FailureLog {
+    /// When we last warned about a failure
+    last_warning: Option<Instant>,
+    /// Failures since then that weren't logged
+    missed: u32,
+}
+
+impl FailureLog {
+    /// Warn about a failed LED update, unless we did so recently
+    fn report(&mut self, result: Result<(), LedError>) {
+        let Err(e) = result else {
+            return;
+        };
+        let now = Instant::now();
+        if self
+            .last_warning
+            .is_some_and(|at| now < at + LED_WARNING_INTERVAL)
+        {
+            self.missed += 1;
+            return;
+        }
+        warn!(
+            "DISPLAY_TASK: LED update failed: {} ({} more since the last warning)",
+            e, self.missed
+        );
+        self.last_warning = Some(now);
+        self.missed = 0;
+    }
+}
+
 /// Display driver main task.
 /// The display is fully managed from this task. It contains the state and responds to messages
 /// sent to it via the channel.
@@ -72,18 +145,37 @@ pub type I2cBus = I2cDevice<'static, CriticalSectionRawMutex, I2c<'static, Async
 #[embassy_executor::task]
 pub async fn display_task(
     channel: &'static DisplayChannelReceiver,
-    led: &'static mut LedDriver,
+    led: &'static mut LedDriver<Option<LedBackend>>,
+    i2c_bus: &'static I2cBus,
+) {
+    // Tasks can't be generic, so hand over to a loop that only knows the LED output trait
+    display_loop(channel, led, i2c_bus).await
+}
+
+/// The body of [`display_task`], written against any [`LedOutput`]
+async fn display_loop<O: LedOutput>(
+    channel: &'static DisplayChannelReceiver,
+    led: &mut LedDriver<O>,
     i2c_bus: &'static I2cBus,
 ) {
     let mut animation = Ticker::every(Duration::from_millis(ANIMATION_UPDATE));
     let mut running = true;
-    let mut animation_queue: Queue<Animation, MAX_PENDING_ANIMATIONS> = Queue::new();
-    let mut current_animation = Animation::Sparkle(SparkleAnimation::new(
-        RGB8::from(DEFAULT_COLOUR),
-        Some(Duration::from_secs(2)),
-    ));
-    let mut brightness: u8 = 10;
+    let mut animation_queue: PriorityQueue<Animation, MAX_PENDING_ANIMATIONS> =
+        PriorityQueue::new(FullPolicy::DropLowest);
+    let mut current_animation = JigStatus::Idle.animation();
+    let mut current_priority = Priority::Background;
+    // Each socket LED runs its own animation alongside the status pixel
+    let mut socket_animations: [Option<Animation>; SOCKET_COUNT] = Default::default();
+    // Overlays are drawn on top of everything else until they expire
+    let mut compositor = Compositor::new();
+    // Changes of animation cross-fade from the last frame shown rather than jumping
+    let mut crossfade = Duration::from_millis(DEFAULT_CROSSFADE);
+    let mut transition: Option<CrossFade> = None;
+    let mut last_frame = LedBuffer::default();
+    let mut brightness = Settings::default().led_brightness;
+    let mut torch_brightness = Settings::default().torch_brightness;
     let mut torch = false;
+    let mut failures = FailureLog::default();
 
     let i2c_dev1 = I2cDevice::new(i2c_bus);
     let interface = I2CDisplayInterface::new(i2c_dev1);
@@ -121,21 +213,28 @@ pub async fn display_task(
                 // The ticker woke us up
                 if running {
                     // Look at our state and return something that we can display.
-                    // Note we must peek into animation_queue because if we are interruptable, we must
+                    // Note we must peek into animation_queue because if we can't be preempted, we must
                     // leave the next animation in the queue until the current animation terminates.
+                    let preempt = animation_queue.peek().is_some_and(|(priority, _)| {
+                        preempts(
+                            current_priority,
+                            is_interruptable(&current_animation),
+                            *priority,
+                        )
+                    });
                     let mut new_buf: Option<LedBuffer> = match (
                         next_buffer(&mut current_animation),
                         animation_queue.peek(),
-                        is_interruptable(&current_animation),
+                        preempt,
                     ) {
-                        // A new animation and the current one is interruptable, set up the new one.
-                        (_, Some(animation), true) => {
+                        // A new animation that can preempt the current one, set up the new one.
+                        (_, Some((priority, animation)), true) => {
                             debug!(
-                                "DISPLAY_TASK: Animation {} replaced by updated {}",
-                                current_animation, animation
+                                "DISPLAY_TASK: Animation {} replaced by updated {} at {}",
+                                current_animation, animation, priority
                             );
-                            current_animation = animation.clone();
-                            animation_queue.dequeue().unwrap(); // Infallible drop because the peek was Some()
+                            (current_priority, current_animation) = animation_queue.pop().unwrap(); // Infallible because the peek was Some()
+                            transition = Some(CrossFade::new(last_frame, crossfade));
                             next_buffer(&mut current_animation)
                         }
                         // Just one animation running, so let it roll
@@ -146,35 +245,59 @@ pub async fn display_task(
                             );
                             Some(buf)
                         }
-                        // A new animation available but we are not interruptable, return the current animation next buffer
-                        (Some(buf), Some(animation), false) => {
+                        // A new animation available but it can't preempt us, return the current animation next buffer
+                        (Some(buf), Some((_, animation)), false) => {
                             debug!(
                                 "DISPLAY_TASK: Uninterruptible animation {} updated with pending animation {}",
                                 current_animation, animation
                             );
                             Some(buf)
                         }
-                        // Current animation terminates, no new animation so revert to default
+                        // Current animation terminates, no new animation so fade out to off
                         (None, None, _) => {
-                            debug!("DISPLAY_TASK: No animations found. Reverting to the default");
-                            // current_animation = default.clone();
-                            led.all_off().await;
-                            next_buffer(&mut current_animation)
+                            debug!("DISPLAY_TASK: No animations found. Fading out");
+                            if transition.is_none() && last_frame != LedBuffer::default() {
+                                transition = Some(CrossFade::new(last_frame, crossfade));
+                            }
+                            Some(LedBuffer::default())
                         }
                         // No new buffer and a pending animation
-                        (None, Some(animation), _) => {
+                        (None, Some((_, animation)), _) => {
                             debug!(
                                 "DISPLAY_TASK: No current animation with a pending animation {}",
                                 animation
                             );
-                            current_animation = animation.clone();
-                            animation_queue.dequeue().unwrap(); // Infallible drop because the peek was Some()
+                            (current_priority, current_animation) = animation_queue.pop().unwrap(); // Infallible because the peek was Some()
+                            transition = Some(CrossFade::new(last_frame, crossfade));
                             next_buffer(&mut current_animation)
                         }
                     };
+                    // Blend the outgoing frame into the new one until the cross-fade is done
+                    if let (Some(fade), Some(b)) = (&mut transition, &mut new_buf)
+                        && !fade.apply(b)
+                    {
+                        transition = None;
+                    }
+                    if let Some(b) = new_buf {
+                        last_frame = b;
+                    }
+                    // Draw each socket animation over its own pixels
+                    for slot in socket_animations.iter_mut() {
+                        if let Some(anim) = slot {
+                            match next_buffer(anim) {
+                                Some(buf) => {
+                                    pixels(anim).blit(new_buf.get_or_insert_default(), &buf)
+                                }
+                                None => *slot = None,
+                            }
+                        }
+                    }
+                    if !compositor.is_empty() {
+                        compositor.compose(new_buf.get_or_insert_default());
+                    }
                     // The buffer is still wrapped in an option, so grab it. It will never be None
-                    if let Some(ref mut b) = new_buf {
-                        led.update_from_buffer(b, brightness).await;
+                    if let Some(ref b) = new_buf {
+                        failures.report(led.update_from_buffer(b, brightness).await);
                     } // Just let the default animation pick this one up if we don't have a new buffer
                 }
             }
@@ -186,7 +309,7 @@ pub async fn display_task(
                     Stop => running = false,
                     Start => running = true,
                     Off => {
-                        led.all_off().await;
+                        failures.report(led.all_off().await);
                         running = false;
                     }
                     On => {
@@ -204,26 +327,42 @@ pub async fn display_task(
                         .unwrap();
                         display.flush().await.unwrap();
                     }
-                    Brightness(b) => {
-                        brightness = b;
+                    Configure(settings) => {
+                        brightness = settings.led_brightness;
+                        torch_brightness = settings.torch_brightness;
                         if torch {
-                            led.white(brightness).await;
+                            failures.report(led.white(torch_brightness).await);
                         }
                     }
+                    SettingsWarning(fault) => {
+                        warn!("DISPLAY_TASK: Settings not used: {}", fault);
+                        display.clear_buffer();
+                        Text::with_baseline(
+                            fault.message(),
+                            Point::zero(),
+                            text_style,
+                            Baseline::Top,
+                        )
+                        .draw(&mut display)
+                        .unwrap();
+                        display.flush().await.unwrap();
+                    }
+                    Brightness(b) => {
+                        brightness = b;
+                    }
                     Torch(on) => {
                         if on {
                             running = false;
                             torch = true;
-                            led.white(brightness).await;
+                            failures.report(led.white(torch_brightness).await);
                         } else {
                             running = true;
                             torch = false;
-                            led.all_off().await;
+                            failures.report(led.all_off().await);
                         };
                     }
-                    SetAddress(pos) => {
+                    SetAddress(pos, addr) => {
                         display.clear_buffer();
-                        let addr = pos + 0x08;
                         let mut msg = heapless::String::<32>::new();
                         ufmt::uwrite!(msg, "Position: {}\nAddress: 0x{:x}", pos, addr).unwrap();
                         Text::with_baseline(msg.as_str(), Point::zero(), text_style, Baseline::Top)
@@ -231,6 +370,103 @@ pub async fn display_task(
                             .unwrap();
                         display.flush().await.unwrap();
                     }
+                    LogRecord(record) => {
+                        display.clear_buffer();
+                        let mut msg = heapless::String::<48>::new();
+                        ufmt::uwrite!(msg, "Run {} P{}\n", record.run, record.position).unwrap();
+                        match record.new_address {
+                            Some(addr) => {
+                                ufmt::uwrite!(msg, "{} 0x{:x}\n", record.verdict.name(), addr)
+                            }
+                            None => ufmt::uwrite!(msg, "{} -\n", record.verdict.name()),
+                        }
+                        .unwrap();
+                        match record.serial {
+                            Some(serial) => ufmt::uwrite!(msg, "SN {}", serial),
+                            None => ufmt::uwrite!(msg, "E{}", record.error as u16),
+                        }
+                        .unwrap();
+                        Text::with_baseline(msg.as_str(), Point::zero(), text_style, Baseline::Top)
+                            .draw(&mut display)
+                            .unwrap();
+                        display.flush().await.unwrap();
+                    }
+                    LogEmpty => {
+                        display.clear_buffer();
+                        Text::with_baseline("Log empty", Point::zero(), text_style, Baseline::Top)
+                            .draw(&mut display)
+                            .unwrap();
+                        display.flush().await.unwrap();
+                    }
+                    Stats(session, lifetime) => {
+                        display.clear_buffer();
+                        let mut msg = heapless::String::<48>::new();
+                        msg.push_str("Yield\n").unwrap();
+                        write_yield(&mut msg, "Now", session);
+                        write_yield(&mut msg, "Life", lifetime);
+                        Text::with_baseline(msg.as_str(), Point::zero(), text_style, Baseline::Top)
+                            .draw(&mut display)
+                            .unwrap();
+                        display.flush().await.unwrap();
+                    }
+                    Panic(record) => {
+                        display.clear_buffer();
+                        let mut msg = heapless::String::<160>::new();
+                        match record {
+                            Some(record) => ufmt::uwrite!(
+                                msg,
+                                "Panic {}s\n{}:{}\n{}",
+                                record.uptime.as_secs(),
+                                record.file_name(),
+                                record.line,
+                                record.message.as_str()
+                            ),
+                            None => ufmt::uwrite!(msg, "No panic"),
+                        }
+                        .unwrap();
+                        Text::with_baseline(msg.as_str(), Point::zero(), text_style, Baseline::Top)
+                            .draw(&mut display)
+                            .unwrap();
+                        display.flush().await.unwrap();
+                    }
+                    Status(status) => {
+                        debug!("DISPLAY_TASK: Status changed to {}", status);
+                        // The status on show is over once another is reported, so it no longer
+                        // holds off lower priorities. An internal error stays until the reset
+                        if is_interruptable(&current_animation)
+                            && current_priority < Priority::Critical
+                        {
+                            current_priority = current_priority.min(status.priority());
+                        }
+                        // Any status still waiting is stale too, so the new one replaces it
+                        // rather than queueing behind it
+                        let status_pixel = PixelRange::single(STATUS_PIXEL);
+                        animation_queue.retain(|_, animation| pixels(animation) != status_pixel);
+                        match animation_queue.push(status.animation(), status.priority()) {
+                            Ok(Pushed::Queued) => {}
+                            Ok(Pushed::Duplicate) => {
+                                debug!("DISPLAY_TASK: Status {} already pending", status)
+                            }
+                            Ok(Pushed::Evicted(animation)) => {
+                                warn!("DISPLAY_TASK: Animation queue full, dropped {}", animation)
+                            }
+                            Err(_) => {
+                                warn!("DISPLAY_TASK: Animation queue full, dropping {}", status)
+                            }
+                        }
+                    }
+                    Socket(pos, state) => {
+                        debug!("DISPLAY_TASK: Socket {} changed to {}", pos, state);
+                        if let Some(slot) = socket_animations.get_mut(pos as usize) {
+                            *slot = state.animation(pos);
+                        }
+                    }
+                    Overlay(overlay) => {
+                        if compositor.push(overlay).is_err() {
+                            warn!("DISPLAY_TASK: Too many overlays, dropping new overlay");
+                        }
+                    }
+                    CrossFadeTime(duration) => crossfade = duration,
                 }
             }
         };
@@ -0,0 +1,129 @@
+use core::cell::Cell;
+
+use defmt::{Format, info};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::{Channel, Receiver, Sender},
+};
+
+use crate::drivers::button::DebouncedButton;
+
+/// What an external input is used for
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum InputRole {
+    /// Starts a programming run when pressed, such as a foot pedal
+    Start,
+    /// Aborts the programming run when pressed
+    Abort,
+    /// Active while the fixture lid is closed. Sensors are only written while it is
+    Interlock,
+}
+
+/// Something that happened on an external input
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum InputEvent {
+    /// The start input was pressed
+    Start,
+    /// The abort input was pressed
+    Abort,
+    /// The lid was opened
+    InterlockOpened,
+    /// The lid was closed
+    InterlockClosed,
+}
+
+/// The state of the lid interlock
+#[derive(Debug, Clone, Copy, PartialEq, Format)]
+pub enum InterlockState {
+    /// This jig has no interlock, so nothing is stopped by it
+    NotFitted,
+    /// The lid is open. Nothing may be written to the sensors
+    Open,
+    /// The lid is closed
+    Closed,
+}
+
+static INTERLOCK: Mutex<CriticalSectionRawMutex, Cell<InterlockState>> =
+    Mutex::new(Cell::new(InterlockState::NotFitted));
+
+/// The current state of the lid interlock
+pub fn interlock_state() -> InterlockState {
+    INTERLOCK.lock(|state| state.get())
+}
+
+/// True if sensors may be written, because the lid is closed or the jig has no interlock. Check
+/// this before every write to the sensor bus
+pub fn interlock_closed() -> bool {
+    interlock_state() != InterlockState::Open
+}
+
+fn set_interlock(closed: bool) {
+    let state = if closed {
+        InterlockState::Closed
+    } else {
+        InterlockState::Open
+    };
+    INTERLOCK.lock(|s| s.set(state));
+}
+
+/// A debounced GPIO input with a role
+pub struct ExternalInput {
+    role: InputRole,
+    button: DebouncedButton,
+}
+
+impl ExternalInput {
+    /// Create a new external input. Creating the interlock input records whether the lid is
+    /// closed straight away, so the interlock is in force before its task has started.
+    ///
+    /// # Arguments
+    /// * `role` - What the input is used for
+    /// * `button` - The debounced input. For the interlock, pressed means the lid is closed
+    pub fn new(role: InputRole, button: DebouncedButton) -> Self {
+        if role == InputRole::Interlock {
+            set_interlock(button.is_pressed());
+        }
+        Self { role, button }
+    }
+
+    pub fn role(&self) -> InputRole {
+        self.role
+    }
+}
+
+const INPUT_QUEUE_SIZE: usize = 4;
+/// Channel types for the external input tasks.
+pub type InputChannel = Channel<CriticalSectionRawMutex, InputEvent, INPUT_QUEUE_SIZE>;
+pub type InputChannelSender =
+    Sender<'static, CriticalSectionRawMutex, InputEvent, INPUT_QUEUE_SIZE>;
+pub type InputChannelReceiver =
+    Receiver<'static, CriticalSectionRawMutex, InputEvent, INPUT_QUEUE_SIZE>;
+
+/// External input task. One instance runs per input, publishing an [`InputEvent`] for each
+/// press of a start or abort input and each change of the interlock.
+///
+/// # Parameters
+/// * `input` - The input to watch
+/// * `sender` - Where to publish the input events
+#[embassy_executor::task(pool_size = 3)]
+pub async fn input_task(mut input: ExternalInput, sender: InputChannelSender) {
+    loop {
+        let transition = input.button.wait_for_transition().await;
+        let event = match (input.role, transition.pressed) {
+            (InputRole::Start, true) => InputEvent::Start,
+            (InputRole::Abort, true) => InputEvent::Abort,
+            (InputRole::Interlock, closed) => {
+                // Record the state before publishing so nothing can write after the lid opens
+                set_interlock(closed);
+                if closed {
+                    InputEvent::InterlockClosed
+                } else {
+                    InputEvent::InterlockOpened
+                }
+            }
+            _ => continue,
+        };
+        info!("INPUT_TASK: {}", event);
+        sender.send(event).await;
+    }
+}
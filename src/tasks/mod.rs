@@ -1,5 +1,9 @@
-// pub mod button;
+pub mod button;
+pub mod console;
 pub mod display;
+pub mod inputs;
 
-// pub use button::{ButtonEvent, handle_button};
+pub use button::{ButtonEvent, button_task, encoder_task, gesture_task};
+pub use console::console_task;
 pub use display::{DisplayState, display_task};
+pub use inputs::{InputEvent, input_task};
@@ -0,0 +1,133 @@
+//! Tests for the persisted next address of continuous mode
+//!
+//! You can run this using `cargo test --test address_counter`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use esp_hal::timer::systimer::SystemTimer;
+    use singletact_programing_jig::{
+        address_counter::{AddressCounter, AddressError},
+        drivers::ram_flash::RamFlash,
+        settings::{AtLimit, ContinuousPlan},
+        storage::{Key, KvStore},
+    };
+
+    type TestFlash = RamFlash<256, 4>;
+
+    fn open(flash: TestFlash) -> (KvStore<TestFlash>, AddressCounter) {
+        let mut store = KvStore::open(flash, 0, 4).unwrap();
+        let counter = AddressCounter::load(&mut store).unwrap();
+        (store, counter)
+    }
+
+    const fn plan(first: u8, limit: u8, at_limit: AtLimit) -> ContinuousPlan {
+        ContinuousPlan {
+            first,
+            limit,
+            at_limit,
+        }
+    }
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn addresses_carry_on_after_reset() {
+        let plan = plan(0x20, 0x77, AtLimit::Stop);
+        let (mut store, mut counter) = open(TestFlash::new());
+        assert_eq!(counter.peek(&plan), Some(0x20));
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x20));
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x21));
+
+        let (mut store, mut counter) = open(store.into_flash());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x22));
+    }
+
+    #[test]
+    fn counter_is_kept_in_a_whole_flash_word() {
+        // The test flash refuses reads that aren't whole words, just like the ESP32-C3's
+        let plan = plan(0x20, 0x77, AtLimit::Stop);
+        let (mut store, mut counter) = open(TestFlash::new());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x20));
+        let mut bytes = [0; 4];
+        assert_eq!(store.read(Key::NextAddress, &mut bytes), Ok(Some(4)));
+        assert_eq!(u32::from_le_bytes(bytes), 0x21);
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x21));
+
+        // A counter kept as a u16 by earlier firmware carries on
+        store
+            .write(Key::NextAddress, &0x40u16.to_le_bytes())
+            .unwrap();
+        let (mut store, mut counter) = open(store.into_flash());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x40));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let plan = plan(0x10, 0x11, AtLimit::Stop);
+        let (mut store, mut counter) = open(TestFlash::new());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x10));
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x11));
+        assert_eq!(counter.take(&mut store, &plan), Err(AddressError::UsedUp));
+
+        let (mut store, mut counter) = open(store.into_flash());
+        assert_eq!(counter.peek(&plan), None);
+        assert_eq!(counter.take(&mut store, &plan), Err(AddressError::UsedUp));
+    }
+
+    #[test]
+    fn wraps_at_the_limit() {
+        let plan = plan(0x10, 0x11, AtLimit::Wrap);
+        let (mut store, mut counter) = open(TestFlash::new());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x10));
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x11));
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x10));
+    }
+
+    #[test]
+    fn plan_changes_never_go_back() {
+        let (mut store, mut counter) = open(TestFlash::new());
+        assert_eq!(
+            counter.take(&mut store, &plan(0x10, 0x77, AtLimit::Stop)),
+            Ok(0x10)
+        );
+        // Moving the first address on skips ahead, moving it back doesn't reissue
+        assert_eq!(
+            counter.take(&mut store, &plan(0x30, 0x77, AtLimit::Stop)),
+            Ok(0x30)
+        );
+        assert_eq!(
+            counter.take(&mut store, &plan(0x10, 0x77, AtLimit::Stop)),
+            Ok(0x31)
+        );
+        // Reserved I2C addresses are never handed out
+        assert_eq!(
+            counter.take(&mut store, &plan(0x78, 0x7f, AtLimit::Wrap)),
+            Err(AddressError::InvalidPlan)
+        );
+    }
+
+    #[test]
+    fn restart_goes_back_on_purpose() {
+        let plan = plan(0x10, 0x77, AtLimit::Stop);
+        let (mut store, mut counter) = open(TestFlash::new());
+        counter.take(&mut store, &plan).unwrap();
+        counter.take(&mut store, &plan).unwrap();
+        counter.restart(&mut store, 0x10).unwrap();
+
+        let (mut store, mut counter) = open(store.into_flash());
+        assert_eq!(counter.take(&mut store, &plan), Ok(0x10));
+    }
+}
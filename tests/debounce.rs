@@ -0,0 +1,134 @@
+//! Tests for debouncing button inputs
+//!
+//! You can run this using `cargo test --test debounce`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use embassy_time::{Duration, Instant};
+    use esp_hal::timer::systimer::SystemTimer;
+    use singletact_programing_jig::drivers::button::{
+        ActiveLevel, DebounceConfig, Debouncer, Transition,
+    };
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    /// Feed one sample per millisecond from `start`, returning every transition
+    fn feed(
+        debouncer: &mut Debouncer,
+        start: u64,
+        levels: &[bool],
+    ) -> heapless::Vec<Transition, 8> {
+        let mut transitions = heapless::Vec::new();
+        for (i, high) in levels.iter().enumerate() {
+            if let Some(transition) = debouncer.sample(*high, at(start + i as u64)) {
+                transitions.push(transition).unwrap();
+            }
+        }
+        transitions
+    }
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn bouncy_press_gives_one_transition() {
+        let config = DebounceConfig::default().with_window(Duration::from_millis(5));
+        let mut debouncer = Debouncer::new(config, false);
+        // Active low: bounce for a few samples, then settle low
+        let levels = [
+            false, true, false, true, false, false, false, false, false, false, false,
+        ];
+        let transitions = feed(&mut debouncer, 100, &levels);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(
+            transitions[0],
+            Transition {
+                pressed: true,
+                at: at(104)
+            }
+        );
+        assert!(debouncer.is_pressed());
+        assert!(!debouncer.is_settling());
+    }
+
+    #[test]
+    fn glitch_shorter_than_window_is_ignored() {
+        let config = DebounceConfig::default().with_window(Duration::from_millis(5));
+        let mut debouncer = Debouncer::new(config, false);
+        let levels = [
+            true, false, false, false, true, true, true, true, true, true,
+        ];
+        assert!(feed(&mut debouncer, 0, &levels).is_empty());
+        assert!(!debouncer.is_pressed());
+    }
+
+    #[test]
+    fn press_and_release() {
+        let config = DebounceConfig::default().with_window(Duration::from_millis(3));
+        let mut debouncer = Debouncer::new(config, false);
+        let levels = [
+            false, false, false, false, false, true, false, true, true, true, true,
+        ];
+        let transitions = feed(&mut debouncer, 0, &levels);
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(
+            transitions[0],
+            Transition {
+                pressed: true,
+                at: at(0)
+            }
+        );
+        assert_eq!(
+            transitions[1],
+            Transition {
+                pressed: false,
+                at: at(7)
+            }
+        );
+    }
+
+    #[test]
+    fn active_high() {
+        let config = DebounceConfig::default()
+            .with_active(ActiveLevel::High)
+            .with_window(Duration::from_millis(2));
+        let mut debouncer = Debouncer::new(config, false);
+        // Low is released for an active high input, so nothing happens
+        assert!(feed(&mut debouncer, 0, &[false, false, false, false]).is_empty());
+        let transitions = feed(&mut debouncer, 10, &[true, true, true]);
+        assert_eq!(
+            transitions[0],
+            Transition {
+                pressed: true,
+                at: at(10)
+            }
+        );
+    }
+
+    #[test]
+    fn zero_window_accepts_straight_away() {
+        let config = DebounceConfig::default().with_window(Duration::from_millis(0));
+        let mut debouncer = Debouncer::new(config, true);
+        assert_eq!(
+            debouncer.sample(true, at(50)),
+            Some(Transition {
+                pressed: false,
+                at: at(50)
+            })
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Tests for decoding the rotary encoder and mapping it onto navigation
+//!
+//! You can run this using `cargo test --test encoder`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use esp_hal::timer::systimer::SystemTimer;
+    use singletact_programing_jig::{
+        drivers::encoder::{Direction, QuadratureDecoder},
+        gestures::{ButtonEvent, ButtonId, EncoderAction, Gesture, Navigation},
+    };
+
+    /// One detent clockwise from rest with both channels high
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(true, false), (false, false), (false, true), (true, true)];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn full_detent_in_each_direction() {
+        let mut decoder = QuadratureDecoder::new(true, true, 4);
+        for (a, b) in &CLOCKWISE[..3] {
+            assert_eq!(decoder.update(*a, *b), None);
+        }
+        assert_eq!(decoder.update(true, true), Some(Direction::Clockwise));
+
+        for (a, b) in CLOCKWISE.iter().rev().skip(1) {
+            assert_eq!(decoder.update(*a, *b), None);
+        }
+        assert_eq!(decoder.update(true, true), Some(Direction::AntiClockwise));
+    }
+
+    #[test]
+    fn bounce_cancels_out() {
+        let mut decoder = QuadratureDecoder::new(true, true, 4);
+        // Chatter on channel A before it settles
+        for _ in 0..5 {
+            assert_eq!(decoder.update(true, false), None);
+            assert_eq!(decoder.update(true, true), None);
+        }
+        for (a, b) in &CLOCKWISE[..3] {
+            assert_eq!(decoder.update(*a, *b), None);
+        }
+        assert_eq!(decoder.update(true, true), Some(Direction::Clockwise));
+    }
+
+    #[test]
+    fn invalid_jumps_are_ignored() {
+        let mut decoder = QuadratureDecoder::new(true, true, 4);
+        // Both channels changing at once could be either direction
+        assert_eq!(decoder.update(false, false), None);
+        assert_eq!(decoder.update(true, true), None);
+        assert_eq!(decoder.update(false, false), None);
+        assert_eq!(decoder.update(true, true), None);
+    }
+
+    #[test]
+    fn buttons_and_encoder_navigate_the_same() {
+        let pairs = [
+            (
+                ButtonEvent::Single(ButtonId::Button0, Gesture::Click),
+                ButtonEvent::Encoder(EncoderAction::Clockwise),
+                Navigation::Next,
+            ),
+            (
+                ButtonEvent::Single(ButtonId::Button0, Gesture::DoubleClick),
+                ButtonEvent::Encoder(EncoderAction::AntiClockwise),
+                Navigation::Previous,
+            ),
+            (
+                ButtonEvent::Single(ButtonId::Button1, Gesture::Click),
+                ButtonEvent::Encoder(EncoderAction::Click),
+                Navigation::Select,
+            ),
+            (
+                ButtonEvent::Single(ButtonId::Button1, Gesture::LongPress),
+                ButtonEvent::Encoder(EncoderAction::LongPress),
+                Navigation::Back,
+            ),
+        ];
+        for (button, encoder, navigation) in pairs {
+            assert_eq!(button.navigation(), Some(navigation));
+            assert_eq!(encoder.navigation(), Some(navigation));
+        }
+        assert_eq!(ButtonEvent::Chord(Gesture::Click).navigation(), None);
+    }
+}
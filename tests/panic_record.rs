@@ -0,0 +1,85 @@
+//! Tests for the record of the last panic
+//!
+//! You can run this using `cargo test --test panic_record`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use embassy_time::Duration;
+    use esp_hal::timer::systimer::SystemTimer;
+    use singletact_programing_jig::{
+        drivers::ram_flash::RamFlash,
+        panic_record::{ENCODED_LEN, FILE_LEN, MESSAGE_LEN, PanicRecord},
+        storage::KvStore,
+    };
+
+    type TestFlash = RamFlash<256, 4>;
+
+    fn record() -> PanicRecord {
+        PanicRecord::new(
+            &"called `Option::unwrap()` on a `None` value",
+            "src/tasks/display.rs",
+            214,
+            Duration::from_secs(3723),
+        )
+    }
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let record = record();
+        assert_eq!(record.file_name(), "display.rs");
+        assert_eq!(PanicRecord::from_bytes(&record.to_bytes()), Some(record));
+    }
+
+    #[test]
+    fn long_text_is_shortened() {
+        let message = [b'x'; MESSAGE_LEN + 10];
+        let message = core::str::from_utf8(&message).unwrap();
+        let file = "/home/builder/.cargo/registry/src/index.crates.io/esp-hal-1.0.0/src/dma/mod.rs";
+        let record = PanicRecord::new(&message, file, 7, Duration::from_millis(5));
+        assert_eq!(record.message.len(), MESSAGE_LEN);
+        assert_eq!(record.file.len(), FILE_LEN);
+        assert!(file.ends_with(record.file.as_str()));
+        assert_eq!(record.file_name(), "mod.rs");
+        assert_eq!(PanicRecord::from_bytes(&record.to_bytes()), Some(record));
+    }
+
+    #[test]
+    fn random_bytes_are_not_a_record() {
+        // What retained RAM may hold after power on
+        assert_eq!(PanicRecord::from_bytes(&[0; ENCODED_LEN]), None);
+        assert_eq!(PanicRecord::from_bytes(&[0xa5; ENCODED_LEN]), None);
+        let mut bytes = record().to_bytes();
+        bytes[20] ^= 0x01;
+        assert_eq!(PanicRecord::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn record_is_kept_until_cleared() {
+        let mut store = KvStore::open(TestFlash::new(), 0, 4).unwrap();
+        assert_eq!(PanicRecord::load(&mut store), Ok(None));
+        record().save(&mut store).unwrap();
+
+        let mut store = KvStore::open(store.into_flash(), 0, 4).unwrap();
+        assert_eq!(PanicRecord::load(&mut store), Ok(Some(record())));
+        PanicRecord::clear(&mut store).unwrap();
+        assert_eq!(PanicRecord::load(&mut store), Ok(None));
+
+        let mut store = KvStore::open(store.into_flash(), 0, 4).unwrap();
+        assert_eq!(PanicRecord::load(&mut store), Ok(None));
+    }
+}
@@ -0,0 +1,116 @@
+//! Tests for the priority ordered animation queue
+//!
+//! You can run this using `cargo test --test priority_queue`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use esp_hal::timer::systimer::SystemTimer;
+    use singletact_programing_jig::queue::{FullPolicy, Priority, PriorityQueue, Pushed, preempts};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn higher_priority_jumps_the_queue() {
+        let mut queue: PriorityQueue<u8, 4> = PriorityQueue::new(FullPolicy::Reject);
+        assert!(queue.push(1, Priority::Background).is_ok());
+        assert!(queue.push(2, Priority::Info).is_ok());
+        assert!(queue.push(3, Priority::Critical).is_ok());
+        assert_eq!(queue.pop(), Some((Priority::Critical, 3)));
+        assert_eq!(queue.pop(), Some((Priority::Info, 2)));
+        assert_eq!(queue.pop(), Some((Priority::Background, 1)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn same_priority_is_first_in_first_out() {
+        let mut queue: PriorityQueue<u8, 4> = PriorityQueue::new(FullPolicy::Reject);
+        assert!(queue.push(1, Priority::Info).is_ok());
+        assert!(queue.push(2, Priority::Info).is_ok());
+        assert!(queue.push(3, Priority::Info).is_ok());
+        assert_eq!(queue.pop(), Some((Priority::Info, 1)));
+        assert_eq!(queue.pop(), Some((Priority::Info, 2)));
+        assert_eq!(queue.pop(), Some((Priority::Info, 3)));
+    }
+
+    #[test]
+    fn duplicates_are_merged() {
+        let mut queue: PriorityQueue<u8, 4> = PriorityQueue::new(FullPolicy::Reject);
+        assert_eq!(queue.push(1, Priority::Info), Ok(Pushed::Queued));
+        assert_eq!(queue.push(1, Priority::Info), Ok(Pushed::Duplicate));
+        assert_eq!(queue.push(1, Priority::Background), Ok(Pushed::Duplicate));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn duplicate_with_higher_priority_is_promoted() {
+        let mut queue: PriorityQueue<u8, 4> = PriorityQueue::new(FullPolicy::Reject);
+        assert!(queue.push(1, Priority::Background).is_ok());
+        assert!(queue.push(2, Priority::Info).is_ok());
+        assert_eq!(queue.push(1, Priority::Warning), Ok(Pushed::Queued));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.peek(), Some((Priority::Warning, &1)));
+    }
+
+    #[test]
+    fn full_queue_rejects() {
+        let mut queue: PriorityQueue<u8, 2> = PriorityQueue::new(FullPolicy::Reject);
+        assert!(queue.push(1, Priority::Background).is_ok());
+        assert!(queue.push(2, Priority::Background).is_ok());
+        assert_eq!(queue.push(3, Priority::Critical), Err(3));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn full_queue_drops_lowest() {
+        let mut queue: PriorityQueue<u8, 2> = PriorityQueue::new(FullPolicy::DropLowest);
+        assert!(queue.push(1, Priority::Background).is_ok());
+        assert!(queue.push(2, Priority::Background).is_ok());
+        // Evicts the newest of the lowest priority entries
+        assert_eq!(queue.push(3, Priority::Warning), Ok(Pushed::Evicted(2)));
+        // Nothing lower than the new entry to evict
+        assert_eq!(queue.push(4, Priority::Background), Err(4));
+        assert_eq!(queue.pop(), Some((Priority::Warning, 3)));
+        assert_eq!(queue.pop(), Some((Priority::Background, 1)));
+    }
+
+    #[test]
+    fn new_status_replaces_a_pending_one() {
+        // Statuses are 1 to 9 here, and anything else is some other animation
+        let is_status = |item: &u8| *item < 10;
+        let mut queue: PriorityQueue<u8, 4> = PriorityQueue::new(FullPolicy::Reject);
+        assert!(queue.push(10, Priority::Info).is_ok());
+        assert!(queue.push(1, Priority::Warning).is_ok());
+        // A lower priority status arrives before the first one was shown
+        queue.retain(|_, item| !is_status(item));
+        assert!(queue.push(2, Priority::Background).is_ok());
+        assert_eq!(queue.pop(), Some((Priority::Info, 10)));
+        assert_eq!(queue.pop(), Some((Priority::Background, 2)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn lower_priority_waits_for_a_steady_animation() {
+        // A critical alert that never ends isn't covered up by a cosmetic animation
+        assert!(!preempts(Priority::Critical, true, Priority::Background));
+        assert!(!preempts(Priority::Warning, true, Priority::Info));
+        // But a steady animation gives way to one of the same or higher priority
+        assert!(preempts(Priority::Info, true, Priority::Info));
+        assert!(preempts(Priority::Info, true, Priority::Critical));
+        // An animation with a fixed lifetime is only cut short by a higher priority
+        assert!(!preempts(Priority::Info, false, Priority::Info));
+        assert!(preempts(Priority::Info, false, Priority::Warning));
+    }
+}
@@ -0,0 +1,184 @@
+//! Tests for the flash ring log of programming results
+//!
+//! You can run this using `cargo test --test result_log`.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use defmt::assert_eq;
+    use esp_hal::timer::systimer::SystemTimer;
+    use heapless::{String, Vec};
+    use singletact_programing_jig::{
+        drivers::{flash::FlashError, ram_flash::RamFlash},
+        result_log::{ErrorCode, LogCursor, ResultLog, ResultRecord, SLOT_LEN, Verdict},
+    };
+
+    /// Four slots per sector, twelve in all
+    type TestFlash = RamFlash<128, 3>;
+
+    fn open(flash: TestFlash) -> ResultLog<TestFlash> {
+        ResultLog::open(flash, 0, 3).unwrap()
+    }
+
+    fn record(run: u32, position: u8) -> ResultRecord {
+        ResultRecord {
+            run,
+            position,
+            old_address: Some(0x04),
+            new_address: Some(0x08 + position),
+            serial: Some(1000 + position as u32),
+            verdict: Verdict::Pass,
+            error: ErrorCode::NoError,
+        }
+    }
+
+    /// The (run, position) of every record, oldest first
+    fn contents(log: &mut ResultLog<TestFlash>) -> Vec<(u32, u8), 16> {
+        let mut found = Vec::new();
+        log.for_each(|record| found.push((record.run, record.position)).unwrap())
+            .unwrap();
+        found
+    }
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn blank_flash_is_empty() {
+        let mut log = open(TestFlash::new());
+        assert!(log.is_empty());
+        assert_eq!(log.last_run(), None);
+        assert_eq!(log.newest(0), Ok(None));
+        assert_eq!(contents(&mut log).len(), 0);
+    }
+
+    #[test]
+    fn one_sector_is_refused() {
+        assert!(matches!(
+            ResultLog::open(TestFlash::new(), 0, 1),
+            Err(FlashError::TooFewSectors)
+        ));
+    }
+
+    #[test]
+    fn records_survive_reopening() {
+        let mut log = open(TestFlash::new());
+        let failed = ResultRecord {
+            old_address: None,
+            new_address: None,
+            serial: None,
+            verdict: Verdict::Fail,
+            error: ErrorCode::NoAddress,
+            ..record(1, 1)
+        };
+        log.append(&record(1, 0)).unwrap();
+        log.append(&failed).unwrap();
+
+        let mut log = open(log.into_flash());
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.last_run(), Some(1));
+        assert_eq!(log.newest(0), Ok(Some(failed)));
+        assert_eq!(log.newest(1), Ok(Some(record(1, 0))));
+        assert_eq!(log.newest(2), Ok(None));
+    }
+
+    #[test]
+    fn oldest_records_are_dropped_a_sector_at_a_time() {
+        let mut log = open(TestFlash::new());
+        for position in 0..12 {
+            log.append(&record(1, position)).unwrap();
+        }
+        assert_eq!(log.len(), 12);
+        // The thirteenth record needs the first sector back
+        log.append(&record(2, 0)).unwrap();
+        assert_eq!(log.len(), 9);
+        assert_eq!(log.flash().erases(), &[2, 1, 1]);
+
+        let mut log = open(log.into_flash());
+        assert_eq!(log.len(), 9);
+        assert_eq!(log.newest(0), Ok(Some(record(2, 0))));
+        assert_eq!(log.newest(8), Ok(Some(record(1, 4))));
+        assert_eq!(log.newest(9), Ok(None));
+        let found = contents(&mut log);
+        assert_eq!(found.first(), Some(&(1, 4)));
+        assert_eq!(found.last(), Some(&(2, 0)));
+        assert_eq!(found.len(), 9);
+    }
+
+    #[test]
+    fn interrupted_write_is_skipped() {
+        let mut log = open(TestFlash::new());
+        log.append(&record(1, 0)).unwrap();
+        log.append(&record(1, 1)).unwrap();
+        let mut flash = log.into_flash();
+        // Power lost part way through writing a third record
+        flash.corrupt(2 * SLOT_LEN, &[0x02, 0, 0, 0, 0x01, 0, 0, 0]);
+
+        let mut log = open(flash);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.newest(0), Ok(Some(record(1, 1))));
+        // The next record goes after the damaged slot rather than over it
+        log.append(&record(2, 0)).unwrap();
+        let mut log = open(log.into_flash());
+        assert_eq!(log.len(), 3);
+        assert_eq!(log.newest(0), Ok(Some(record(2, 0))));
+        assert_eq!(log.newest(1), Ok(Some(record(1, 1))));
+        assert_eq!(contents(&mut log), [(1, 0), (1, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn damaged_record_is_not_trusted() {
+        let mut log = open(TestFlash::new());
+        log.append(&record(1, 0)).unwrap();
+        log.append(&record(1, 1)).unwrap();
+        let mut flash = log.into_flash();
+        // Flip the verdict of the newest record
+        flash.corrupt(SLOT_LEN + 11, &[Verdict::Fail as u8]);
+
+        let mut log = open(flash);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log.newest(0), Ok(Some(record(1, 0))));
+    }
+
+    #[test]
+    fn cursor_reads_each_record_once() {
+        let mut log = open(TestFlash::new());
+        log.append(&record(1, 0)).unwrap();
+        log.append(&record(1, 1)).unwrap();
+        let mut cursor = LogCursor::default();
+        assert_eq!(log.next(&mut cursor), Ok(Some(record(1, 0))));
+        // A record appended part way through is left out
+        log.append(&record(2, 0)).unwrap();
+        assert_eq!(log.next(&mut cursor), Ok(Some(record(1, 1))));
+        assert_eq!(log.next(&mut cursor), Ok(None));
+        assert_eq!(log.next(&mut cursor), Ok(None));
+    }
+
+    #[test]
+    fn records_export_as_csv() {
+        let mut line: String<64> = String::new();
+        record(3, 2).write_csv(&mut line).unwrap();
+        assert_eq!(line.as_str(), "3,2,0x4,0xa,1002,pass,none");
+
+        let aborted = ResultRecord {
+            old_address: None,
+            serial: None,
+            verdict: Verdict::Aborted,
+            error: ErrorCode::InterlockOpen,
+            ..record(4, 0)
+        };
+        line.clear();
+        aborted.write_csv(&mut line).unwrap();
+        assert_eq!(line.as_str(), "4,0,,0x8,,aborted,interlock_open");
+    }
+}